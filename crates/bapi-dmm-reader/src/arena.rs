@@ -1,30 +1,108 @@
-use std::{
-    cell::{OnceCell, RefCell},
-    collections::HashMap,
+//! Parsed maps, kept around between parsing and loading.
+//!
+//! Each map owns the text it was parsed from, so it can be freed on its own once the last
+//! /datum/bapi_parsed_map using it lets go. They're kept in [`crate::registry::Registry`].
+use std::collections::HashMap;
+
+use dmm_lite::{transform::MapTransform, LocatedError};
+
+use crate::{
+    handle::{Handle, Slots},
+    load::command_buffer::CommandBuffer,
 };
 
-use typed_arena::Arena;
+/// A parsed map along with its text.
+///
+/// `parsed_data` points into `text`, which is fine because moving a [`String`] doesn't move its
+/// contents, and the two are only ever handed out together. Can be sent between threads, unlike
+/// [`ArenaMap`].
+pub struct OwnedMap {
+    parsed_data: (dmm_lite::MapInfo, dmm_lite::MapData<'static>),
+    text: String,
+}
 
-use crate::ResumeKey;
+impl OwnedMap {
+    pub fn parse(name: String, text: String) -> Result<Self, LocatedError> {
+        // SAFETY: See above, `text` outlives `parsed_data`
+        let static_text: &'static str = unsafe { &*(text.as_str() as *const str) };
+        let parsed_data = dmm_lite::parse_map_multithreaded(name, static_text)?;
+        Ok(OwnedMap { parsed_data, text })
+    }
 
-pub static mut ARENA: OnceCell<Arena<String>> = OnceCell::new();
-pub static mut PARSED_MAPS_ARENABASED: RefCell<Vec<ArenaMap>> = RefCell::new(vec![]);
+    pub fn parsed_data(&self) -> &(dmm_lite::MapInfo, dmm_lite::MapData<'_>) {
+        &self.parsed_data
+    }
+}
 
-/// OnceCell helper: Gives you a mutable reference to the string arena.
+/// A parsed map, with everything made from it while loading.
 ///
-/// # Safety
-///
-/// This must only be called from the main thread.
-pub unsafe fn get_arena<'s>() -> &'s mut Arena<String> {
-    if let Some(x) = ARENA.get_mut() {
-        x
-    } else {
-        let _ = ARENA.set(Arena::new());
-        ARENA.get_mut().unwrap()
+/// The data here isn't really `'static`, it points into `_text` and lives as long as the map does.
+/// So none of it is handed out as `'static`: buffers are only reachable through closures that
+/// work for any lifetime, which keeps references from escaping.
+pub struct ArenaMap {
+    /// These point into the prefabs below, so they're declared (and dropped) first
+    command_buffers: Slots<CommandBuffer<'static>>,
+    parsed_data: (dmm_lite::MapInfo, dmm_lite::MapData<'static>),
+    /// Rotated copies of `parsed_data`, made the first time a map is loaded with that rotation
+    transformed_data: HashMap<MapTransform, dmm_lite::MapData<'static>>,
+    /// How many /datum/bapi_parsed_map are using this map
+    pub refcount: usize,
+    /// Everything above points into this, so it's declared (and dropped) last
+    _text: String,
+}
+
+impl From<OwnedMap> for ArenaMap {
+    fn from(OwnedMap { parsed_data, text }: OwnedMap) -> Self {
+        ArenaMap {
+            command_buffers: Slots::default(),
+            parsed_data,
+            transformed_data: HashMap::new(),
+            refcount: 1,
+            _text: text,
+        }
     }
 }
 
-pub struct ArenaMap<'s> {
-    pub parsed_data: (dmm_lite::MapInfo, dmm_lite::MapData<'s>),
-    pub command_buffers: HashMap<ResumeKey, crate::load::command_buffer::CommandBuffer<'s>>,
+impl ArenaMap {
+    pub fn parsed_data(&self) -> &(dmm_lite::MapInfo, dmm_lite::MapData<'_>) {
+        &self.parsed_data
+    }
+
+    /// Builds a command buffer from the map's data, rotated by `transform`, and keeps it with the
+    /// map. Rotated copies are made the first time they're asked for.
+    pub fn insert_command_buffer(
+        &mut self,
+        transform: MapTransform,
+        build: impl for<'s> FnOnce(&'s dmm_lite::MapData<'s>) -> eyre::Result<CommandBuffer<'s>>,
+    ) -> eyre::Result<Handle> {
+        let map_data = if transform == MapTransform::Identity {
+            &self.parsed_data.1
+        } else {
+            let map_data = &self.parsed_data.1;
+            &*self
+                .transformed_data
+                .entry(transform)
+                .or_insert_with(|| transform.transform_map(map_data))
+        };
+        // SAFETY: The buffer only points at prefabs, and each prefab list has its own allocation,
+        // which stays put until the map is dropped. Buffers are dropped before the prefabs, see
+        // the field order above.
+        let map_data = unsafe { &*(map_data as *const dmm_lite::MapData<'static>) };
+        let buffer = build(map_data)?;
+        Ok(self.command_buffers.insert(buffer))
+    }
+
+    /// Runs `f` on one of the map's command buffers, or returns None if the handle is stale.
+    pub fn with_command_buffer<T>(
+        &mut self,
+        handle: Handle,
+        f: impl for<'s> FnOnce(&mut CommandBuffer<'s>) -> T,
+    ) -> Option<T> {
+        self.command_buffers.get_mut(handle).map(f)
+    }
+
+    /// Drops a command buffer. Returns false if the handle was already stale.
+    pub fn remove_command_buffer(&mut self, handle: Handle) -> bool {
+        self.command_buffers.remove(handle).is_some()
+    }
 }
@@ -0,0 +1,215 @@
+//! Handles for things DM holds on to between calls, like parsed maps and command buffers.
+//!
+//! A handle is a slot plus the generation of whatever was in that slot when the handle was made,
+//! so once the slot is emptied or reused, old handles stop working instead of pointing at
+//! something else. DM sees them as strings like `"map:3:2"`, and shouldn't look inside.
+use std::fmt;
+
+use byondapi::value::ByondValue;
+use eyre::eyre;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle {
+    pub index: usize,
+    pub generation: u32,
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+/// A list where removing something leaves a hole for the next insert. Every insert gets a new
+/// generation, even in a reused slot.
+pub struct Slots<T> {
+    slots: Vec<Slot<T>>,
+}
+
+impl<T> Default for Slots<T> {
+    fn default() -> Self {
+        Slots { slots: vec![] }
+    }
+}
+
+impl<T> Slots<T> {
+    pub fn insert(&mut self, value: T) -> Handle {
+        let index = match self.slots.iter().position(|slot| slot.value.is_none()) {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: None,
+                });
+                self.slots.len() - 1
+            }
+        };
+        let slot = &mut self.slots[index];
+        slot.generation += 1;
+        slot.value = Some(value);
+        Handle {
+            index,
+            generation: slot.generation,
+        }
+    }
+
+    /// None if the handle is stale.
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut T> {
+        self.slots
+            .get_mut(handle.index)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_mut())
+    }
+
+    pub fn remove(&mut self, handle: Handle) -> Option<T> {
+        self.slots
+            .get_mut(handle.index)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.take())
+    }
+
+    /// Removes everything. Slots keep their generations, so every handle given out so far goes stale.
+    pub fn clear(&mut self) {
+        for slot in &mut self.slots {
+            slot.value = None;
+        }
+    }
+}
+
+/// Splits `"{kind}:index:generation:..."` into its handles, or None if it isn't a handle of that kind.
+fn parse_parts<const N: usize>(kind: &str, text: &str) -> Option<[Handle; N]> {
+    let mut parts = text.split(':');
+    if parts.next()? != kind {
+        return None;
+    }
+    let mut handles = [Handle {
+        index: 0,
+        generation: 0,
+    }; N];
+    for handle in &mut handles {
+        handle.index = parts.next()?.parse().ok()?;
+        handle.generation = parts.next()?.parse().ok()?;
+    }
+    parts.next().is_none().then_some(handles)
+}
+
+fn handle_text(value: &ByondValue, what: &str) -> eyre::Result<String> {
+    if !value.is_str() {
+        return Err(eyre!("Expected a {what} handle, got {value:#?}"));
+    }
+    Ok(value.get_string()?)
+}
+
+/// A parsed map, in [`crate::registry::Registry`]. Stored on /datum/bapi_parsed_map/_internal_index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MapHandle(pub Handle);
+
+impl fmt::Display for MapHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "map:{}:{}", self.0.index, self.0.generation)
+    }
+}
+
+impl MapHandle {
+    pub fn parse(text: &str) -> eyre::Result<Self> {
+        let [handle] = parse_parts("map", text)
+            .ok_or_else(|| eyre!("Expected a parsed map handle, got {text:#?}"))?;
+        Ok(MapHandle(handle))
+    }
+
+    pub fn from_byond(value: &ByondValue) -> eyre::Result<Self> {
+        Self::parse(&handle_text(value, "parsed map")?)
+    }
+}
+
+/// A command buffer, in the parsed map it was made from. Returned from
+/// `_bapidmm_load_map_buffered` to pass to `_bapidmm_work_commandbuffer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BufferHandle {
+    pub map: MapHandle,
+    pub buffer: Handle,
+}
+
+impl fmt::Display for BufferHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "buffer:{}:{}:{}:{}",
+            self.map.0.index, self.map.0.generation, self.buffer.index, self.buffer.generation
+        )
+    }
+}
+
+impl BufferHandle {
+    pub fn parse(text: &str) -> eyre::Result<Self> {
+        let [map, buffer] = parse_parts("buffer", text)
+            .ok_or_else(|| eyre!("Expected a command buffer handle, got {text:#?}"))?;
+        Ok(BufferHandle {
+            map: MapHandle(map),
+            buffer,
+        })
+    }
+
+    pub fn from_byond(value: &ByondValue) -> eyre::Result<Self> {
+        Self::parse(&handle_text(value, "command buffer")?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots() {
+        let mut slots = Slots::default();
+        let a = slots.insert("a");
+        let b = slots.insert("b");
+        assert_eq!(slots.remove(a), Some("a"));
+        assert_eq!(slots.get_mut(a), None);
+
+        let c = slots.insert("c");
+        assert_eq!(c.index, a.index);
+        assert_ne!(c, a);
+        assert_eq!(slots.get_mut(a), None);
+        assert_eq!(slots.remove(a), None);
+        assert_eq!(slots.get_mut(c), Some(&mut "c"));
+
+        slots.clear();
+        assert_eq!(slots.get_mut(b), None);
+        assert_eq!(slots.get_mut(c), None);
+        assert_ne!(slots.insert("d"), c);
+    }
+
+    #[test]
+    fn test_handle_text() {
+        let map = MapHandle(Handle {
+            index: 3,
+            generation: 2,
+        });
+        assert_eq!(map.to_string(), "map:3:2");
+        assert_eq!(MapHandle::parse("map:3:2").unwrap(), map);
+
+        let buffer = BufferHandle {
+            map,
+            buffer: Handle {
+                index: 0,
+                generation: 7,
+            },
+        };
+        assert_eq!(buffer.to_string(), "buffer:3:2:0:7");
+        assert_eq!(BufferHandle::parse(&buffer.to_string()).unwrap(), buffer);
+
+        // Foreign handles
+        assert!(MapHandle::parse("buffer:3:2:0:7").is_err());
+        assert!(BufferHandle::parse("map:3:2").is_err());
+        assert!(MapHandle::parse("map:3").is_err());
+        assert!(MapHandle::parse("map:3:2:1").is_err());
+        assert!(MapHandle::parse("3").is_err());
+        // Released datums have -1, and nothing may stand in for a slot but a whole number
+        assert!(MapHandle::parse("-1").is_err());
+        assert!(MapHandle::parse("map:-1:2").is_err());
+        assert!(MapHandle::parse("map:0.5:2").is_err());
+        // Generations that don't fit aren't wrapped around to an older one
+        assert!(MapHandle::parse("map:0:4294967298").is_err());
+        assert!(BufferHandle::parse("buffer:0:0:0:4294967296").is_err());
+    }
+}
@@ -2,23 +2,52 @@ use byondapi::prelude::*;
 
 use crate::{
     _compat::setup_panic_handler,
-    arena::{ARENA, PARSED_MAPS_ARENABASED},
+    load::helpers::ParsedMapTranslationLayer,
+    registry::{with_registry, with_registry_or_defer, Deferred},
 };
 
-type ResumeKey = usize;
+/// Handle for a parse running on a worker thread
+type ParseHandle = usize;
 
 pub mod _compat;
 pub mod arena;
+pub mod handle;
 pub mod load;
 pub mod parse;
 pub mod random_map;
+pub mod registry;
 
 #[byondapi::bind]
-/// This function empties out the cached map data
+/// This function empties out the cached map data, and forgets any parses still running
 pub fn _bapidmm_clear_map_data() {
     setup_panic_handler();
-    // This must be dropped FIRST or there will be a bunch of invalid refs
-    let _ = unsafe { PARSED_MAPS_ARENABASED.replace(vec![]) };
-    let _ = unsafe { ARENA.take() };
+    with_registry(|registry| {
+        registry.clear_maps();
+        Ok(ByondValue::null())
+    })
+}
+
+#[byondapi::bind]
+/// Another datum is sharing this one's parsed map, so it has to stay around until both release it
+pub fn _bapidmm_retain_map(parsed_map: ByondValue) {
+    setup_panic_handler();
+    let parsed_map = ParsedMapTranslationLayer { parsed_map };
+    let handle = parsed_map.get_map_handle()?;
+    with_registry(|registry| registry.retain_map(handle))?;
     Ok(ByondValue::null())
 }
+
+#[byondapi::bind]
+/// This datum is done with its parsed map. Frees the map if nothing else is using it.
+/// Returns 1 if it was freed. From inside a load, the release waits until the load's call is done.
+pub fn _bapidmm_release_map(parsed_map: ByondValue) {
+    setup_panic_handler();
+    let mut parsed_map = ParsedMapTranslationLayer { parsed_map };
+    let handle = parsed_map.get_map_handle()?;
+    let freed = with_registry_or_defer(Deferred::Release(handle), |registry| {
+        registry.release_map(handle)
+    })?
+    .unwrap_or(false);
+    parsed_map.set_map_handle(None)?;
+    Ok(ByondValue::new_num(if freed { 1. } else { 0. }))
+}
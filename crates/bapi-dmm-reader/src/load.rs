@@ -1,5 +1,15 @@
 //! All things to do with loading maps into the game (as opposed to parsing them)
+pub mod backend;
 pub mod command_buffer;
 pub mod helpers;
 pub mod load_buffer;
+pub mod plan;
 pub mod smart_byond_value;
+
+/// Parses a map from a string, for tests.
+#[cfg(test)]
+pub(crate) fn parse_test_map(map: &str) -> dmm_lite::MapData<'_> {
+    dmm_lite::parse_map_multithreaded("test".to_owned(), map)
+        .unwrap()
+        .1
+}
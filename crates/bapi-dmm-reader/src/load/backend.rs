@@ -0,0 +1,65 @@
+//! Everything loading a map does to the world, behind a trait.
+//!
+//! [`byond::ByondWorld`] is the real thing, calling into DM through byondapi. [`mock::MockWorld`]
+//! keeps an in-memory model of the world instead, so command buffers can be run in tests.
+use dmm_lite::prefabs::Literal;
+use eyre::Result;
+
+use crate::load::plan::Coord;
+
+pub mod byond;
+#[cfg(test)]
+pub mod mock;
+
+/// The world a [`crate::load::command_buffer::CommandBuffer`] works on.
+pub trait WorldBackend {
+    /// Something in the world: a turf, an area or a type path.
+    /// Kept across ticks, in the command buffer's caches.
+    type Ref: Clone;
+    /// A newly created atom. Only used until its preloader is applied, so it's never kept.
+    type Movable;
+
+    /// world.maxx, world.maxy and world.maxz
+    fn world_bounds(&mut self) -> Result<Coord>;
+
+    /// The turf at a coordinate, given the world bounds it was looked up with.
+    /// None if there's no turf there.
+    fn locate_turf(&mut self, coord: Coord, world_bounds: Coord) -> Result<Option<Self::Ref>>;
+
+    /// Finds the existing instance of an area type, or creates it.
+    fn create_or_get_area(&mut self, path: &str) -> Result<Self::Ref>;
+
+    /// Only used on /tg/ downstreams, handles turfs_by_zlevel on /area.
+    fn handle_area_contain(&mut self, turf: &Self::Ref, area: &Self::Ref) -> Result<()>;
+
+    /// Adds a turf to an area's contents.
+    fn add_turf_to_area(&mut self, area: &Self::Ref, turf: &Self::Ref) -> Result<()>;
+
+    /// Changes the turf at `turf` into what the map says should be there.
+    fn change_turf(
+        &mut self,
+        turf: &Self::Ref,
+        path: &str,
+        vars: Option<&[(&str, Literal)]>,
+        place_on_top: bool,
+        no_changeturf: bool,
+    ) -> Result<()>;
+
+    /// The type for a path, or None if it doesn't exist.
+    fn text2path(&mut self, path: &str) -> Result<Option<Self::Ref>>;
+
+    /// Sets up the preloader, so the next atom of type `path` gets these vars before New().
+    fn setup_preloader(&mut self, vars: &[(&str, Literal)], path: &Self::Ref) -> Result<()>;
+
+    /// Creates a movable on a turf.
+    fn new_movable(&mut self, path: &Self::Ref, turf: &Self::Ref) -> Result<Self::Movable>;
+
+    /// Applies the preloader to a new atom right away, for atoms that sleep in New().
+    fn apply_preloader(&mut self, instance: &Self::Movable) -> Result<()>;
+
+    /// True if the server is about to run over its tick, and loading should pause.
+    fn tick_check(&mut self) -> Result<bool>;
+
+    /// Something went wrong, but not badly enough to stop loading.
+    fn add_warning(&mut self, warning: String) -> Result<()>;
+}
@@ -0,0 +1,213 @@
+//! The real world, through byondapi and the `_bapi_*` procs in `bapi_dmm_reader.dm`.
+use std::rc::Rc;
+
+use byondapi::{prelude::*, value::ByondValue};
+use dmm_lite::prefabs::Literal;
+use eyre::{eyre, Result};
+use tracy_full::zone;
+
+use crate::load::{
+    backend::WorldBackend,
+    helpers::{
+        _bapi_add_turf_to_area, _bapi_apply_preloader, _bapi_create_or_get_area, _bapi_create_turf,
+        _bapi_handle_area_contain, _bapi_helper_get_world_bounds, _bapi_helper_text2file,
+        _bapi_helper_text2path, _bapi_helper_tick_check, _bapi_setup_preloader,
+        ParsedMapTranslationLayer,
+    },
+    plan::Coord,
+    smart_byond_value::{SharedByondValue, SmartByondValue},
+};
+
+/// Safety: You're fucked honestly
+/// This is extremely dependent on internal BYOND data structures that ~probably~ won't ever change
+/// You'll find out it did when byond starts throwing "BAD REF!" internal debug messages (or segfaults)
+unsafe fn extremely_unsafe_resolve_coord(
+    coord: (usize, usize, usize),
+    world_size: (usize, usize, usize),
+) -> eyre::Result<ByondValue> {
+    zone!("extremely_unsafe_resolve_coord");
+    let (max_x, max_y, max_z) = world_size;
+    let (x, y, z) = (coord.0 - 1, coord.1 - 1, coord.2 - 1);
+    if (0..max_x).contains(&x) && (0..max_y).contains(&y) && (0..max_z).contains(&z) {
+        Ok(ByondValue::new_ref(
+            ValueType::Turf,
+            (x + y * max_x + z * max_x * max_y) as u32,
+        ))
+    } else {
+        Err(eyre!(
+            "Attempted to get out-of-range tile at coords {coord:#?}"
+        ))
+    }
+}
+
+/// Loads into the running BYOND world. Warnings go to the /datum/bapi_parsed_map being loaded.
+pub struct ByondWorld {
+    pub parsed_map: ParsedMapTranslationLayer,
+}
+
+impl WorldBackend for ByondWorld {
+    type Ref = SharedByondValue;
+    type Movable = ByondValue;
+
+    fn world_bounds(&mut self) -> Result<Coord> {
+        _bapi_helper_get_world_bounds()
+    }
+
+    fn locate_turf(&mut self, coord: Coord, world_bounds: Coord) -> Result<Option<Self::Ref>> {
+        let turf = unsafe { extremely_unsafe_resolve_coord(coord, world_bounds)? };
+        if turf.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(Rc::new(SmartByondValue::from(turf))))
+    }
+
+    fn create_or_get_area(&mut self, path: &str) -> Result<Self::Ref> {
+        zone!("new area creation");
+        let area = _bapi_create_or_get_area(path)?;
+        Ok(Rc::new(SmartByondValue::from(area)))
+    }
+
+    fn handle_area_contain(&mut self, turf: &Self::Ref, area: &Self::Ref) -> Result<()> {
+        _bapi_handle_area_contain(turf.get_temp_ref(), area.get_temp_ref())
+    }
+
+    fn add_turf_to_area(&mut self, area: &Self::Ref, turf: &Self::Ref) -> Result<()> {
+        _bapi_add_turf_to_area(area.get_temp_ref(), turf.get_temp_ref())
+    }
+
+    fn change_turf(
+        &mut self,
+        turf: &Self::Ref,
+        path: &str,
+        vars: Option<&[(&str, Literal)]>,
+        place_on_top: bool,
+        no_changeturf: bool,
+    ) -> Result<()> {
+        zone!("create_turf");
+        let vars_list = self.convert_vars_list_to_byondlist(vars)?;
+        _bapi_create_turf(
+            turf.get_temp_ref(),
+            path,
+            vars_list,
+            place_on_top,
+            no_changeturf,
+        )?;
+        Ok(())
+    }
+
+    fn text2path(&mut self, path: &str) -> Result<Option<Self::Ref>> {
+        let path = _bapi_helper_text2path(path)?;
+        if path.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(Rc::new(SmartByondValue::from(path))))
+    }
+
+    fn setup_preloader(&mut self, vars: &[(&str, Literal)], path: &Self::Ref) -> Result<()> {
+        let vars_list = self.convert_vars_list_to_byondlist(Some(vars))?;
+        _bapi_setup_preloader(vars_list, path.get_temp_ref())
+    }
+
+    fn new_movable(&mut self, path: &Self::Ref, turf: &Self::Ref) -> Result<Self::Movable> {
+        zone!("byond_new");
+        Ok(ByondValue::builtin_new(
+            path.get_temp_ref(),
+            &[turf.get_temp_ref()],
+        )?)
+    }
+
+    fn apply_preloader(&mut self, instance: &Self::Movable) -> Result<()> {
+        _bapi_apply_preloader(*instance)
+    }
+
+    fn tick_check(&mut self) -> Result<bool> {
+        _bapi_helper_tick_check()
+    }
+
+    fn add_warning(&mut self, warning: String) -> Result<()> {
+        self.parsed_map.add_warning(warning)
+    }
+}
+
+impl ByondWorld {
+    fn convert_vars_list_to_byondlist(
+        &mut self,
+        vars: Option<&[(&str, Literal)]>,
+    ) -> eyre::Result<ByondValue> {
+        zone!("convert_vars_list_to_byondlist");
+        if let Some(vars) = vars {
+            let mut vars_list = ByondValue::new_list()?;
+            for (key, literal) in vars {
+                let value = self.convert_literal_to_byondvalue(key, literal)?;
+                vars_list.write_list_index(ByondValue::new_str(*key)?, value)?;
+            }
+            Ok(vars_list)
+        } else {
+            Ok(ByondValue::null())
+        }
+    }
+
+    /// This only hard errors when running into an internal BYOND error, such as bad proc, bad value, out of memory, etc
+    fn convert_literal_to_byondvalue(
+        &mut self,
+        key: &str,
+        literal: &Literal,
+    ) -> eyre::Result<ByondValue> {
+        zone!("convert_literal_to_byondvalue");
+        Ok(match literal {
+            Literal::Number(n) => ByondValue::new_num(*n),
+            Literal::String(s) => ByondValue::new_str(*s)?,
+            Literal::Path(p) => _bapi_helper_text2path(p)?,
+            Literal::File(f) => _bapi_helper_text2file(f)?,
+            Literal::Null => ByondValue::null(),
+            Literal::Fallback(s) => {
+                self.parsed_map.add_warning(format!(
+                    "Parser failed to parse value for {:#?} and fellback to string: {s:#?}",
+                    key
+                ))?;
+                ByondValue::new_str(*s)?
+            }
+            Literal::List(l) => {
+                zone!("convert_literal_to_byondvalue(list)");
+                let mut list = ByondValue::new_list()?;
+
+                for literal in l {
+                    match self.convert_literal_to_byondvalue(key, literal) {
+                        Ok(item) => list.push_list(item)?,
+                        Err(e) => {
+                            self.parsed_map.add_warning(format!(
+                                "Inside list inside {:#?}, failed to parse value: {e:#?}",
+                                key
+                            ))?;
+                        }
+                    }
+                }
+
+                list
+            }
+            Literal::AssocList(map) => {
+                zone!("convert_literal_to_byondvalue(assoc list)");
+                let mut list = ByondValue::new_list()?;
+
+                for (list_key, list_val) in map.iter() {
+                    let key_bv = self.convert_literal_to_byondvalue(key, list_key);
+                    let val_bv = self.convert_literal_to_byondvalue(key, list_val);
+
+                    match (key_bv, val_bv) {
+                        (Ok(key), Ok(val)) => list.write_list_index(key, val)?,
+                        (Err(e), _) => self.parsed_map.add_warning(format!(
+                            "Inside assoc list inside {:#?}, failed to parse assoc list key: {e:#?}",
+                            key,
+                        ))?,
+                        (_, Err(e)) => self.parsed_map.add_warning(format!(
+                            "Inside assoc list inside {:#?}, failed to parse assoc list value: {e:#?}",
+                            key
+                        ))?,
+                    }
+                }
+
+                list
+            }
+        })
+    }
+}
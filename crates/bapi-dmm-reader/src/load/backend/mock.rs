@@ -0,0 +1,228 @@
+//! An in-memory world, for running command buffers in tests without BYOND.
+use std::collections::{BTreeMap, BTreeSet};
+
+use dmm_lite::{prefabs::Literal, writer::write_literal};
+use eyre::{eyre, Result};
+
+use crate::load::{backend::WorldBackend, plan::Coord};
+
+/// Var edits as (name, value written back out as DM)
+pub type MockVars = Vec<(String, String)>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockTurf {
+    pub path: String,
+    pub vars: MockVars,
+    pub area: Option<String>,
+    /// Turfs this one was placed on top of, bottom first
+    pub baseturfs: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockMovable {
+    pub path: String,
+    pub vars: MockVars,
+    pub loc: Coord,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockRef {
+    Turf(Coord),
+    Area(String),
+    Type(String),
+}
+
+#[derive(Debug, Default)]
+pub struct MockWorld {
+    pub bounds: Coord,
+    /// Coords missing from here have no turf, like a null from locate()
+    pub turfs: BTreeMap<Coord, MockTurf>,
+    pub areas: BTreeSet<String>,
+    pub movables: Vec<MockMovable>,
+    /// Paths text2path knows about. None means every path exists.
+    pub types: Option<BTreeSet<String>>,
+    pub warnings: Vec<String>,
+    /// tick_check says the tick is over every this many calls
+    pub tick_every: Option<usize>,
+    pub tick_checks: usize,
+    preloader: Option<(String, MockVars)>,
+}
+
+impl MockWorld {
+    /// A world filled with `turf`, with no areas.
+    pub fn new(bounds: Coord, turf: &str) -> Self {
+        let mut turfs = BTreeMap::new();
+        for z in 1..=bounds.2 {
+            for y in 1..=bounds.1 {
+                for x in 1..=bounds.0 {
+                    turfs.insert(
+                        (x, y, z),
+                        MockTurf {
+                            path: turf.to_owned(),
+                            vars: vec![],
+                            area: None,
+                            baseturfs: vec![],
+                        },
+                    );
+                }
+            }
+        }
+        MockWorld {
+            bounds,
+            turfs,
+            ..Default::default()
+        }
+    }
+
+    pub fn turf(&self, coord: Coord) -> &MockTurf {
+        &self.turfs[&coord]
+    }
+
+    /// Paths of the movables on a turf, in the order they were created.
+    pub fn contents(&self, coord: Coord) -> Vec<&str> {
+        self.movables
+            .iter()
+            .filter(|movable| movable.loc == coord)
+            .map(|movable| movable.path.as_str())
+            .collect()
+    }
+
+    fn turf_mut(&mut self, turf: &MockRef) -> Result<&mut MockTurf> {
+        match turf {
+            MockRef::Turf(coord) => self
+                .turfs
+                .get_mut(coord)
+                .ok_or_else(|| eyre!("No turf at {coord:?}")),
+            other => Err(eyre!("Expected a turf, got {other:?}")),
+        }
+    }
+}
+
+fn convert_vars(vars: &[(&str, Literal)]) -> MockVars {
+    vars.iter()
+        .map(|(key, literal)| {
+            let mut value = String::new();
+            write_literal(&mut value, literal).unwrap();
+            (key.to_string(), value)
+        })
+        .collect()
+}
+
+impl WorldBackend for MockWorld {
+    type Ref = MockRef;
+    /// Index into `movables`
+    type Movable = usize;
+
+    fn world_bounds(&mut self) -> Result<Coord> {
+        Ok(self.bounds)
+    }
+
+    fn locate_turf(&mut self, coord: Coord, world_bounds: Coord) -> Result<Option<Self::Ref>> {
+        let (x, y, z) = coord;
+        if x == 0
+            || y == 0
+            || z == 0
+            || x > world_bounds.0
+            || y > world_bounds.1
+            || z > world_bounds.2
+        {
+            return Err(eyre!(
+                "Attempted to get out-of-range tile at coords {coord:#?}"
+            ));
+        }
+        Ok(self
+            .turfs
+            .contains_key(&coord)
+            .then_some(MockRef::Turf(coord)))
+    }
+
+    fn create_or_get_area(&mut self, path: &str) -> Result<Self::Ref> {
+        self.areas.insert(path.to_owned());
+        Ok(MockRef::Area(path.to_owned()))
+    }
+
+    fn handle_area_contain(&mut self, turf: &Self::Ref, _area: &Self::Ref) -> Result<()> {
+        self.turf_mut(turf)?;
+        Ok(())
+    }
+
+    fn add_turf_to_area(&mut self, area: &Self::Ref, turf: &Self::Ref) -> Result<()> {
+        let MockRef::Area(area) = area else {
+            return Err(eyre!("Expected an area, got {area:?}"));
+        };
+        self.turf_mut(turf)?.area = Some(area.clone());
+        Ok(())
+    }
+
+    fn change_turf(
+        &mut self,
+        turf: &Self::Ref,
+        path: &str,
+        vars: Option<&[(&str, Literal)]>,
+        place_on_top: bool,
+        _no_changeturf: bool,
+    ) -> Result<()> {
+        let turf = self.turf_mut(turf)?;
+        if place_on_top {
+            let below = std::mem::replace(&mut turf.path, path.to_owned());
+            turf.baseturfs.push(below);
+        } else {
+            turf.path = path.to_owned();
+            turf.baseturfs.clear();
+        }
+        turf.vars = vars.map(convert_vars).unwrap_or_default();
+        Ok(())
+    }
+
+    fn text2path(&mut self, path: &str) -> Result<Option<Self::Ref>> {
+        let exists = self.types.as_ref().is_none_or(|types| types.contains(path));
+        Ok(exists.then(|| MockRef::Type(path.to_owned())))
+    }
+
+    fn setup_preloader(&mut self, vars: &[(&str, Literal)], path: &Self::Ref) -> Result<()> {
+        let MockRef::Type(path) = path else {
+            return Err(eyre!("Expected a type, got {path:?}"));
+        };
+        self.preloader = Some((path.clone(), convert_vars(vars)));
+        Ok(())
+    }
+
+    fn new_movable(&mut self, path: &Self::Ref, turf: &Self::Ref) -> Result<Self::Movable> {
+        let (MockRef::Type(path), MockRef::Turf(loc)) = (path, turf) else {
+            return Err(eyre!("Can't create {path:?} on {turf:?}"));
+        };
+        // Like the real preloader, only the type it was set up for picks it up in New()
+        let vars = match self.preloader.take() {
+            Some((preloader_path, vars)) if preloader_path == *path => vars,
+            other => {
+                self.preloader = other;
+                vec![]
+            }
+        };
+        self.movables.push(MockMovable {
+            path: path.clone(),
+            vars,
+            loc: *loc,
+        });
+        Ok(self.movables.len() - 1)
+    }
+
+    fn apply_preloader(&mut self, instance: &Self::Movable) -> Result<()> {
+        if let Some((_, vars)) = self.preloader.take() {
+            self.movables[*instance].vars = vars;
+        }
+        Ok(())
+    }
+
+    fn tick_check(&mut self) -> Result<bool> {
+        self.tick_checks += 1;
+        Ok(self
+            .tick_every
+            .is_some_and(|every| self.tick_checks.is_multiple_of(every)))
+    }
+
+    fn add_warning(&mut self, warning: String) -> Result<()> {
+        self.warnings.push(warning);
+        Ok(())
+    }
+}
@@ -2,26 +2,24 @@
 //! Allows working piecemeal to actually place down a map
 use std::{
     collections::{HashMap, VecDeque},
-    rc::Rc,
+    time::Instant,
 };
 
 use byondapi::{prelude::*, value::ByondValue};
-use dmm_lite::prefabs::{Literal, Prefab};
+use dmm_lite::prefabs::Prefab;
 use eyre::eyre;
 use tracy_full::zone;
 
 use crate::{
     _compat::setup_panic_handler,
+    handle::{BufferHandle, MapHandle},
     load::{
-        helpers::{
-            ParsedMapTranslationLayer, _bapi_add_turf_to_area, _bapi_apply_preloader,
-            _bapi_create_or_get_area, _bapi_create_turf, _bapi_handle_area_contain,
-            _bapi_helper_get_world_bounds, _bapi_helper_text2file, _bapi_helper_text2path,
-            _bapi_helper_tick_check, _bapi_setup_preloader,
-        },
-        smart_byond_value::{SharedByondValue, SmartByondValue},
+        backend::{byond::ByondWorld, WorldBackend},
+        helpers::ParsedMapTranslationLayer,
+        plan::{Bounds, Coord},
+        smart_byond_value::SharedByondValue,
     },
-    PARSED_MAPS_ARENABASED,
+    registry::{with_registry, with_registry_or_defer, Deferred},
 };
 
 /// Used by [`CommandBuffer`] to know what it needs to do in a big list.
@@ -44,40 +42,52 @@ pub enum Command<'s> {
     },
 }
 
-/// Safety: You're fucked honestly
-/// This is extremely dependent on internal BYOND data structures that ~probably~ won't ever change
-/// You'll find out it did when byond starts throwing "BAD REF!" internal debug messages (or segfaults)
-unsafe fn extremely_unsafe_resolve_coord(
-    coord: (usize, usize, usize),
-    world_size: (usize, usize, usize),
-) -> eyre::Result<ByondValue> {
-    zone!("extremely_unsafe_resolve_coord");
-    let (max_x, max_y, max_z) = world_size;
-    let (x, y, z) = (coord.0 - 1, coord.1 - 1, coord.2 - 1);
-    if (0..max_x).contains(&x) && (0..max_y).contains(&y) && (0..max_z).contains(&z) {
-        Ok(ByondValue::new_ref(
-            ValueType::Turf,
-            (x + y * max_x + z * max_x * max_y) as u32,
-        ))
-    } else {
-        Err(eyre!(
-            "Attempted to get out-of-range tile at coords {coord:#?}"
-        ))
+/// How many commands of each kind, for progress reporting
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CommandCounts {
+    pub areas: usize,
+    pub turfs: usize,
+    pub atoms: usize,
+}
+
+impl CommandCounts {
+    pub fn count(&mut self, command: &Command) {
+        match command {
+            Command::CreateArea { .. } => self.areas += 1,
+            Command::CreateTurf { .. } => self.turfs += 1,
+            Command::CreateAtom { .. } => self.atoms += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.areas + self.turfs + self.atoms
     }
 }
 
 /// This thing allows us to cache turfs ahead of time in a safe way,
 /// respecting when turf references become invalidated (world.max[x|y|z] changes)
-#[derive(Default, Debug)]
-pub struct CachedTurfs {
+#[derive(Debug)]
+pub struct CachedTurfs<R = SharedByondValue> {
     /// Invalidates cache if this changes
     pub world_bounds: (usize, usize, usize),
-    pub cached_turfs: HashMap<(usize, usize, usize), SharedByondValue>,
+    pub cached_turfs: HashMap<(usize, usize, usize), R>,
+}
+
+impl<R> Default for CachedTurfs<R> {
+    fn default() -> Self {
+        CachedTurfs {
+            world_bounds: Default::default(),
+            cached_turfs: Default::default(),
+        }
+    }
 }
 
-impl CachedTurfs {
-    pub fn check_invalidate(&mut self) -> eyre::Result<()> {
-        let world_bounds = _bapi_helper_get_world_bounds()?;
+impl<R: Clone> CachedTurfs<R> {
+    pub fn check_invalidate<B: WorldBackend<Ref = R>>(
+        &mut self,
+        backend: &mut B,
+    ) -> eyre::Result<()> {
+        let world_bounds = backend.world_bounds()?;
 
         if world_bounds != self.world_bounds {
             self.cached_turfs.clear();
@@ -89,25 +99,34 @@ impl CachedTurfs {
     }
 
     /// Caches a turf
-    pub fn cache(&mut self, coord: (usize, usize, usize)) -> eyre::Result<()> {
+    pub fn cache<B: WorldBackend<Ref = R>>(
+        &mut self,
+        backend: &mut B,
+        coord: (usize, usize, usize),
+    ) -> eyre::Result<()> {
         if let std::collections::hash_map::Entry::Vacant(e) = self.cached_turfs.entry(coord) {
-            let turf = unsafe { extremely_unsafe_resolve_coord(coord, self.world_bounds)? };
-            e.insert(Rc::new(SmartByondValue::from(turf)));
+            if let Some(turf) = backend.locate_turf(coord, self.world_bounds)? {
+                e.insert(turf);
+            }
         }
 
         Ok(())
     }
 
-    /// Resolves the turf, either by looking it up internally, or failing that, looking it up through byondapi
-    /// Will cache byondapi results
-    pub fn resolve_coord(&mut self, coord: (usize, usize, usize)) -> eyre::Result<ByondValue> {
+    /// Resolves the turf, either by looking it up internally, or failing that, looking it up through the backend
+    /// Will cache backend results
+    pub fn resolve_coord<B: WorldBackend<Ref = R>>(
+        &mut self,
+        backend: &mut B,
+        coord: (usize, usize, usize),
+    ) -> eyre::Result<Option<R>> {
         if let Some(turf) = self.cached_turfs.get(&coord) {
-            Ok(turf.get_temp_ref())
+            Ok(Some(turf.clone()))
         } else {
-            let turf = unsafe { extremely_unsafe_resolve_coord(coord, self.world_bounds)? };
-
-            self.cached_turfs
-                .insert(coord, Rc::new(SmartByondValue::from(turf)));
+            let turf = backend.locate_turf(coord, self.world_bounds)?;
+            if let Some(turf) = &turf {
+                self.cached_turfs.insert(coord, turf.clone());
+            }
 
             Ok(turf)
         }
@@ -116,260 +135,472 @@ impl CachedTurfs {
 
 /// A single command buffer to place a map down. The coordinates are fixed at this point, this is
 /// only for the purpose of splitting the work up across ticks.
-#[derive(Default, Debug)]
-pub struct CommandBuffer<'s> {
-    pub created_areas: HashMap<&'s str, SharedByondValue>,
-    pub known_types: HashMap<&'s str, SharedByondValue>,
-    pub cached_turfs: CachedTurfs,
+#[derive(Debug)]
+pub struct CommandBuffer<'s, R = SharedByondValue> {
+    pub created_areas: HashMap<&'s str, R>,
+    pub known_types: HashMap<&'s str, R>,
+    pub cached_turfs: CachedTurfs<R>,
     pub commands: VecDeque<Command<'s>>,
+    /// Everything that was in `commands` to begin with
+    pub total: CommandCounts,
+    /// Commands run so far, including ones skipped with a warning
+    pub done: CommandCounts,
+    pub created: Instant,
+    /// Smallest box around every command run so far, as (minx, miny, minz, maxx, maxy, maxz)
+    pub loaded_region: Option<Bounds>,
 }
 
-const MIN_PAUSE: usize = 100;
-
-#[byondapi::bind]
-pub fn _bapidmm_work_commandbuffer(parsed_map: ByondValue, resume_key: ByondValue) {
-    zone!("_bapidmm_work_commandbuffer");
-    setup_panic_handler();
-    let mut parsed_map = ParsedMapTranslationLayer { parsed_map };
-    let id = parsed_map.get_internal_index()? as usize;
-    let resume_key = resume_key.get_number()? as usize;
+impl<R> Default for CommandBuffer<'_, R> {
+    fn default() -> Self {
+        CommandBuffer {
+            created_areas: Default::default(),
+            known_types: Default::default(),
+            cached_turfs: Default::default(),
+            commands: Default::default(),
+            total: Default::default(),
+            done: Default::default(),
+            created: Instant::now(),
+            loaded_region: None,
+        }
+    }
+}
 
-    zone!("borrow parsed_map");
-    let internal_data = unsafe { PARSED_MAPS_ARENABASED.get_mut() }
-        .get_mut(id)
-        .ok_or_else(|| eyre!("Bad internal index {id:#?}"))?;
+const MIN_PAUSE: usize = 100;
 
-    zone!("borrow internal_data");
-    let mut minimum_pause_counter = 0;
+impl<'s, R: Clone> CommandBuffer<'s, R> {
+    pub fn set_commands(&mut self, commands: VecDeque<Command<'s>>) {
+        self.total = CommandCounts::default();
+        for command in &commands {
+            self.total.count(command);
+        }
+        self.done = CommandCounts::default();
+        self.loaded_region = None;
+        self.commands = commands;
+    }
 
-    let command_buffers_map = &mut internal_data.command_buffers;
+    fn grow_loaded_region(&mut self, (x, y, z): Coord) {
+        let region = self.loaded_region.get_or_insert((x, y, z, x, y, z));
+        region.0 = region.0.min(x);
+        region.1 = region.1.min(y);
+        region.2 = region.2.min(z);
+        region.3 = region.3.max(x);
+        region.4 = region.4.max(y);
+        region.5 = region.5.max(z);
+    }
 
-    zone!("lookup our buffer");
-    if let Some(our_command_buffer) = command_buffers_map.get_mut(&resume_key) {
+    /// Runs commands until they run out or the backend says the tick is over.
+    /// Returns true if it stopped early and needs to be called again.
+    pub fn work<B: WorldBackend<Ref = R>>(&mut self, backend: &mut B) -> eyre::Result<bool> {
         zone!("command loop");
-        let cached_turfs = &mut our_command_buffer.cached_turfs;
-        cached_turfs.check_invalidate()?;
+        self.cached_turfs.check_invalidate(backend)?;
+        let mut minimum_pause_counter = 0;
+
+        while let Some(command) = self.commands.pop_front() {
+            self.done.count(&command);
+            let loc = match command {
+                Command::CreateArea { loc, .. }
+                | Command::CreateTurf { loc, .. }
+                | Command::CreateAtom { loc, .. } => loc,
+            };
+            let Some(turf) = self.cached_turfs.resolve_coord(backend, loc)? else {
+                backend.add_warning(format!(
+                    "Unable to create atom at {loc:#?} because coord was null"
+                ))?;
+                continue;
+            };
+            self.grow_loaded_region(loc);
 
-        while let Some(command) = our_command_buffer.commands.pop_front() {
             match command {
-                Command::CreateArea { loc, prefab, new_z } => {
+                Command::CreateArea { prefab, new_z, .. } => {
                     zone!("Commmand::CreateArea");
 
-                    let area =
-                        if let Some(area) = our_command_buffer.created_areas.get_mut(prefab.0) {
-                            area
-                        } else {
-                            zone!("new area creation");
-                            let area = _bapi_create_or_get_area(prefab.0)?;
-                            let area = Rc::new(SmartByondValue::from(area));
-                            our_command_buffer.created_areas.insert(prefab.0, area);
-                            // This can't possibly fail, I hope
-                            our_command_buffer.created_areas.get_mut(prefab.0).unwrap()
-                        };
-
-                    let area_ref = area.get_temp_ref();
-                    let turf_ref = cached_turfs.resolve_coord(loc)?;
-                    if turf_ref.is_null() {
-                        parsed_map.add_warning(format!(
-                            "Unable to create atom at {loc:#?} because coord was null"
-                        ))?;
-                        continue;
-                    }
+                    let area = if let Some(area) = self.created_areas.get(prefab.0) {
+                        area.clone()
+                    } else {
+                        let area = backend.create_or_get_area(prefab.0)?;
+                        self.created_areas.insert(prefab.0, area.clone());
+                        area
+                    };
 
                     if !new_z {
-                        _bapi_handle_area_contain(turf_ref, area_ref)?;
+                        backend.handle_area_contain(&turf, &area)?;
                     }
-                    _bapi_add_turf_to_area(area_ref, turf_ref)?;
+                    backend.add_turf_to_area(&area, &turf)?;
                 }
                 Command::CreateTurf {
-                    loc,
                     prefab,
                     no_changeturf,
                     place_on_top,
+                    ..
                 } => {
                     zone!("Commmand::CreateTurf");
-                    let turf_ref = cached_turfs.resolve_coord(loc)?;
-                    if turf_ref.is_null() {
-                        parsed_map.add_warning(format!(
-                            "Unable to create atom at {loc:#?} because coord was null"
-                        ))?;
-                        continue;
-                    }
-
-                    create_turf(
-                        &mut parsed_map,
-                        turf_ref,
-                        prefab,
+                    backend.change_turf(
+                        &turf,
+                        prefab.0,
+                        prefab.1.as_deref(),
                         place_on_top,
                         no_changeturf,
                     )?;
                 }
-                Command::CreateAtom { loc, prefab } => {
+                Command::CreateAtom { prefab, .. } => {
                     zone!("Commmand::CreateAtom");
-                    let turf_ref = cached_turfs.resolve_coord(loc)?;
-                    if turf_ref.is_null() {
-                        parsed_map.add_warning(format!(
-                            "Unable to create atom at {loc:#?} because coord was null"
-                        ))?;
-                        continue;
-                    }
-                    create_movable(
-                        &mut parsed_map,
-                        &mut our_command_buffer.known_types,
-                        turf_ref,
-                        prefab,
-                    )?;
+                    create_movable(backend, &mut self.known_types, &turf, prefab)?;
                 }
             }
             minimum_pause_counter += 1;
 
             // Yield
-            if (minimum_pause_counter % MIN_PAUSE == 0) && _bapi_helper_tick_check()? {
-                return Ok(ByondValue::new_num(1.));
+            if (minimum_pause_counter % MIN_PAUSE == 0) && backend.tick_check()? {
+                return Ok(true);
             }
         }
 
-        // Clean up after ourselves
-        if our_command_buffer.commands.is_empty() {
-            zone!("cleanup");
-            command_buffers_map.remove(&resume_key);
-        }
+        Ok(false)
     }
+}
 
-    zone!("set_loading false and return 0");
-    parsed_map.set_loading(false)?;
+/// Reads the handles passed to the command buffer bindings, making sure they go together.
+fn buffer_handles(
+    parsed_map: &ParsedMapTranslationLayer,
+    resume_key: &ByondValue,
+) -> eyre::Result<(MapHandle, BufferHandle)> {
+    let map_handle = parsed_map.get_map_handle()?;
+    let resume_key = BufferHandle::from_byond(resume_key)?;
+    if resume_key.map != map_handle {
+        return Err(eyre!(
+            "Command buffer {resume_key} belongs to a different map than {map_handle}"
+        ));
+    }
+    Ok((map_handle, resume_key))
+}
 
-    Ok(ByondValue::new_num(0.))
+#[byondapi::bind]
+pub fn _bapidmm_work_commandbuffer(parsed_map: ByondValue, resume_key: ByondValue) {
+    zone!("_bapidmm_work_commandbuffer");
+    setup_panic_handler();
+    let mut world = ByondWorld {
+        parsed_map: ParsedMapTranslationLayer { parsed_map },
+    };
+    let yielded = work_commandbuffer(&mut world, &resume_key);
+    if !matches!(yielded, Ok(true)) {
+        // Finished or failed, either way it isn't loading anymore
+        zone!("set_loading false");
+        world.parsed_map.set_loading(false)?;
+    }
+
+    Ok(ByondValue::new_num(if yielded? { 1. } else { 0. }))
+}
+
+/// Works through a command buffer until it's time to yield. Returns true if there's more to do.
+fn work_commandbuffer(world: &mut ByondWorld, resume_key: &ByondValue) -> eyre::Result<bool> {
+    let (map_handle, resume_key) = buffer_handles(&world.parsed_map, resume_key)?;
+
+    with_registry(|registry| {
+        zone!("borrow parsed_map");
+        let map = registry.get_map(map_handle)?;
+
+        zone!("work our buffer");
+        let yielded = map
+            .with_command_buffer(resume_key.buffer, |buffer| buffer.work(world))
+            .ok_or_else(|| eyre!("Command buffer {resume_key} is stale, it already finished"))?;
+        if matches!(yielded, Ok(true)) {
+            return Ok(true);
+        }
+
+        // Clean up after ourselves, a failed buffer can't be picked back up either
+        zone!("cleanup");
+        map.remove_command_buffer(resume_key.buffer);
+        yielded
+    })
+}
+
+#[byondapi::bind]
+/// How far along a command buffer from `_bapidmm_load_map_buffered` is, as
+/// `list("done" = 10, "total" = 20, "elapsed" = 5, "areas" = list(done, total), "turfs" = ..., "atoms" = ...)`.
+/// `elapsed` is in deciseconds since the buffer was made. Finished buffers are gone, so this errors.
+pub fn _bapidmm_commandbuffer_progress(parsed_map: ByondValue, resume_key: ByondValue) {
+    setup_panic_handler();
+    let parsed_map = ParsedMapTranslationLayer { parsed_map };
+    let (map_handle, resume_key) = buffer_handles(&parsed_map, &resume_key)?;
+
+    let (done, total, elapsed) = with_registry(|registry| {
+        registry
+            .get_map(map_handle)?
+            .with_command_buffer(resume_key.buffer, |buffer| {
+                (buffer.done, buffer.total, buffer.created.elapsed())
+            })
+            .ok_or_else(|| eyre!("Command buffer {resume_key} is stale, it already finished"))
+    })?;
+
+    let mut progress = ByondValue::new_list()?;
+    let count = |n: usize| ByondValue::new_num(n as f32);
+    progress.write_list_index(ByondValue::new_str("done")?, count(done.total()))?;
+    progress.write_list_index(ByondValue::new_str("total")?, count(total.total()))?;
+    progress.write_list_index(
+        ByondValue::new_str("elapsed")?,
+        ByondValue::new_num(elapsed.as_secs_f32() * 10.),
+    )?;
+    for (kind, done, total) in [
+        ("areas", done.areas, total.areas),
+        ("turfs", done.turfs, total.turfs),
+        ("atoms", done.atoms, total.atoms),
+    ] {
+        let pair = ByondValue::new_list()?;
+        pair.write_list(&[count(done), count(total)])?;
+        progress.write_list_index(ByondValue::new_str(kind)?, pair)?;
+    }
+
+    Ok(progress)
 }
 
-fn create_turf(
-    parsed_map: &mut ParsedMapTranslationLayer,
-    turf: ByondValue,
-    prefab_turf: &dmm_lite::prefabs::Prefab,
-    place_on_top: bool,
-    no_changeturf: bool,
-) -> eyre::Result<ByondValue> {
-    zone!("create_turf");
-    let (path_text, vars) = prefab_turf;
+#[byondapi::bind]
+/// Stops a load partway, throwing away the rest of its commands. Returns the region that was
+/// (partially) loaded as list(minx, miny, minz, maxx, maxy, maxz), or null if nothing was placed yet
+/// or if this was called from inside a load.
+pub fn _bapidmm_cancel_commandbuffer(parsed_map: ByondValue, resume_key: ByondValue) {
+    setup_panic_handler();
+    let mut parsed_map = ParsedMapTranslationLayer { parsed_map };
+    let (_, resume_key) = buffer_handles(&parsed_map, &resume_key)?;
 
-    zone!("creating path string");
-    let vars_list = convert_vars_list_to_byondlist(parsed_map, vars)?;
+    // A datum can be deleted from inside a load, and cancel itself then. The buffer is dropped once
+    // that load's call returns, and we don't know what it had loaded yet.
+    let loaded_region = with_registry_or_defer(Deferred::CancelBuffer(resume_key), |registry| {
+        registry.cancel_buffer(resume_key)
+    })?
+    .flatten();
 
-    _bapi_create_turf(turf, path_text, vars_list, place_on_top, no_changeturf)
+    parsed_map.set_loading(false)?;
+
+    let Some(region) = loaded_region else {
+        return Ok(ByondValue::null());
+    };
+    let list = ByondValue::new_list()?;
+    list.write_list(
+        &[region.0, region.1, region.2, region.3, region.4, region.5]
+            .map(|n| ByondValue::new_num(n as f32)),
+    )?;
+    Ok(list)
 }
 
-fn create_movable<'s>(
-    parsed_map: &mut ParsedMapTranslationLayer,
-    path_cache: &mut HashMap<&'s str, SharedByondValue>,
-    turf: ByondValue,
+fn create_movable<'s, B: WorldBackend>(
+    backend: &mut B,
+    path_cache: &mut HashMap<&'s str, B::Ref>,
+    turf: &B::Ref,
     obj: &'s dmm_lite::prefabs::Prefab,
 ) -> eyre::Result<()> {
     zone!("movable creation");
     let (path_text, vars) = obj;
     let path = if let Some(path) = path_cache.get(*path_text) {
-        path
+        path.clone()
     } else {
-        let path = _bapi_helper_text2path(path_text)?;
-        if path.is_null() {
-            parsed_map.add_warning(format!("Bad path {path_text:#?}"))?;
+        let Some(path) = backend.text2path(path_text)? else {
+            backend.add_warning(format!("Bad path {path_text:#?}"))?;
             return Ok(());
-        }
+        };
 
-        let path = Rc::new(SmartByondValue::from(path));
-        path_cache.insert(path_text, path);
-        path_cache.get(path_text).unwrap()
+        path_cache.insert(path_text, path.clone());
+        path
     };
 
-    if vars.is_some() {
-        let vars_list = convert_vars_list_to_byondlist(parsed_map, vars)?;
-        _bapi_setup_preloader(vars_list, path.get_temp_ref())?;
+    if let Some(vars) = vars {
+        backend.setup_preloader(vars, &path)?;
     }
 
-    zone!("byond_new");
-    let instance = ByondValue::builtin_new(path.get_temp_ref(), &[turf])?;
+    let instance = backend.new_movable(&path, turf)?;
 
-    _bapi_apply_preloader(instance)?;
+    backend.apply_preloader(&instance)?;
 
     Ok(())
 }
 
-fn convert_vars_list_to_byondlist(
-    parsed_map: &mut ParsedMapTranslationLayer,
-    vars: &Option<Vec<(&str, Literal)>>,
-) -> eyre::Result<ByondValue> {
-    zone!("convert_vars_list_to_byondlist");
-    if let Some(vars) = vars {
-        let mut vars_list = ByondValue::new_list()?;
-        for (key, literal) in vars {
-            let value = convert_literal_to_byondvalue(parsed_map, key, literal)?;
-            vars_list.write_list_index(ByondValue::new_str(*key)?, value)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::{
+        backend::mock::{MockRef, MockWorld},
+        parse_test_map,
+        plan::{plan_load, Bounds, LoadOptions, WorldInfo},
+    };
+
+    const MAP: &str = r#""a" = (/turf/space,/area/space)
+"b" = (/obj/table,/obj/item/pen{name = "biro"; pixel_x = -4},/turf/floor{dir = 4},/area/hall)
+
+(1,1,1) = {"
+ab
+ba
+"}
+"#;
+
+    /// Plans loading the map at (1, 1, 1), the way load_buffer does
+    fn buffer<'s>(
+        world: &mut MockWorld,
+        map: &'s dmm_lite::MapData<'s>,
+        parsed_bounds: Bounds,
+    ) -> CommandBuffer<'s, MockRef> {
+        let plan = plan_load(
+            map,
+            1,
+            parsed_bounds,
+            &WorldInfo {
+                bounds: world.bounds,
+                turf: "/turf/space",
+                area: "/area/space",
+            },
+            &LoadOptions {
+                offset: (1., 1., 1.),
+                crop_map: false,
+                no_changeturf: false,
+                lower_bounds: (1., 1., 1.),
+                upper_bounds: (f32::INFINITY, f32::INFINITY, f32::INFINITY),
+                place_on_top: false,
+                new_z: false,
+            },
+        );
+        let mut buffer = CommandBuffer::default();
+        buffer.cached_turfs.world_bounds = plan.world_bounds;
+        for coord in plan.turfs {
+            buffer.cached_turfs.cache(world, coord).unwrap();
         }
-        Ok(vars_list)
-    } else {
-        Ok(ByondValue::null())
+        buffer.set_commands(plan.commands);
+        buffer
     }
-}
 
-/// This only hard errors when running into an internal BYOND error, such as bad proc, bad value, out of memory, etc
-fn convert_literal_to_byondvalue(
-    parsed_map: &mut ParsedMapTranslationLayer,
-    key: &str,
-    literal: &Literal,
-) -> eyre::Result<ByondValue> {
-    zone!("convert_literal_to_byondvalue");
-    Ok(match literal {
-        Literal::Number(n) => ByondValue::new_num(*n),
-        Literal::String(s) => ByondValue::new_str(*s)?,
-        Literal::Path(p) => _bapi_helper_text2path(p)?,
-        Literal::File(f) => _bapi_helper_text2file(f)?,
-        Literal::Null => ByondValue::null(),
-        Literal::Fallback(s) => {
-            parsed_map.add_warning(format!(
-                "Parser failed to parse value for {:#?} and fellback to string: {s:#?}",
-                key
-            ))?;
-            ByondValue::new_str(*s)?
-        }
-        Literal::List(l) => {
-            zone!("convert_literal_to_byondvalue(list)");
-            let mut list = ByondValue::new_list()?;
-
-            for literal in l {
-                match convert_literal_to_byondvalue(parsed_map, key, literal) {
-                    Ok(item) => list.push_list(item)?,
-                    Err(e) => {
-                        parsed_map.add_warning(format!(
-                            "Inside list inside {:#?}, failed to parse value: {e:#?}",
-                            key
-                        ))?;
-                    }
-                }
-            }
+    #[test]
+    fn test_work() {
+        let map = parse_test_map(MAP);
+        let mut world = MockWorld::new((3, 3, 1), "/turf/space");
+        let mut buffer = buffer(&mut world, &map, (1, 1, 1, 2, 2, 1));
+
+        assert!(!buffer.work(&mut world).unwrap());
+        assert!(buffer.commands.is_empty());
+        assert!(world.warnings.is_empty());
+
+        let floor = world.turf((2, 2, 1));
+        assert_eq!(floor.path, "/turf/floor");
+        assert_eq!(floor.vars, vec![("dir".to_owned(), "4".to_owned())]);
+        assert_eq!(floor.area.as_deref(), Some("/area/hall"));
+        assert_eq!(world.turf((2, 1, 1)).area.as_deref(), Some("/area/space"));
+        // Outside the map
+        assert_eq!(world.turf((3, 3, 1)).area, None);
+
+        assert_eq!(
+            world.contents((1, 1, 1)),
+            vec!["/obj/table", "/obj/item/pen"]
+        );
+        assert_eq!(world.contents((2, 1, 1)), Vec::<&str>::new());
+        let pen = &world.movables[1];
+        assert_eq!(
+            pen.vars,
+            vec![
+                ("name".to_owned(), "\"biro\"".to_owned()),
+                ("pixel_x".to_owned(), "-4".to_owned()),
+            ]
+        );
+        assert!(world.movables[0].vars.is_empty());
+        assert_eq!(
+            world.areas.iter().collect::<Vec<_>>(),
+            vec!["/area/hall", "/area/space"]
+        );
+    }
 
-            list
-        }
-        Literal::AssocList(map) => {
-            zone!("convert_literal_to_byondvalue(assoc list)");
-            let mut list = ByondValue::new_list()?;
-
-            for (list_key, list_val) in map.iter() {
-                let key_bv = convert_literal_to_byondvalue(parsed_map, key, list_key);
-                let val_bv = convert_literal_to_byondvalue(parsed_map, key, list_val);
-
-                match (key_bv, val_bv) {
-                    (Ok(key), Ok(val)) => list.write_list_index(key, val)?,
-                    (Err(e), _) => parsed_map.add_warning(format!(
-                        "Inside assoc list inside {:#?}, failed to parse assoc list key: {e:#?}",
-                        key,
-                    ))?,
-                    (_, Err(e)) => parsed_map.add_warning(format!(
-                        "Inside assoc list inside {:#?}, failed to parse assoc list value: {e:#?}",
-                        key
-                    ))?,
-                }
+    #[test]
+    fn test_work_yields() {
+        // 50 tiles of 4 commands each
+        let map = format!(
+            "\"b\" = (/obj/table,/obj/item/pen,/turf/floor,/area/hall)\n\n(1,1,1) = {{\"\n{}\n\"}}\n",
+            "b".repeat(50)
+        );
+        let map = parse_test_map(&map);
+        let mut world = MockWorld::new((50, 1, 1), "/turf/space");
+        world.tick_every = Some(1);
+        let mut buffer = buffer(&mut world, &map, (1, 1, 1, 50, 1, 1));
+        assert_eq!(buffer.commands.len(), 200);
+
+        assert_eq!(buffer.total.total(), 200);
+        assert_eq!(buffer.done.total(), 0);
+
+        assert!(buffer.work(&mut world).unwrap());
+        assert_eq!(buffer.commands.len(), 100);
+        assert_eq!(buffer.done.total(), 100);
+        assert_eq!(buffer.loaded_region, Some((1, 1, 1, 25, 1, 1)));
+        assert!(buffer.work(&mut world).unwrap());
+        assert!(buffer.commands.is_empty());
+        assert!(!buffer.work(&mut world).unwrap());
+        assert_eq!(world.movables.len(), 100);
+        assert_eq!(world.tick_checks, 2);
+        assert_eq!(buffer.done, buffer.total);
+        assert_eq!(buffer.loaded_region, Some((1, 1, 1, 50, 1, 1)));
+        assert_eq!(
+            buffer.total,
+            CommandCounts {
+                areas: 50,
+                turfs: 50,
+                atoms: 100,
             }
+        );
+    }
 
-            list
-        }
-    })
+    #[test]
+    fn test_cancel_partway() {
+        // 50 tiles of 4 commands each
+        let map = format!(
+            "\"b\" = (/obj/table,/obj/item/pen,/turf/floor,/area/hall)\n\n(1,1,1) = {{\"\n{}\n\"}}\n",
+            "b".repeat(50)
+        );
+        let map = parse_test_map(&map);
+        let mut world = MockWorld::new((50, 1, 1), "/turf/space");
+        world.tick_every = Some(1);
+        let mut buffer = buffer(&mut world, &map, (1, 1, 1, 50, 1, 1));
+        // Nothing can be placed on the first tile
+        world.turfs.remove(&(1, 1, 1));
+        buffer.cached_turfs.cached_turfs.remove(&(1, 1, 1));
+
+        assert!(buffer.work(&mut world).unwrap());
+        assert_eq!(buffer.commands.len(), 96);
+
+        // Cancelled the way _bapidmm_cancel_commandbuffer does it, the rest is thrown away
+        let loaded_region = buffer.loaded_region;
+        drop(buffer);
+        assert_eq!(loaded_region, Some((2, 1, 1, 26, 1, 1)));
+        assert_eq!(world.movables.len(), 50);
+        assert_eq!(world.turf((26, 1, 1)).path, "/turf/floor");
+        assert_eq!(world.turf((27, 1, 1)).path, "/turf/space");
+        assert_eq!(world.turf((27, 1, 1)).area, None);
+    }
+
+    #[test]
+    fn test_work_warnings() {
+        let map = parse_test_map(MAP);
+        let mut world = MockWorld::new((3, 3, 1), "/turf/space");
+        world.types = Some(["/obj/table".to_owned()].into());
+        let mut buffer = buffer(&mut world, &map, (1, 1, 1, 2, 2, 1));
+        // The turf goes missing after the buffer was made
+        world.turfs.remove(&(2, 2, 1));
+        buffer.cached_turfs.cached_turfs.remove(&(2, 2, 1));
+
+        assert!(!buffer.work(&mut world).unwrap());
+        assert_eq!(
+            world.warnings,
+            vec!["Unable to create atom at (\n    2,\n    2,\n    1,\n) because coord was null"; 4]
+                .into_iter()
+                .chain(["Bad path \"/obj/item/pen\""])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(world.contents((1, 1, 1)), vec!["/obj/table"]);
+        // The preloader was never set up for a type that doesn't exist
+        assert!(world.movables[0].vars.is_empty());
+    }
+
+    #[test]
+    fn test_work_invalidates_turfs() {
+        let map = parse_test_map(MAP);
+        let mut world = MockWorld::new((2, 2, 1), "/turf/space");
+        let mut buffer = buffer(&mut world, &map, (1, 1, 1, 2, 2, 1));
+
+        // Something else grew the world between ticks
+        world.bounds = (2, 2, 2);
+        buffer.work(&mut world).unwrap();
+        assert_eq!(buffer.cached_turfs.world_bounds, (2, 2, 2));
+        assert_eq!(buffer.cached_turfs.cached_turfs.len(), 4);
+    }
 }
@@ -5,6 +5,8 @@ use byondapi::{global_call::call_global, value::ByondValue};
 use eyre::{Context, Result};
 use tracy_full::zone;
 
+use crate::handle::MapHandle;
+
 /// Gets the current world.maxx, world.maxy, and world.maxz
 pub fn _bapi_helper_get_world_bounds() -> Result<(usize, usize, usize)> {
     zone!("_bapi_helper_get_world_bounds");
@@ -149,11 +151,25 @@ impl ParsedMapTranslationLayer {
     }
 
     // Getters/Setters
-    /// Get the _internal_index pointing into our ouroboros struct of parsed map data.
-    pub fn get_internal_index(&self) -> Result<f32> {
-        self.parsed_map
-            .read_number("_internal_index")
-            .context("Unable to read /datum/bapi_parsed_map/_internal_index")
+    /// Get the handle in _internal_index pointing to our parsed map data.
+    pub fn get_map_handle(&self) -> Result<MapHandle> {
+        let handle = self
+            .parsed_map
+            .read_var("_internal_index")
+            .context("Unable to read /datum/bapi_parsed_map/_internal_index")?;
+        MapHandle::from_byond(&handle).context(
+            "/datum/bapi_parsed_map doesn't have a parsed map, it failed to parse or was released",
+        )
+    }
+
+    /// Point the datum at a different map, or at nothing (-1).
+    pub fn set_map_handle(&mut self, handle: Option<MapHandle>) -> Result<()> {
+        let handle = match handle {
+            Some(handle) => ByondValue::new_str(handle.to_string())?,
+            None => ByondValue::new_num(-1.),
+        };
+        self.parsed_map.write_var("_internal_index", &handle)?;
+        Ok(())
     }
 
     /// Get the earlier-calculated key length without having to check again.
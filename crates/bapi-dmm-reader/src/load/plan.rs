@@ -0,0 +1,479 @@
+//! Working out what loading a map will do, without touching BYOND.
+//!
+//! [`plan_load`] takes the parsed map and what it needs to know about the world, and returns the
+//! commands to run, the bounds the map ends up covering, how far the world needs to grow and any
+//! warnings. [`crate::load::load_buffer`] does the BYOND side: asking for the world's size,
+//! expanding it and handing the warnings to DM.
+use std::collections::VecDeque;
+
+use dmm_lite::MapData;
+use tracy_full::zone;
+
+use crate::load::command_buffer::Command;
+
+/// (x, y, z), starting at (1, 1, 1)
+pub type Coord = (usize, usize, usize);
+
+/// (minx, miny, minz, maxx, maxy, maxz)
+pub type Bounds = (usize, usize, usize, usize, usize, usize);
+
+/// How the map should be loaded, as passed in from DM.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadOptions {
+    /// Where (1, 1, 1) of the map goes
+    pub offset: (f32, f32, f32),
+    /// Drop anything outside the world, instead of expanding it
+    pub crop_map: bool,
+    pub no_changeturf: bool,
+    /// Only load the part of the map inside these, in map coordinates.
+    /// These MUST be f32 because they can be INFINITY
+    pub lower_bounds: (f32, f32, f32),
+    pub upper_bounds: (f32, f32, f32),
+    pub place_on_top: bool,
+    pub new_z: bool,
+}
+
+/// What planning needs to know about the world being loaded into.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorldInfo<'w> {
+    /// world.maxx, world.maxy and world.maxz
+    pub bounds: Coord,
+    /// world.turf and world.area, to know which tiles are just empty space
+    pub turf: &'w str,
+    pub area: &'w str,
+}
+
+/// Everything loading a map will do.
+#[derive(Debug, Default)]
+pub struct LoadPlan<'s> {
+    pub commands: VecDeque<Command<'s>>,
+    /// What the map actually covers once placed, which can be less than its parsed bounds
+    pub bounds: Bounds,
+    /// The world size to expand to first, if the map doesn't fit
+    pub expand_to: Option<Coord>,
+    /// The world size the commands are planned against, after any expansion
+    pub world_bounds: Coord,
+    /// Every tile something gets placed on, in order
+    pub turfs: Vec<Coord>,
+    pub warnings: Vec<String>,
+}
+
+/// Plans loading a map. `parsed_bounds` are the map's own bounds, after any rotation.
+pub fn plan_load<'s>(
+    (prefabs, blocks): &'s MapData<'s>,
+    key_len: usize,
+    parsed_bounds: Bounds,
+    world: &WorldInfo,
+    options: &LoadOptions,
+) -> LoadPlan<'s> {
+    zone!("plan_load");
+    let offset = options.offset;
+    let mut plan = LoadPlan {
+        world_bounds: world.bounds,
+        // Starts at (1, 1, 1)
+        bounds: (usize::MAX, usize::MAX, usize::MAX, 1, 1, 1),
+        ..Default::default()
+    };
+
+    // Expand map if necessary
+    if !options.crop_map {
+        let max_extent_offset = (
+            offset.0 as usize + parsed_bounds.3 - 1,
+            offset.1 as usize + parsed_bounds.4 - 1,
+            offset.2 as usize + parsed_bounds.5 - 1,
+        );
+        if exceeds_upper_bounds(max_extent_offset, world.bounds) {
+            plan.expand_to = Some(max_extent_offset);
+            // Expanding only ever grows the world
+            plan.world_bounds = (
+                world.bounds.0.max(max_extent_offset.0),
+                world.bounds.1.max(max_extent_offset.1),
+                world.bounds.2.max(max_extent_offset.2),
+            );
+        }
+    }
+
+    let space_key: Option<&str> = if options.no_changeturf {
+        prefabs.iter().find_map(|(key, prefab_list)| {
+            if prefab_list.len() != 2 {
+                return None;
+            }
+            match prefab_list[0] {
+                (turf, None) if turf == world.turf => {}
+                _ => return None,
+            }
+            match prefab_list[1] {
+                (area, None) if area == world.area => {}
+                _ => return None,
+            }
+            Some(*key)
+        })
+    } else {
+        None
+    };
+
+    // We know bounds ahead of time so we
+    let mut no_afterchange = options.no_changeturf;
+    if parsed_bounds.5 + (offset.2 as usize) - 1 > world.bounds.2 {
+        // z expansion
+        if !options.no_changeturf {
+            plan.warnings.push("Z-level expansion occurred without no_changeturf set, this may cause problems when /turf/AfterChange is called, and therefore ChangeTurf will NOT be called".to_owned());
+            no_afterchange = true; // force no_afterchange
+        }
+    }
+
+    for (bottom_left, block) in blocks {
+        // We have to reverse and THEN enumerate this to translate from
+        // origin TOP left to origin BOTTOM left
+        // and then reverse it again to do the correct iteration order
+        for (map_y_offset, line) in block.iter().rev().enumerate().rev() {
+            let turfs = separate_turfs(line, key_len);
+            for (map_x_offset, prefab_key) in turfs.enumerate() {
+                let relative_coord = (
+                    bottom_left.0 + map_x_offset,
+                    bottom_left.1 + map_y_offset,
+                    bottom_left.2,
+                );
+
+                // Skip anything outside of our relative bounds
+                if float_exceeds_upper_bounds(relative_coord, options.upper_bounds) {
+                    continue;
+                }
+                // for some reason, negative bounds are permitted?
+                if float_exceeds_lower_bounds(relative_coord, options.lower_bounds) {
+                    continue;
+                }
+
+                // Calculate absolute position
+                // This is offset - 1 because (1,1,1) actually goes *at* offset
+                let exact_coord = (
+                    relative_coord.0 + offset.0 as usize - 1,
+                    relative_coord.1 + offset.1 as usize - 1,
+                    relative_coord.2 + offset.2 as usize - 1,
+                );
+
+                // This will just guaranteed fail to locate a turf
+                if exceeds_lower_bounds(exact_coord, (1, 1, 1)) {
+                    plan.warnings.push(format!(
+                        "Bad map coord (tries to spawn in negative space): {exact_coord:#?}"
+                    ));
+                    continue;
+                }
+
+                // Avoid generating OOB commands
+                if exceeds_upper_bounds(exact_coord, world.bounds) && options.crop_map {
+                    continue;
+                }
+
+                if Some(prefab_key) == space_key && no_afterchange {
+                    continue;
+                }
+
+                let Some(prefab) = prefabs.get(prefab_key) else {
+                    // Note: Cannot hard error or map will fail to finish loading
+                    // This is necessarily just a warning
+                    plan.warnings
+                        .push(format!("Invalid prefab key: {prefab_key:#?}"));
+                    continue;
+                };
+
+                // DMM prefab require that all prefab lists end with one /turf, and then one /area.
+                if prefab.len() < 2 {
+                    plan.warnings.push(format!(
+                        "Prefab {prefab_key:#?} is too short, violating requirement for /turf and /area!"
+                    ));
+                    continue;
+                }
+
+                // This is the point where we are committed, we are GOING to put something at this coord
+                // Accordingly, this is where we calculate bounds
+                plan.bounds.0 = plan.bounds.0.min(exact_coord.0);
+                plan.bounds.1 = plan.bounds.1.min(exact_coord.1);
+                plan.bounds.2 = plan.bounds.2.min(exact_coord.2);
+                plan.bounds.3 = plan.bounds.3.max(exact_coord.0);
+                plan.bounds.4 = plan.bounds.4.max(exact_coord.1);
+                plan.bounds.5 = plan.bounds.5.max(exact_coord.2);
+
+                plan.turfs.push(exact_coord);
+
+                let mut prefab_list = prefab.iter().rev();
+                // Above check ensures that these cannot panic
+                let prefab_area = prefab_list.next().unwrap();
+                if !prefab_area.0.starts_with("/area") {
+                    plan.warnings.push(format!(
+                        "Prefab {prefab_key:#?} does not end in an area, instead ending in {prefab_area:#?}!"
+                    ));
+                    continue;
+                }
+                if !prefab_area.0.starts_with("/area/template_noop") {
+                    plan.commands.push_back(Command::CreateArea {
+                        loc: exact_coord,
+                        prefab: prefab_area,
+                        new_z: options.new_z,
+                    });
+                }
+
+                let prefab_turf = prefab_list.next().unwrap();
+                if !prefab_turf.0.starts_with("/turf") {
+                    plan.warnings.push(format!(
+                        "Prefab {prefab_key:#?} does not second-end in a turf, instead ending in {prefab_turf:#?}!"
+                    ));
+                    continue;
+                }
+                if !prefab_turf.0.starts_with("/turf/template_noop") {
+                    plan.commands.push_back(Command::CreateTurf {
+                        loc: exact_coord,
+                        prefab: prefab_turf,
+                        no_changeturf: no_afterchange,
+                        place_on_top: options.place_on_top,
+                    })
+                }
+
+                // We reverse it again after doing the turf and area
+                for instance in prefab_list.rev() {
+                    // We allow these but warn about them
+                    if !instance.0.starts_with("/obj") && !instance.0.starts_with("/mob") {
+                        if instance.0.starts_with("/turf") {
+                            plan.warnings.push(format!(
+                                "Prefab {prefab_key:#?} had a secondary turf that we aren't going to deal with: {instance:#?}"
+                            ));
+                            continue;
+                        } else {
+                            plan.warnings.push(format!(
+                                "Prefab {prefab_key:#?} has a strange element that we'll treat as a movable: {instance:#?}"
+                            ));
+                        }
+                    }
+                    // Movables are easy
+                    plan.commands.push_back(Command::CreateAtom {
+                        loc: exact_coord,
+                        prefab: instance,
+                    });
+                }
+            }
+        }
+    }
+
+    plan
+}
+
+// Helpers
+fn exceeds_upper_bounds(check: Coord, bounds: Coord) -> bool {
+    check.0 > bounds.0 || check.1 > bounds.1 || check.2 > bounds.2
+}
+
+fn exceeds_lower_bounds(check: Coord, bounds: Coord) -> bool {
+    check.0 < bounds.0 || check.1 < bounds.1 || check.2 < bounds.2
+}
+
+fn float_exceeds_upper_bounds(check: Coord, bounds: (f32, f32, f32)) -> bool {
+    (check.0 as f32) > bounds.0 || (check.1 as f32) > bounds.1 || (check.2 as f32) > bounds.2
+}
+
+fn float_exceeds_lower_bounds(check: Coord, bounds: (f32, f32, f32)) -> bool {
+    (check.0 as f32) < bounds.0 || (check.1 as f32) < bounds.1 || (check.2 as f32) < bounds.2
+}
+
+fn separate_turfs(mut s: &str, n: usize) -> impl Iterator<Item = &'_ str> {
+    assert_ne!(n, 0);
+    std::iter::from_fn(move || {
+        let index = s
+            .char_indices()
+            .nth(n)
+            .map(|(index, _)| index)
+            .unwrap_or(s.len());
+        let (item, rest) = s.split_at(index);
+        if item.is_empty() {
+            None
+        } else {
+            s = rest;
+            Some(item)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::parse_test_map;
+
+    const MAP: &str = r#""a" = (/turf/space,/area/space)
+"b" = (/obj/table,/obj/item/pen,/turf/floor,/area/hall)
+"c" = (/turf/template_noop,/area/template_noop)
+
+(1,1,1) = {"
+ab
+ca
+"}
+"#;
+
+    const WORLD: WorldInfo = WorldInfo {
+        bounds: (10, 10, 1),
+        turf: "/turf/space",
+        area: "/area/space",
+    };
+
+    fn options() -> LoadOptions {
+        LoadOptions {
+            offset: (1., 1., 1.),
+            crop_map: false,
+            no_changeturf: false,
+            lower_bounds: (1., 1., 1.),
+            upper_bounds: (f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            place_on_top: false,
+            new_z: false,
+        }
+    }
+
+    /// Each command as (kind, coord, path)
+    fn summary<'s>(plan: &LoadPlan<'s>) -> Vec<(&'static str, Coord, &'s str)> {
+        plan.commands
+            .iter()
+            .map(|command| match command {
+                Command::CreateArea { loc, prefab, .. } => ("area", *loc, prefab.0),
+                Command::CreateTurf { loc, prefab, .. } => ("turf", *loc, prefab.0),
+                Command::CreateAtom { loc, prefab } => ("atom", *loc, prefab.0),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_load() {
+        let map = parse_test_map(MAP);
+        let plan = plan_load(&map, 1, (1, 1, 1, 2, 2, 1), &WORLD, &options());
+        assert_eq!(
+            summary(&plan),
+            vec![
+                // Top row first, left to right
+                ("area", (1, 2, 1), "/area/space"),
+                ("turf", (1, 2, 1), "/turf/space"),
+                ("area", (2, 2, 1), "/area/hall"),
+                ("turf", (2, 2, 1), "/turf/floor"),
+                ("atom", (2, 2, 1), "/obj/table"),
+                ("atom", (2, 2, 1), "/obj/item/pen"),
+                // Template noops are skipped, but still count towards the bounds
+                ("area", (2, 1, 1), "/area/space"),
+                ("turf", (2, 1, 1), "/turf/space"),
+            ]
+        );
+        assert_eq!(plan.bounds, (1, 1, 1, 2, 2, 1));
+        assert_eq!(plan.turfs, vec![(1, 2, 1), (2, 2, 1), (1, 1, 1), (2, 1, 1)]);
+        assert_eq!(plan.expand_to, None);
+        assert!(plan.warnings.is_empty());
+
+        // Offset, and only the top row
+        let plan = plan_load(
+            &map,
+            1,
+            (1, 1, 1, 2, 2, 1),
+            &WORLD,
+            &LoadOptions {
+                offset: (5., 3., 1.),
+                lower_bounds: (1., 2., 1.),
+                ..options()
+            },
+        );
+        assert_eq!(plan.bounds, (5, 4, 1, 6, 4, 1));
+        assert_eq!(plan.commands.len(), 6);
+    }
+
+    #[test]
+    fn test_plan_load_space() {
+        let map = parse_test_map(MAP);
+        let plan = plan_load(
+            &map,
+            1,
+            (1, 1, 1, 2, 2, 1),
+            &WORLD,
+            &LoadOptions {
+                no_changeturf: true,
+                ..options()
+            },
+        );
+        // Plain space is already there, so isn't loaded at all
+        assert_eq!(plan.turfs, vec![(2, 2, 1), (1, 1, 1)]);
+        assert!(summary(&plan)
+            .iter()
+            .all(|(_, _, path)| *path != "/turf/space"));
+    }
+
+    #[test]
+    fn test_plan_load_expansion() {
+        let map = parse_test_map(MAP);
+        let small_world = WorldInfo {
+            bounds: (1, 1, 1),
+            ..WORLD
+        };
+
+        let plan = plan_load(&map, 1, (1, 1, 1, 2, 2, 1), &small_world, &options());
+        assert_eq!(plan.expand_to, Some((2, 2, 1)));
+        assert_eq!(plan.world_bounds, (2, 2, 1));
+        assert_eq!(plan.turfs.len(), 4);
+
+        let plan = plan_load(
+            &map,
+            1,
+            (1, 1, 1, 2, 2, 1),
+            &small_world,
+            &LoadOptions {
+                crop_map: true,
+                ..options()
+            },
+        );
+        assert_eq!(plan.expand_to, None);
+        assert_eq!(plan.turfs, vec![(1, 1, 1)]);
+
+        // A new z-level without no_changeturf can't call ChangeTurf
+        let plan = plan_load(
+            &map,
+            1,
+            (1, 1, 1, 2, 2, 1),
+            &WORLD,
+            &LoadOptions {
+                offset: (1., 1., 2.),
+                ..options()
+            },
+        );
+        // Just what the map needs, DM only ever grows the world
+        assert_eq!(plan.expand_to, Some((2, 2, 2)));
+        assert_eq!(plan.world_bounds, (10, 10, 2));
+        assert_eq!(plan.warnings.len(), 1);
+        assert!(plan.commands.iter().all(|command| match command {
+            Command::CreateTurf { no_changeturf, .. } => *no_changeturf,
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn test_plan_load_warnings() {
+        let map = parse_test_map(
+            r#""a" = (/turf/floor,/area/hall)
+"b" = (/turf/floor,/turf/wall,/area/hall)
+"c" = (/area/hall)
+
+(1,1,1) = {"
+abcd
+"}
+"#,
+        );
+        let plan = plan_load(
+            &map,
+            1,
+            (1, 1, 1, 4, 1, 1),
+            &WORLD,
+            &LoadOptions {
+                offset: (0., 1., 1.),
+                ..options()
+            },
+        );
+        assert_eq!(
+            plan.warnings,
+            vec![
+                "Bad map coord (tries to spawn in negative space): (\n    0,\n    1,\n    1,\n)",
+                "Prefab \"b\" had a secondary turf that we aren't going to deal with: (\n    \"/turf/floor\",\n    None,\n)",
+                "Prefab \"c\" is too short, violating requirement for /turf and /area!",
+                "Invalid prefab key: \"d\"",
+            ]
+        );
+    }
+}
@@ -1,74 +1,270 @@
 //! All things to do with parsing the map in preparation for loading it
 use byondapi::prelude::*;
 use eyre::eyre;
-use std::{collections::HashMap, path::Path};
+use std::{
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+    time::SystemTime,
+};
 
 use crate::{
-    _compat::setup_panic_handler,
-    arena::{get_arena, ArenaMap, PARSED_MAPS_ARENABASED},
+    _compat::setup_panic_handler, arena::OwnedMap, handle::MapHandle, registry::with_registry,
+    ParseHandle,
 };
 
 const MAP_TGM: &str = "tgm";
 const MAP_DMM: &str = "dmm";
 
 #[byondapi::bind]
-/// This is a dumb function: It will simply parse the file you tell it to
-/// Any caching must be done in DM
+/// Parses the file you tell it to, or reuses the last parse of it if the file hasn't changed since
 pub fn _bapidmm_parse_map_blocking(dmm_file: ByondValue, mut map_datum: ByondValue) {
     setup_panic_handler();
 
-    if !dmm_file.is_str() {
-        return Err(eyre!("dmm_file was not a string: {dmm_file:#?}"));
+    let dmm_file_str = map_file_path(&dmm_file)?;
+    let file = MapFile::open(&dmm_file_str)?;
+
+    let handle = match with_registry(|registry| Ok(registry.cached_map(&file)))? {
+        Some(handle) => handle,
+        None => {
+            let map = parse_map_file(&dmm_file_str, &file)?;
+            with_registry(|registry| Ok(registry.insert_parsed_file(map, file)))?
+        }
+    };
+
+    store_parsed_map(dmm_file, &mut map_datum, handle)?;
+
+    Ok(ByondValue::new_num(1.0))
+}
+
+#[byondapi::bind]
+/// Parses map text straight from DM, for maps that aren't on disk like uploaded templates.
+/// `name` stands in for the file name, in errors and as the map's original_path.
+pub fn _bapidmm_parse_map_text(text: ByondValue, name: ByondValue, mut map_datum: ByondValue) {
+    setup_panic_handler();
+
+    if !text.is_str() {
+        return Err(eyre!("text was not a string: {text:#?}"));
     }
+    if !name.is_str() {
+        return Err(eyre!("name was not a string: {name:#?}"));
+    }
+
+    let name_str = name.get_string()?;
+
+    let map = OwnedMap::parse(name_str.clone(), text.get_string()?)
+        .map_err(|e| eyre!("Error parsing {name_str:#?}: {e:#?}"))?;
+
+    let handle = with_registry(|registry| Ok(registry.insert_map(map)))?;
+    store_parsed_map(name, &mut map_datum, handle)?;
+
+    Ok(ByondValue::new_num(1.0))
+}
+
+/// What a map file looked like when it was parsed. If it changes, the file gets parsed again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+/// A map file on disk, for the parse cache in [`crate::registry::Registry`]
+#[derive(Debug, Clone)]
+pub struct MapFile {
+    /// Canonical, so the same file under different paths is only parsed once
+    pub path: PathBuf,
+    pub stamp: FileStamp,
+}
 
-    let dmm_file_str = dmm_file.get_string()?;
+impl MapFile {
+    pub fn open(dmm_file_str: &str) -> eyre::Result<Self> {
+        let path = Path::new(dmm_file_str);
+        if !path.is_file() {
+            return Err(eyre!("Unable to find {dmm_file_str:#?} on disk"));
+        }
 
-    let path = Path::new(&dmm_file_str);
-    if !path.is_file() {
-        return Err(eyre!("Unable to find {dmm_file_str:#?} on disk"));
+        let path = path
+            .canonicalize()
+            .map_err(|e| eyre!("Failed to resolve {dmm_file_str:#?}: {e:#?}"))?;
+        let metadata = std::fs::metadata(&path)
+            .map_err(|e| eyre!("Failed to read {dmm_file_str:#?}: {e:#?}"))?;
+
+        Ok(MapFile {
+            path,
+            stamp: FileStamp {
+                modified: metadata.modified().ok(),
+                len: metadata.len(),
+            },
+        })
     }
+}
+
+/// A parse started by `_bapidmm_parse_map_async`
+pub struct PendingParse {
+    pub dmm_file: String,
+    pub job: ParseJob,
+}
+
+pub enum ParseJob {
+    /// The file was already parsed, this holds a reference to it until it's polled
+    Cached(MapHandle),
+    Parsing {
+        file: MapFile,
+        thread: JoinHandle<eyre::Result<OwnedMap>>,
+    },
+}
+
+#[byondapi::bind]
+/// Same as `_bapidmm_parse_map_blocking`, but reads and parses the file on another thread.
+/// Returns a handle to pass to `_bapidmm_poll_parse`.
+pub fn _bapidmm_parse_map_async(dmm_file: ByondValue) {
+    setup_panic_handler();
+
+    let dmm_file_str = map_file_path(&dmm_file)?;
+    let file = MapFile::open(&dmm_file_str)?;
+
+    let handle = with_registry(|registry| {
+        let job = match registry.cached_map(&file) {
+            Some(handle) => ParseJob::Cached(handle),
+            None => {
+                let thread = {
+                    let dmm_file_str = dmm_file_str.clone();
+                    let file = file.clone();
+                    std::thread::spawn(move || parse_map_file(&dmm_file_str, &file))
+                };
+                ParseJob::Parsing { file, thread }
+            }
+        };
+
+        let handle = registry.next_parse_handle();
+        registry.pending_parses.insert(
+            handle,
+            PendingParse {
+                dmm_file: dmm_file_str,
+                job,
+            },
+        );
+        Ok(handle)
+    })?;
+
+    Ok(ByondValue::new_num(handle as f32))
+}
 
-    let string = std::fs::read_to_string(path)
+#[byondapi::bind]
+/// Checks on a parse started by `_bapidmm_parse_map_async`. Returns 0 while it's still going, and
+/// 1 once it's done and `map_datum` is filled in, the same as `_bapidmm_parse_map_blocking` would.
+/// Failed parses return null, and their handle can't be polled again.
+pub fn _bapidmm_poll_parse(handle: ByondValue, mut map_datum: ByondValue) {
+    setup_panic_handler();
+    let handle = handle.get_number()? as ParseHandle;
+
+    let pending = with_registry(|registry| {
+        let pending = registry
+            .pending_parses
+            .get(&handle)
+            .ok_or_else(|| eyre!("Bad parse handle {handle:#?}"))?;
+        if let ParseJob::Parsing { thread, .. } = &pending.job {
+            if !thread.is_finished() {
+                return Ok(None);
+            }
+        }
+        Ok(registry.pending_parses.remove(&handle))
+    })?;
+    let Some(pending) = pending else {
+        return Ok(ByondValue::new_num(0.));
+    };
+
+    let map_handle = match pending.job {
+        ParseJob::Cached(map_handle) => map_handle,
+        ParseJob::Parsing { file, thread } => {
+            let map = thread
+                .join()
+                .map_err(|_| eyre!("Parsing {:#?} panicked", pending.dmm_file))??;
+            with_registry(|registry| Ok(registry.insert_parsed_file(map, file)))?
+        }
+    };
+
+    store_parsed_map(
+        ByondValue::new_str(pending.dmm_file)?,
+        &mut map_datum,
+        map_handle,
+    )?;
+
+    Ok(ByondValue::new_num(1.))
+}
+
+#[byondapi::bind]
+/// Gives up on a parse started by `_bapidmm_parse_map_async`, for when DM won't be polling it.
+pub fn _bapidmm_cancel_parse(handle: ByondValue) {
+    setup_panic_handler();
+    let handle = handle.get_number()? as ParseHandle;
+    with_registry(|registry| registry.cancel_parse(handle))?;
+    Ok(ByondValue::new_num(1.))
+}
+
+/// Safe to run on a worker thread.
+fn parse_map_file(dmm_file_str: &str, file: &MapFile) -> eyre::Result<OwnedMap> {
+    let text = std::fs::read_to_string(&file.path)
         .map_err(|e| eyre!("Failed to read {dmm_file_str:#?}: {e:#?}"))?;
+    OwnedMap::parse(map_name(dmm_file_str), text)
+        .map_err(|e| eyre!("Error parsing {dmm_file_str:#?}: {e:#?}"))
+}
 
-    // SAFETY: Only called from main thread.
-    let string = unsafe { get_arena() }.alloc(string);
-
-    let map = dmm_lite::parse_map_multithreaded(
-        path.file_name()
-            .map(|s| s.to_string_lossy())
-            .unwrap_or(std::borrow::Cow::Owned("<unk filename>".to_owned()))
-            .to_string(),
-        string,
-    )
-    .map(|parsed_data| ArenaMap {
-        parsed_data,
-        command_buffers: HashMap::new(),
-    })
-    .map_err(|e| eyre!("Error parsing {dmm_file_str:#?}: {e:#?}"))?;
+fn map_file_path(dmm_file: &ByondValue) -> eyre::Result<String> {
+    if !dmm_file.is_str() {
+        return Err(eyre!("dmm_file was not a string: {dmm_file:#?}"));
+    }
 
+    Ok(dmm_file.get_string()?)
+}
+
+fn map_name(dmm_file_str: &str) -> String {
+    Path::new(dmm_file_str)
+        .file_name()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or(std::borrow::Cow::Owned("<unk filename>".to_owned()))
+        .to_string()
+}
+
+/// Fills in the metadata on `map_datum` and points it at the parsed map, taking over the caller's
+/// reference to it. If that fails, the reference is released instead.
+fn store_parsed_map(
+    dmm_file: ByondValue,
+    map_datum: &mut ByondValue,
+    handle: MapHandle,
+) -> eyre::Result<()> {
+    let stored = write_parsed_map(dmm_file, map_datum, handle);
+    if stored.is_err() {
+        // Nothing points at the map, so nothing else would ever release it
+        let _ = with_registry(|registry| registry.release_map(handle));
+    }
+    stored
+}
+
+fn write_parsed_map(
+    dmm_file: ByondValue,
+    map_datum: &mut ByondValue,
+    handle: MapHandle,
+) -> eyre::Result<()> {
     map_datum.write_var("original_path", &dmm_file)?;
 
-    map_datum.write_var(
-        "map_format",
-        &ByondValue::new_str(if map.parsed_data.0.is_tgm {
-            MAP_TGM
-        } else {
-            MAP_DMM
-        })?,
-    )?;
+    with_registry(|registry| {
+        let parsed_data = registry.get_map(handle)?.parsed_data();
 
-    find_metadata(&mut map_datum, &map.parsed_data)?;
+        map_datum.write_var(
+            "map_format",
+            &ByondValue::new_str(if parsed_data.0.is_tgm {
+                MAP_TGM
+            } else {
+                MAP_DMM
+            })?,
+        )?;
 
-    let index = {
-        let mut maps_list = unsafe { PARSED_MAPS_ARENABASED.borrow_mut() };
-        maps_list.push(map);
-        maps_list.len() - 1
-    };
+        find_metadata(map_datum, parsed_data)
+    })?;
 
-    map_datum.write_var("_internal_index", &ByondValue::new_num(index as f32))?;
+    map_datum.write_var("_internal_index", &ByondValue::new_str(handle.to_string())?)?;
 
-    Ok(ByondValue::new_num(1.0))
+    Ok(())
 }
 
 // Maploader bounds indices
@@ -142,3 +338,28 @@ fn find_metadata(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_in_background() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/test_project/test_map_tgm.dmm"
+        );
+        let file = MapFile::open(path).unwrap();
+        let map = std::thread::spawn(move || parse_map_file(path, &file))
+            .join()
+            .unwrap()
+            .unwrap();
+        let (info, (prefabs, blocks)) = map.parsed_data();
+        assert_eq!(info.name, "test_map_tgm.dmm");
+        assert!(info.is_tgm);
+        assert!(!prefabs.is_empty());
+        assert_eq!(blocks.len(), 10);
+
+        assert!(MapFile::open("does/not/exist.dmm").is_err());
+    }
+}
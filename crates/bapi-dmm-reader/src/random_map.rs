@@ -32,8 +32,8 @@ pub fn bapidmm_generate_automata(
                         let idx = ((i as i32) + x + (y * (limit_y as i32))) as usize;
 
                         if let Some(&value) = map.get(idx) {
-                            let is_border_left = idx % limit_x == 0;
-                            let is_border_right = (idx + 1) % limit_x == 0;
+                            let is_border_left = idx.is_multiple_of(limit_x);
+                            let is_border_right = (idx + 1).is_multiple_of(limit_x);
 
                             if is_border_left && x == -1 {
                                 continue;
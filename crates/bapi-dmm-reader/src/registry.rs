@@ -0,0 +1,335 @@
+//! Everything we keep between calls from BYOND, in one place.
+//!
+//! The registry lives on the main thread, and is borrowed for the length of a call. A call that
+//! comes in while another is still going, like a /New() loading a map while
+//! `_bapidmm_work_commandbuffer` is placing atoms, gets an error instead of a second borrow.
+//! So does a call from any other thread, which would otherwise quietly get an empty registry.
+//! Releasing a map or cancelling a load is the exception, since a datum can be deleted from inside
+//! a load; those are put off until the current call is done.
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::OnceLock,
+    thread::{self, ThreadId},
+};
+
+use eyre::eyre;
+
+use crate::{
+    arena::{ArenaMap, OwnedMap},
+    handle::{BufferHandle, MapHandle, Slots},
+    load::plan::Bounds,
+    parse::{FileStamp, MapFile, ParseJob, PendingParse},
+    ParseHandle,
+};
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = RefCell::new(Registry::default());
+    static DEFERRED: RefCell<Vec<Deferred>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Something that came in while the registry was busy, to be done once it's free.
+#[derive(Debug, Clone, Copy)]
+pub enum Deferred {
+    Release(MapHandle),
+    CancelBuffer(BufferHandle),
+}
+
+/// The thread that first used the registry, which should be BYOND's main thread.
+static OWNER: OnceLock<ThreadId> = OnceLock::new();
+
+/// Fails unless we're on the thread that `owner` was first set to.
+fn check_owner(owner: &OnceLock<ThreadId>) -> eyre::Result<()> {
+    let current = thread::current().id();
+    let owner = *owner.get_or_init(|| current);
+    if owner != current {
+        return Err(eyre!(
+            "bapi-dmm-reader was called from {current:?}, but its maps live on {owner:?}"
+        ));
+    }
+    Ok(())
+}
+
+/// Runs `f` with the registry.
+pub fn with_registry<T>(f: impl FnOnce(&mut Registry) -> eyre::Result<T>) -> eyre::Result<T> {
+    check_owner(&OWNER)?;
+    REGISTRY.with(|registry| {
+        let mut registry = registry.try_borrow_mut().map_err(|_| {
+            eyre!("bapi-dmm-reader was called again while it was still busy, maps can't be parsed or loaded from inside a map load")
+        })?;
+        let ret = f(&mut registry);
+        registry.run_deferred();
+        ret
+    })
+}
+
+/// Runs `f` with the registry, or if it's busy, queues `action` for once it's free and returns None.
+pub fn with_registry_or_defer<T>(
+    action: Deferred,
+    f: impl FnOnce(&mut Registry) -> eyre::Result<T>,
+) -> eyre::Result<Option<T>> {
+    check_owner(&OWNER)?;
+    REGISTRY.with(|registry| {
+        let Ok(mut registry) = registry.try_borrow_mut() else {
+            DEFERRED.with_borrow_mut(|deferred| deferred.push(action));
+            return Ok(None);
+        };
+        let ret = f(&mut registry);
+        registry.run_deferred();
+        ret.map(Some)
+    })
+}
+
+#[derive(Default)]
+pub struct Registry {
+    /// Pointed to by /datum/bapi_parsed_map/_internal_index
+    maps: Slots<ArenaMap>,
+    /// The last parse of each map file, by canonical path. Doesn't hold a reference, entries go
+    /// away with their map.
+    parse_cache: HashMap<PathBuf, (FileStamp, MapHandle)>,
+    pub pending_parses: BTreeMap<ParseHandle, PendingParse>,
+    last_parse_handle: ParseHandle,
+}
+
+impl Registry {
+    /// Stores a newly parsed map, with one reference.
+    pub fn insert_map(&mut self, map: OwnedMap) -> MapHandle {
+        MapHandle(self.maps.insert(map.into()))
+    }
+
+    /// Looks up a map, failing for maps that were released or cleared.
+    pub fn get_map(&mut self, handle: MapHandle) -> eyre::Result<&mut ArenaMap> {
+        self.maps
+            .get_mut(handle.0)
+            .ok_or_else(|| eyre!("Parsed map {handle} is stale, it was released or cleared"))
+    }
+
+    /// Stores a map parsed from `file`, with one reference, and caches it in place of any older
+    /// parse of the same file.
+    pub fn insert_parsed_file(&mut self, map: OwnedMap, file: MapFile) -> MapHandle {
+        let handle = self.insert_map(map);
+        self.parse_cache.insert(file.path, (file.stamp, handle));
+        handle
+    }
+
+    /// The cached parse of `file`, with a new reference, unless the file changed since.
+    pub fn cached_map(&mut self, file: &MapFile) -> Option<MapHandle> {
+        let &(stamp, handle) = self.parse_cache.get(&file.path)?;
+        if stamp != file.stamp {
+            return None;
+        }
+        self.retain_map(handle).ok()?;
+        Some(handle)
+    }
+
+    /// Adds a reference to a map, for another datum sharing it.
+    pub fn retain_map(&mut self, handle: MapHandle) -> eyre::Result<()> {
+        self.get_map(handle)?.refcount += 1;
+        Ok(())
+    }
+
+    /// Drops a reference to a map, freeing it if that was the last one. Returns true if it was freed.
+    pub fn release_map(&mut self, handle: MapHandle) -> eyre::Result<bool> {
+        let map = self.get_map(handle)?;
+        map.refcount -= 1;
+        if map.refcount > 0 {
+            return Ok(false);
+        }
+        self.maps.remove(handle.0);
+        self.parse_cache
+            .retain(|_, &mut (_, cached)| cached != handle);
+        Ok(true)
+    }
+
+    /// Drops a command buffer partway through, returning the region it had loaded so far.
+    pub fn cancel_buffer(&mut self, handle: BufferHandle) -> eyre::Result<Option<Bounds>> {
+        let map = self.get_map(handle.map)?;
+        let loaded_region = map
+            .with_command_buffer(handle.buffer, |buffer| buffer.loaded_region)
+            .ok_or_else(|| eyre!("Command buffer {handle} is stale, it already finished"))?;
+        // Dropping the buffer lets go of the areas, types and turfs it was holding on to
+        map.remove_command_buffer(handle.buffer);
+        Ok(loaded_region)
+    }
+
+    /// Does everything that was put off while the registry was busy. By now some of it may be
+    /// stale, like a buffer that finished anyway, which is fine to skip.
+    fn run_deferred(&mut self) {
+        while let Some(action) = DEFERRED.with_borrow_mut(|deferred| deferred.pop()) {
+            let _ = match action {
+                Deferred::Release(handle) => self.release_map(handle).map(drop),
+                Deferred::CancelBuffer(handle) => self.cancel_buffer(handle).map(drop),
+            };
+        }
+    }
+
+    /// Forgets a parse that hasn't been polled to completion. The worker thread finishes on its
+    /// own, and its result is thrown away.
+    pub fn cancel_parse(&mut self, handle: ParseHandle) -> eyre::Result<()> {
+        let pending = self
+            .pending_parses
+            .remove(&handle)
+            .ok_or_else(|| eyre!("Bad parse handle {handle:#?}"))?;
+        if let ParseJob::Cached(map) = pending.job {
+            self.release_map(map)?;
+        }
+        Ok(())
+    }
+
+    /// Frees every map and pending parse, making every handle stale.
+    pub fn clear_maps(&mut self) {
+        self.maps.clear();
+        self.parse_cache.clear();
+        self.pending_parses.clear();
+    }
+
+    pub fn next_parse_handle(&mut self) -> ParseHandle {
+        self.last_parse_handle += 1;
+        self.last_parse_handle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = r#""a" = (/turf/floor,/area/hall)
+
+(1,1,1) = {"
+a
+"}
+"#;
+
+    fn parse() -> OwnedMap {
+        OwnedMap::parse("test.dmm".to_owned(), MAP.to_owned()).unwrap()
+    }
+
+    #[test]
+    fn test_map_slots() {
+        let mut registry = Registry::default();
+        let first = registry.insert_map(parse());
+        let second = registry.insert_map(parse());
+        assert_ne!(first, second);
+
+        registry.retain_map(first).unwrap();
+        assert!(!registry.release_map(first).unwrap());
+        assert!(registry.get_map(first).is_ok());
+        assert!(registry.release_map(first).unwrap());
+
+        // Stale handles are rejected, even once the slot is reused
+        assert!(registry.get_map(first).is_err());
+        assert!(registry.retain_map(first).is_err());
+        assert!(registry.release_map(first).is_err());
+        let third = registry.insert_map(parse());
+        assert_eq!(third.0.index, first.0.index);
+        assert!(registry.get_map(first).is_err());
+
+        let (info, (prefabs, _)) = registry.get_map(second).unwrap().parsed_data();
+        assert_eq!(info.name, "test.dmm");
+        assert_eq!(prefabs["a"][0].0, "/turf/floor");
+
+        registry.clear_maps();
+        assert!(registry.get_map(second).is_err());
+        assert!(registry.get_map(third).is_err());
+    }
+
+    #[test]
+    fn test_parse_cache() {
+        let path = std::env::temp_dir().join(format!("bapidmm_cache_{}.dmm", std::process::id()));
+        std::fs::write(&path, MAP).unwrap();
+        let open = || MapFile::open(path.to_str().unwrap()).unwrap();
+
+        let mut registry = Registry::default();
+        assert_eq!(registry.cached_map(&open()), None);
+        let first = registry.insert_parsed_file(parse(), open());
+        assert_eq!(registry.cached_map(&open()), Some(first));
+
+        // The file changed, so it has to be parsed again
+        std::fs::write(&path, format!("{MAP}\n")).unwrap();
+        assert_eq!(registry.cached_map(&open()), None);
+        let second = registry.insert_parsed_file(parse(), open());
+        assert_ne!(first, second);
+        assert_eq!(registry.cached_map(&open()), Some(second));
+
+        // The old parse is freed once both its datums let go
+        assert!(!registry.release_map(first).unwrap());
+        assert!(registry.release_map(first).unwrap());
+        assert_eq!(registry.cached_map(&open()), Some(second));
+
+        // The cache doesn't keep a map alive on its own, once all three lookups let go it's gone
+        assert!(!registry.release_map(second).unwrap());
+        assert!(!registry.release_map(second).unwrap());
+        assert!(registry.release_map(second).unwrap());
+        assert_eq!(registry.cached_map(&open()), None);
+
+        let third = registry.insert_parsed_file(parse(), open());
+        assert_eq!(registry.cached_map(&open()), Some(third));
+
+        registry.clear_maps();
+        assert_eq!(registry.cached_map(&open()), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cancel_parse() {
+        let mut registry = Registry::default();
+        let map = registry.insert_map(parse());
+        registry.retain_map(map).unwrap();
+        let handle = registry.next_parse_handle();
+        registry.pending_parses.insert(
+            handle,
+            PendingParse {
+                dmm_file: "test.dmm".to_owned(),
+                job: ParseJob::Cached(map),
+            },
+        );
+
+        registry.cancel_parse(handle).unwrap();
+        assert!(registry.pending_parses.is_empty());
+        assert!(registry.cancel_parse(handle).is_err());
+        // Only the first reference is left
+        assert!(registry.release_map(map).unwrap());
+    }
+
+    #[test]
+    fn test_reentrant_access() {
+        let handles = with_registry(|registry| {
+            let first = registry.next_parse_handle();
+            // Something called back into us
+            assert!(with_registry(|_| Ok(())).is_err());
+            Ok((first, registry.next_parse_handle()))
+        })
+        .unwrap();
+        assert_eq!(handles, (1, 2));
+        assert!(with_registry(|_| Ok(())).is_ok());
+
+        // A datum deleted mid-load lets go of its map once the load's call is done
+        let map = with_registry(|registry| {
+            let map = registry.insert_map(parse());
+            let released = with_registry_or_defer(Deferred::Release(map), |registry| {
+                registry.release_map(map)
+            });
+            assert_eq!(released.unwrap(), None);
+            assert!(registry.get_map(map).is_ok());
+            Ok(map)
+        })
+        .unwrap();
+        assert!(with_registry(|registry| Ok(registry.get_map(map).is_err())).unwrap());
+        assert!(
+            with_registry_or_defer(Deferred::Release(map), |registry| registry.release_map(map))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_thread_owner() {
+        let owner = OnceLock::new();
+        check_owner(&owner).unwrap();
+        check_owner(&owner).unwrap();
+        thread::scope(|scope| {
+            assert!(scope.spawn(|| check_owner(&owner)).join().unwrap().is_err());
+        });
+        assert_eq!(owner.get(), Some(&thread::current().id()));
+    }
+}
@@ -0,0 +1,269 @@
+//! Works out how a map's areas fit together: which areas border which, and whether each area
+//! is one connected piece. Areas split into islands get their power and air split up too.
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+use serde::Serialize;
+
+use crate::{
+    grid::{Coord, TileGrid},
+    prefabs::Prefabs,
+    query::tile_area,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AreaInfo<'s> {
+    pub path: &'s str,
+    pub tiles: usize,
+    /// Groups of tiles connected to each other, largest first. Tiles only connect to their
+    /// four neighbours on the same z-level.
+    pub islands: Vec<Vec<Coord>>,
+}
+
+impl<'s> AreaInfo<'s> {
+    pub fn is_split(&self) -> bool {
+        self.islands.len() > 1
+    }
+
+    /// Tiles with no neighbours in the same area.
+    pub fn isolated_tiles(&self) -> impl Iterator<Item = Coord> + '_ {
+        self.islands
+            .iter()
+            .filter(|island| island.len() == 1)
+            .map(|island| island[0])
+    }
+}
+
+/// Two areas that touch.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AreaBorder<'s> {
+    pub a: &'s str,
+    pub b: &'s str,
+    /// How many pairs of tiles touch across the border
+    pub length: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AreaGraph<'s> {
+    /// Sorted by path
+    pub areas: Vec<AreaInfo<'s>>,
+    /// Sorted by `a`, then `b`, with `a < b`
+    pub borders: Vec<AreaBorder<'s>>,
+}
+
+pub fn analyze_areas<'s>(prefabs: &Prefabs<'s>, grid: &TileGrid<'s>) -> AreaGraph<'s> {
+    let area_at = |coord: Coord| {
+        grid.get(coord)
+            .and_then(|key| prefabs.get(key))
+            .and_then(|prefab_list| tile_area(prefab_list))
+    };
+
+    let mut tiles_by_area: BTreeMap<&'s str, Vec<Coord>> = BTreeMap::new();
+    let mut borders: BTreeMap<(&'s str, &'s str), usize> = BTreeMap::new();
+    for (coord, _) in grid.iter() {
+        let Some(area) = area_at(coord) else {
+            continue;
+        };
+        tiles_by_area.entry(area).or_default().push(coord);
+
+        // Only looking right and up, so each pair is seen once
+        let (x, y, z) = coord;
+        for neighbour in [(x + 1, y, z), (x, y + 1, z)] {
+            match area_at(neighbour) {
+                Some(other) if other != area => {
+                    *borders
+                        .entry((area.min(other), area.max(other)))
+                        .or_default() += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let areas = tiles_by_area
+        .into_iter()
+        .map(|(path, tiles)| AreaInfo {
+            path,
+            tiles: tiles.len(),
+            islands: find_islands(&tiles),
+        })
+        .collect();
+    let borders = borders
+        .into_iter()
+        .map(|((a, b), length)| AreaBorder { a, b, length })
+        .collect();
+
+    AreaGraph { areas, borders }
+}
+
+/// Splits tiles into groups of neighbours, largest first. Each island is in grid order.
+fn find_islands(tiles: &[Coord]) -> Vec<Vec<Coord>> {
+    let mut island_of: HashMap<Coord, Option<usize>> =
+        tiles.iter().map(|coord| (*coord, None)).collect();
+
+    let mut islands = vec![];
+    for start in tiles {
+        if island_of[start].is_some() {
+            continue;
+        }
+
+        let index = islands.len();
+        let mut island = vec![];
+        let mut stack = vec![*start];
+        island_of.insert(*start, Some(index));
+        while let Some(coord) = stack.pop() {
+            island.push(coord);
+            let (x, y, z) = coord;
+            let neighbours = [
+                (x.wrapping_sub(1), y, z),
+                (x + 1, y, z),
+                (x, y.wrapping_sub(1), z),
+                (x, y + 1, z),
+            ];
+            for neighbour in neighbours {
+                if let Some(slot @ None) = island_of.get_mut(&neighbour) {
+                    *slot = Some(index);
+                    stack.push(neighbour);
+                }
+            }
+        }
+        island.sort_by_key(|(x, y, z)| (*z, *y, *x));
+        islands.push(island);
+    }
+
+    // Stable, so equal sized islands stay in grid order
+    islands.sort_by_key(|island| std::cmp::Reverse(island.len()));
+    islands
+}
+
+impl<'s> AreaGraph<'s> {
+    pub fn area(&self, path: &str) -> Option<&AreaInfo<'s>> {
+        self.areas.iter().find(|area| area.path == path)
+    }
+
+    /// Every area bordering `path`.
+    pub fn neighbours<'a>(&'a self, path: &'a str) -> impl Iterator<Item = &'s str> + 'a {
+        self.borders.iter().filter_map(move |border| {
+            if border.a == path {
+                Some(border.b)
+            } else if border.b == path {
+                Some(border.a)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The graph in Graphviz's DOT format, with split areas in red.
+    /// Graphviz source for the graph, named `name`, usually the map's name.
+    pub fn to_dot(&self, name: &str) -> String {
+        let mut dot = format!("graph {name:?} {{\n");
+        for area in &self.areas {
+            let mut label = format!("{}\\n{} tiles", area.path, area.tiles);
+            let mut attributes = String::new();
+            if area.is_split() {
+                write!(label, ", {} islands", area.islands.len()).unwrap();
+                attributes.push_str(", color=red");
+            }
+            writeln!(dot, "\t\"{}\" [label=\"{label}\"{attributes}];", area.path).unwrap();
+        }
+        for border in &self.borders {
+            writeln!(
+                dot,
+                "\t\"{}\" -- \"{}\" [label=\"{}\"];",
+                border.a, border.b, border.length
+            )
+            .unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Area graphs are always valid JSON")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_map_multithreaded;
+
+    const MAP: &str = r#""a" = (/turf/floor,/area/hall)
+"b" = (/turf/floor,/area/bar)
+"c" = (/turf/floor,/area/space)
+
+(1,1,1) = {"
+aaaba
+cccbb
+bcccb
+"}
+"#;
+
+    #[test]
+    fn test_analyze_areas() {
+        let (_, (prefabs, blocks)) = parse_map_multithreaded("test".to_owned(), MAP).unwrap();
+        let grid = TileGrid::from_blocks(&blocks, 1);
+        let graph = analyze_areas(&prefabs, &grid);
+
+        let paths: Vec<_> = graph.areas.iter().map(|area| area.path).collect();
+        assert_eq!(paths, vec!["/area/bar", "/area/hall", "/area/space"]);
+
+        let bar = graph.area("/area/bar").unwrap();
+        assert_eq!(bar.tiles, 5);
+        assert!(bar.is_split());
+        assert_eq!(
+            bar.islands,
+            vec![
+                vec![(5, 1, 1), (4, 2, 1), (5, 2, 1), (4, 3, 1)],
+                vec![(1, 1, 1)]
+            ]
+        );
+        assert_eq!(bar.isolated_tiles().collect::<Vec<_>>(), vec![(1, 1, 1)]);
+
+        let hall = graph.area("/area/hall").unwrap();
+        assert_eq!(hall.islands.len(), 2);
+        assert_eq!(hall.isolated_tiles().collect::<Vec<_>>(), vec![(5, 3, 1)]);
+        assert!(!graph.area("/area/space").unwrap().is_split());
+
+        assert_eq!(
+            graph.borders,
+            vec![
+                AreaBorder {
+                    a: "/area/bar",
+                    b: "/area/hall",
+                    length: 3
+                },
+                AreaBorder {
+                    a: "/area/bar",
+                    b: "/area/space",
+                    length: 5
+                },
+                AreaBorder {
+                    a: "/area/hall",
+                    b: "/area/space",
+                    length: 3
+                },
+            ]
+        );
+        assert_eq!(
+            graph.neighbours("/area/hall").collect::<Vec<_>>(),
+            vec!["/area/bar", "/area/space"]
+        );
+
+        let dot = graph.to_dot("test.dmm");
+        assert!(dot.starts_with("graph \"test.dmm\" {\n"));
+        assert!(dot
+            .contains("\t\"/area/bar\" [label=\"/area/bar\\n5 tiles, 2 islands\", color=red];\n"));
+        assert!(dot.contains("\t\"/area/bar\" -- \"/area/hall\" [label=\"3\"];\n"));
+
+        let json: serde_json::Value = serde_json::from_str(&graph.to_json()).unwrap();
+        assert_eq!(json["borders"][0]["length"], 3);
+        assert_eq!(
+            json["areas"][0]["islands"][1][0],
+            serde_json::json!([1, 1, 1])
+        );
+    }
+}
@@ -59,7 +59,7 @@ pub fn get_block_locations(i: &str) -> Vec<usize> {
     results
 }
 
-pub fn multithreaded_parse_map_locations(i: Located<&str>) -> Result<Vec<Block>, LocatedError> {
+pub fn multithreaded_parse_map_locations(i: Located<&str>) -> Result<Vec<Block<'_>>, LocatedError> {
     let locations = get_block_locations(&i);
 
     locations
@@ -0,0 +1,159 @@
+//! Canonical formatting for maps, so that different editors don't produce noisy diffs.
+//!
+//! On top of what the writer already normalizes (whitespace, number formatting and sorting
+//! prefabs by key), canonical maps have their var edits sorted by name and unnecessary string
+//! escapes removed.
+use std::borrow::Cow;
+
+use typed_arena::Arena;
+
+use crate::{
+    prefabs::{Literal, Prefab},
+    writer::{write_map, WriteError},
+    MapData,
+};
+
+/// Removes escapes that don't do anything, like `\'` inside a `"` string.
+/// BYOND's text macros (`\the`, `\improper`, ...) and real escapes are kept as they are.
+pub fn normalize_string_escapes(raw: &str) -> Cow<'_, str> {
+    if !raw.contains("\\'") {
+        return Cow::Borrowed(raw);
+    }
+
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\'') => out.push('\''),
+            Some(escaped) => {
+                out.push('\\');
+                out.push(escaped);
+            }
+            None => out.push('\\'),
+        }
+    }
+    Cow::Owned(out)
+}
+
+pub(crate) fn canonicalize_literal<'s>(
+    literal: &Literal<'s>,
+    arena: &'s Arena<String>,
+) -> Literal<'s> {
+    match literal {
+        Literal::String(s) => match normalize_string_escapes(s) {
+            Cow::Borrowed(_) => Literal::String(s),
+            Cow::Owned(normalized) => Literal::String(arena.alloc(normalized)),
+        },
+        Literal::List(list) => Literal::List(
+            list.iter()
+                .map(|item| canonicalize_literal(item, arena))
+                .collect(),
+        ),
+        Literal::AssocList(list) => Literal::AssocList(
+            list.iter()
+                .map(|(key, value)| {
+                    (
+                        canonicalize_literal(key, arena),
+                        canonicalize_literal(value, arena),
+                    )
+                })
+                .collect(),
+        ),
+        literal => literal.clone(),
+    }
+}
+
+/// Sorts var edits by name, and normalizes their values.
+pub fn canonicalize_prefab<'s>((path, vars): &Prefab<'s>, arena: &'s Arena<String>) -> Prefab<'s> {
+    let vars = vars.as_ref().map(|vars| {
+        let mut vars: Vec<_> = vars
+            .iter()
+            .map(|(key, value)| (*key, canonicalize_literal(value, arena)))
+            .collect();
+        // Stable, so if a var is somehow edited twice the last one still wins
+        vars.sort_by_key(|(key, _)| *key);
+        vars
+    });
+    (path, vars)
+}
+
+pub fn canonicalize_map<'s>(
+    (prefabs, blocks): &MapData<'s>,
+    arena: &'s Arena<String>,
+) -> MapData<'s> {
+    let prefabs = prefabs
+        .iter()
+        .map(|(key, prefab_list)| {
+            (
+                *key,
+                prefab_list
+                    .iter()
+                    .map(|prefab| canonicalize_prefab(prefab, arena))
+                    .collect(),
+            )
+        })
+        .collect();
+    (prefabs, blocks.clone())
+}
+
+/// Writes a map in canonical form.
+pub fn format_map(is_tgm: bool, data: &MapData) -> Result<String, WriteError> {
+    let arena = Arena::new();
+    write_map(is_tgm, &canonicalize_map(data, &arena))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prefabs::Prefabs;
+
+    #[test]
+    fn test_normalize_string_escapes() {
+        assert!(matches!(
+            normalize_string_escapes(r#"plain \"quoted\" \the"#),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(
+            normalize_string_escapes(r#"it\'s \\ \"fine\" \improper"#),
+            r#"it's \\ \"fine\" \improper"#
+        );
+    }
+
+    #[test]
+    fn test_format_map() {
+        let mut prefabs = Prefabs::new();
+        prefabs.insert(
+            "a",
+            vec![
+                (
+                    "/obj/thing",
+                    Some(vec![
+                        ("name", Literal::String(r#"Bob\'s"#)),
+                        ("dir", Literal::Number(4.)),
+                        (
+                            "list",
+                            Literal::List(vec![Literal::String(r#"\'"#), Literal::Number(-0.)]),
+                        ),
+                    ]),
+                ),
+                ("/turf", None),
+                ("/area", None),
+            ],
+        );
+        let data = (prefabs, vec![((1, 1, 1), vec!["a"])]);
+
+        assert_eq!(
+            format_map(false, &data).unwrap(),
+            r#""a" = (/obj/thing{dir = 4; list = list("'",0); name = "Bob's"},/turf,/area)
+
+(1,1,1) = {"
+a
+"}
+"#
+        );
+    }
+}
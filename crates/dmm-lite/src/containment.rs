@@ -0,0 +1,200 @@
+//! Finds holes in a station's hull: tiles that air could escape from into space.
+//!
+//! Space spreads from every space turf (and from any tile the map leaves empty, which get the
+//! world's turf) into neighbouring tiles, unless their turf or something on them blocks it.
+//! Tiles it reaches that aren't in a space area are exposed.
+use std::collections::VecDeque;
+
+use serde::Deserialize;
+
+use crate::{
+    grid::{Coord, TileGrid},
+    prefabs::{Prefab, Prefabs},
+    query::{is_subtype, tile_area, tile_turf},
+};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExposureOptions {
+    /// Where space spreads from. Subtypes count too, as they do for the other lists.
+    pub space_turfs: Vec<String>,
+    /// Areas that are meant to be open to space, so they aren't reported
+    pub space_areas: Vec<String>,
+    pub blocking_turfs: Vec<String>,
+    /// Anything on a tile that stops space spreading into it, like windows and doors
+    pub blocking_objects: Vec<String>,
+}
+
+impl Default for ExposureOptions {
+    fn default() -> Self {
+        let strings = |paths: &[&str]| paths.iter().map(|path| path.to_string()).collect();
+        ExposureOptions {
+            space_turfs: strings(&["/turf/open/space", "/turf/space"]),
+            space_areas: strings(&["/area/space"]),
+            blocking_turfs: strings(&["/turf/closed", "/turf/simulated/wall"]),
+            blocking_objects: strings(&[
+                "/obj/machinery/door",
+                "/obj/structure/window/fulltile",
+                "/obj/structure/window/reinforced/fulltile",
+                "/obj/effect/spawner/structure/window",
+            ]),
+        }
+    }
+}
+
+fn is_any_subtype(path: &str, parents: &[String]) -> bool {
+    parents.iter().any(|parent| is_subtype(path, parent))
+}
+
+/// A tile that isn't in a space area, but that space can reach.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExposedTile<'s> {
+    pub coord: Coord,
+    pub key: &'s str,
+    pub area: Option<&'s str>,
+}
+
+/// How space treats a tile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tile {
+    Space,
+    Open,
+    Blocking,
+}
+
+fn classify(prefab_list: Option<&Vec<Prefab>>, options: &ExposureOptions) -> Tile {
+    let Some(prefab_list) = prefab_list else {
+        // Undefined keys get nothing loaded, leaving whatever was there before
+        return Tile::Blocking;
+    };
+    match tile_turf(prefab_list) {
+        Some(turf) if is_any_subtype(turf, &options.space_turfs) => return Tile::Space,
+        Some(turf) if is_any_subtype(turf, &options.blocking_turfs) => return Tile::Blocking,
+        _ => {}
+    }
+    if prefab_list
+        .iter()
+        .any(|(path, _)| is_any_subtype(path, &options.blocking_objects))
+    {
+        Tile::Blocking
+    } else {
+        Tile::Open
+    }
+}
+
+/// Every exposed tile, in the order they were reached from space.
+pub fn find_exposed<'s>(
+    prefabs: &Prefabs<'s>,
+    grid: &TileGrid<'s>,
+    options: &ExposureOptions,
+) -> Vec<ExposedTile<'s>> {
+    let (width, height, depth) = grid.size;
+    let index = |(x, y, z): Coord| {
+        (x - grid.origin.0) + (y - grid.origin.1) * width + (z - grid.origin.2) * width * height
+    };
+
+    let mut tiles = Vec::with_capacity(width * height * depth);
+    let mut queue = VecDeque::new();
+    for z in 0..depth {
+        for y in 0..height {
+            for x in 0..width {
+                let coord = (grid.origin.0 + x, grid.origin.1 + y, grid.origin.2 + z);
+                let tile = match grid.get(coord) {
+                    Some(key) => classify(prefabs.get(key), options),
+                    None => Tile::Space,
+                };
+                if tile == Tile::Space {
+                    queue.push_back(coord);
+                }
+                tiles.push(tile);
+            }
+        }
+    }
+
+    let mut reached = vec![false; tiles.len()];
+    for coord in &queue {
+        reached[index(*coord)] = true;
+    }
+
+    let max = grid.max();
+    let mut exposed = vec![];
+    while let Some(coord) = queue.pop_front() {
+        let (x, y, z) = coord;
+        let neighbours = [
+            (x > grid.origin.0).then(|| (x - 1, y, z)),
+            (x < max.0).then(|| (x + 1, y, z)),
+            (y > grid.origin.1).then(|| (x, y - 1, z)),
+            (y < max.1).then(|| (x, y + 1, z)),
+        ];
+        for neighbour in neighbours.into_iter().flatten() {
+            let i = index(neighbour);
+            if reached[i] || tiles[i] == Tile::Blocking {
+                continue;
+            }
+            reached[i] = true;
+            queue.push_back(neighbour);
+
+            let Some(key) = grid.get(neighbour) else {
+                continue;
+            };
+            let area = prefabs.get(key).and_then(|list| tile_area(list));
+            if area.is_some_and(|area| is_any_subtype(area, &options.space_areas)) {
+                continue;
+            }
+            exposed.push(ExposedTile {
+                coord: neighbour,
+                key,
+                area,
+            });
+        }
+    }
+
+    exposed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grid::key_len, parse_map_multithreaded};
+
+    const MAP: &str = r#""a" = (/turf/open/space,/area/space)
+"b" = (/turf/closed/wall,/area/station)
+"c" = (/turf/open/floor,/area/station)
+"d" = (/obj/machinery/door/airlock,/turf/open/floor,/area/station)
+"e" = (/turf/open/floor,/area/space/nearstation)
+
+(1,1,1) = {"
+aaaaaaa
+abbbbba
+abcccba
+abbbbba
+abcccea
+abbbdba
+aaaaaaa
+"}
+"#;
+
+    #[test]
+    fn test_find_exposed() {
+        let (_, (prefabs, blocks)) = parse_map_multithreaded("test".to_owned(), MAP).unwrap();
+        let grid = TileGrid::from_blocks(&blocks, key_len(&prefabs));
+
+        // The lower room has a floor in a space area as its wall
+        let exposed = find_exposed(&prefabs, &grid, &ExposureOptions::default());
+        let mut coords: Vec<_> = exposed.iter().map(|tile| tile.coord).collect();
+        coords.sort();
+        assert_eq!(coords, vec![(3, 3, 1), (4, 3, 1), (5, 3, 1)]);
+        assert!(exposed
+            .iter()
+            .all(|tile| tile.key == "c" && tile.area == Some("/area/station")));
+
+        // Without doors blocking, the door is exposed too
+        let options = ExposureOptions {
+            blocking_objects: vec![],
+            ..Default::default()
+        };
+        let exposed = find_exposed(&prefabs, &grid, &options);
+        assert_eq!(exposed.len(), 4);
+        assert!(exposed.iter().any(|tile| tile.coord == (5, 2, 1)));
+    }
+}
@@ -0,0 +1,306 @@
+//! Reading BYOND's .dmi icons, which are PNGs with their icon states described in a text chunk:
+//! ```text
+//! # BEGIN DMI
+//! version = 4.0
+//!     width = 32
+//!     height = 32
+//! state = "closed"
+//!     dirs = 4
+//!     frames = 1
+//! # END DMI
+//! ```
+use std::{fs::File, io::BufReader, path::Path};
+
+use thiserror::Error;
+
+use crate::render::Image;
+
+#[derive(Debug, Error)]
+pub enum DmiError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Png(#[from] png::DecodingError),
+    #[error("No DMI metadata, this is a plain PNG")]
+    MissingMetadata,
+    #[error("Invalid DMI metadata on line {line}: {message}")]
+    Metadata { line: usize, message: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IconState {
+    pub name: String,
+    pub dirs: u32,
+    pub frames: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DmiMetadata {
+    /// Size of each frame, in pixels
+    pub width: u32,
+    pub height: u32,
+    /// In file order, which is also the order of their frames in the image
+    pub states: Vec<IconState>,
+}
+
+impl DmiMetadata {
+    pub fn parse(text: &str) -> Result<Self, DmiError> {
+        let mut metadata = DmiMetadata {
+            width: 32,
+            height: 32,
+            states: vec![],
+        };
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message: String| DmiError::Metadata {
+                line: index + 1,
+                message,
+            };
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error(format!("Expected key = value, got {line:?}")));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            let number = || {
+                value
+                    .parse::<u32>()
+                    .map_err(|_| error(format!("Expected a number for {key}, got {value:?}")))
+            };
+
+            match key {
+                "state" => {
+                    let name = value
+                        .strip_prefix('"')
+                        .and_then(|value| value.strip_suffix('"'))
+                        .ok_or_else(|| {
+                            error(format!("Expected a quoted state name, got {value:?}"))
+                        })?;
+                    metadata.states.push(IconState {
+                        name: name.to_owned(),
+                        dirs: 1,
+                        frames: 1,
+                    });
+                }
+                "width" => metadata.width = number()?,
+                "height" => metadata.height = number()?,
+                "dirs" | "frames" => {
+                    let Some(state) = metadata.states.last_mut() else {
+                        return Err(error(format!("{key} before any state")));
+                    };
+                    let count = number()?;
+                    if count == 0 {
+                        return Err(error(format!("{key} can't be 0")));
+                    }
+                    if key == "dirs" {
+                        state.dirs = count;
+                    } else {
+                        state.frames = count;
+                    }
+                }
+                // Version, delays, hotspots, and so on
+                _ => {}
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    pub fn state(&self, name: &str) -> Option<&IconState> {
+        self.states.iter().find(|state| state.name == name)
+    }
+}
+
+fn description(info: &png::Info) -> Result<String, DmiError> {
+    for chunk in &info.compressed_latin1_text {
+        if chunk.keyword == "Description" {
+            return Ok(chunk.get_text()?);
+        }
+    }
+    for chunk in &info.uncompressed_latin1_text {
+        if chunk.keyword == "Description" {
+            return Ok(chunk.text.clone());
+        }
+    }
+    Err(DmiError::MissingMetadata)
+}
+
+/// Reads just the metadata of a .dmi, without decoding the image.
+pub fn read_metadata(path: &Path) -> Result<DmiMetadata, DmiError> {
+    let decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    let reader = decoder.read_info()?;
+    DmiMetadata::parse(&description(reader.info())?)
+}
+
+/// The order BYOND stores directions in
+const DIR_ORDER: [u8; 8] = [2, 1, 4, 8, 6, 10, 5, 9];
+
+/// A whole .dmi, for drawing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dmi {
+    pub metadata: DmiMetadata,
+    pub image: Image,
+}
+
+impl Dmi {
+    pub fn read(path: &Path) -> Result<Self, DmiError> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        // Palettes and odd bit depths get turned into 8 bit grey or RGB, with alpha if needed
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let metadata = DmiMetadata::parse(&description(reader.info())?)?;
+
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut buffer)?;
+        let rgba: Vec<u8> = match frame.color_type {
+            png::ColorType::Rgba => buffer[..frame.buffer_size()].to_vec(),
+            png::ColorType::Rgb => buffer[..frame.buffer_size()]
+                .chunks_exact(3)
+                .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buffer[..frame.buffer_size()]
+                .chunks_exact(2)
+                .flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]])
+                .collect(),
+            png::ColorType::Grayscale => buffer[..frame.buffer_size()]
+                .iter()
+                .flat_map(|grey| [*grey, *grey, *grey, 255])
+                .collect(),
+            png::ColorType::Indexed => unreachable!("Palettes are expanded by the decoder"),
+        };
+
+        Ok(Dmi {
+            metadata,
+            image: Image::from_rgba(frame.width, frame.height, &rgba),
+        })
+    }
+
+    /// One frame of one direction of an icon state. Directions the state doesn't have fall back
+    /// to the closest one it does, like in game.
+    pub fn icon(&self, state: &str, dir: u8, frame: u32) -> Option<Image> {
+        let (width, height) = (self.metadata.width, self.metadata.height);
+        let columns = (self.image.width / width.max(1)).max(1);
+
+        let mut index = 0;
+        for icon_state in &self.metadata.states {
+            if icon_state.name != state {
+                index += icon_state.dirs * icon_state.frames;
+                continue;
+            }
+
+            let dir = match icon_state.dirs {
+                1 => 2,
+                // Diagonals show their east or west side
+                4 if dir & 4 != 0 => 4,
+                4 if dir & 8 != 0 => 8,
+                _ => dir,
+            };
+            let dir_index = DIR_ORDER[..icon_state.dirs.min(8) as usize]
+                .iter()
+                .position(|d| *d == dir)
+                .unwrap_or(0) as u32;
+            let index = index
+                + frame.min(icon_state.frames.saturating_sub(1)) * icon_state.dirs
+                + dir_index;
+            return Some(self.image.crop(
+                index % columns * width,
+                index / columns * height,
+                width,
+                height,
+            ));
+        }
+        None
+    }
+}
+
+/// Writes a .dmi with the given metadata, for tests.
+#[cfg(test)]
+pub(crate) fn write_test_dmi(path: &Path, metadata: &str, image: &Image) {
+    let mut encoder = png::Encoder::new(File::create(path).unwrap(), image.width, image.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder
+        .add_ztxt_chunk("Description".to_owned(), metadata.to_owned())
+        .unwrap();
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&image.to_rgba()).unwrap();
+    writer.finish().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::Color;
+
+    const METADATA: &str = "# BEGIN DMI
+version = 4.0
+\twidth = 32
+\theight = 32
+state = \"closed\"
+\tdirs = 4
+\tframes = 1
+state = \"open\"
+\tdirs = 1
+\tframes = 3
+\tdelay = 1,1,1
+# END DMI
+";
+
+    #[test]
+    fn test_parse_metadata() {
+        let metadata = DmiMetadata::parse(METADATA).unwrap();
+        assert_eq!(metadata.width, 32);
+        assert_eq!(metadata.states.len(), 2);
+        assert_eq!(
+            metadata.state("open"),
+            Some(&IconState {
+                name: "open".to_owned(),
+                dirs: 1,
+                frames: 3
+            })
+        );
+        assert_eq!(metadata.state("broken"), None);
+
+        assert!(DmiMetadata::parse("state = closed").is_err());
+        assert!(DmiMetadata::parse("dirs = 4").is_err());
+        assert!(DmiMetadata::parse("state = \"closed\"\nframes = 0").is_err());
+    }
+
+    #[test]
+    fn test_read_metadata() {
+        let path = std::env::temp_dir().join("dmm-lite-test-read-metadata.dmi");
+        write_test_dmi(&path, METADATA, &Image::new(32, 32, Color::TRANSPARENT));
+
+        let metadata = read_metadata(&path).unwrap();
+        assert!(metadata.state("closed").is_some());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_read_dmi() {
+        // Two 2x2 icons per row: a one dir state, then a four dir state with two frames
+        let metadata = "# BEGIN DMI\nversion = 4.0\n\twidth = 2\n\theight = 2\nstate = \"one\"\nstate = \"four\"\n\tdirs = 4\n\tframes = 2\n# END DMI\n";
+        let mut image = Image::new(4, 10, Color::TRANSPARENT);
+        let shades = 9;
+        for index in 0..shades {
+            let shade = (index * 20) as u8;
+            image.fill_rect(index % 2 * 2, index / 2 * 2, 2, 2, Color::rgb(shade, 0, 0));
+        }
+        let path = std::env::temp_dir().join("dmm-lite-test-read-dmi.dmi");
+        write_test_dmi(&path, metadata, &image);
+        let dmi = Dmi::read(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let shade = |icon: Option<Image>| icon.unwrap().get(1, 1).unwrap().r / 20;
+        assert_eq!(shade(dmi.icon("one", 4, 0)), 0);
+        // South, north, east, west
+        assert_eq!(shade(dmi.icon("four", 2, 0)), 1);
+        assert_eq!(shade(dmi.icon("four", 8, 0)), 4);
+        assert_eq!(shade(dmi.icon("four", 8, 1)), 8);
+        // Northeast shows east
+        assert_eq!(shade(dmi.icon("four", 5, 0)), 3);
+        assert!(dmi.icon("missing", 2, 0).is_none());
+    }
+}
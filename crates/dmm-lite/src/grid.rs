@@ -0,0 +1,223 @@
+//! Resolves the coordinate blocks of a map into a dense grid of prefab keys,
+//! so that tiles can be looked up (and moved around) by coordinate.
+use crate::{block::Block, prefabs::Prefabs};
+
+/// An (x, y, z) map coordinate. Like BYOND, the bottom left of the map is (1, 1, 1).
+pub type Coord = (usize, usize, usize);
+
+/// Every key in a map has the same length, so we just look at the first one.
+pub fn key_len(prefabs: &Prefabs) -> usize {
+    prefabs.keys().next().map(|s| s.len()).unwrap_or(0)
+}
+
+/// A dense grid of prefab keys, covering the bounding box of every block in a map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileGrid<'s> {
+    /// The lowest coordinate covered by the grid
+    pub origin: Coord,
+    /// Width, height and depth of the grid, in tiles
+    pub size: Coord,
+    /// Indexed by `x + y * width + z * width * height`, relative to origin.
+    /// `None` for tiles that no block covers.
+    tiles: Vec<Option<&'s str>>,
+}
+
+impl<'s> TileGrid<'s> {
+    /// Creates an empty grid covering `size` tiles starting at `origin`.
+    pub fn new(origin: Coord, size: Coord) -> Self {
+        TileGrid {
+            origin,
+            size,
+            tiles: vec![None; size.0 * size.1 * size.2],
+        }
+    }
+
+    /// Builds the grid from parsed blocks. Blocks are laid out top to bottom, so the
+    /// last line of a block sits at the block's coordinate.
+    /// If blocks overlap, the later block wins, same as BYOND.
+    pub fn from_blocks(blocks: &[Block<'s>], key_len: usize) -> Self {
+        if blocks.is_empty() || key_len == 0 {
+            return TileGrid::new((1, 1, 1), (0, 0, 0));
+        }
+
+        let mut min = (usize::MAX, usize::MAX, usize::MAX);
+        let mut max = (0, 0, 0);
+        for (coord, lines) in blocks {
+            let width = lines.iter().map(|l| l.len() / key_len).max().unwrap_or(0);
+            min.0 = min.0.min(coord.0);
+            min.1 = min.1.min(coord.1);
+            min.2 = min.2.min(coord.2);
+            max.0 = max.0.max(coord.0 + width.max(1) - 1);
+            max.1 = max.1.max(coord.1 + lines.len().max(1) - 1);
+            max.2 = max.2.max(coord.2);
+        }
+
+        let mut grid = TileGrid::new(
+            min,
+            (max.0 - min.0 + 1, max.1 - min.1 + 1, max.2 - min.2 + 1),
+        );
+        for (coord, lines) in blocks {
+            for (y_offset, line) in lines.iter().rev().enumerate() {
+                // Keys are made of ascii letters, so byte offsets are fine here
+                for x_offset in 0..line.len() / key_len {
+                    let key = &line[x_offset * key_len..(x_offset + 1) * key_len];
+                    grid.set((coord.0 + x_offset, coord.1 + y_offset, coord.2), key);
+                }
+            }
+        }
+
+        grid
+    }
+
+    /// The highest coordinate covered by the grid
+    pub fn max(&self) -> Coord {
+        (
+            self.origin.0 + self.size.0.max(1) - 1,
+            self.origin.1 + self.size.1.max(1) - 1,
+            self.origin.2 + self.size.2.max(1) - 1,
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.iter().all(Option::is_none)
+    }
+
+    fn index(&self, coord: Coord) -> Option<usize> {
+        let x = coord.0.checked_sub(self.origin.0)?;
+        let y = coord.1.checked_sub(self.origin.1)?;
+        let z = coord.2.checked_sub(self.origin.2)?;
+        if x >= self.size.0 || y >= self.size.1 || z >= self.size.2 {
+            return None;
+        }
+        Some(x + y * self.size.0 + z * self.size.0 * self.size.1)
+    }
+
+    /// Gets the prefab key at a coordinate, if anything is there.
+    pub fn get(&self, coord: Coord) -> Option<&'s str> {
+        self.index(coord).and_then(|i| self.tiles[i])
+    }
+
+    /// Sets the prefab key at a coordinate. Panics if the coordinate is outside the grid.
+    pub fn set(&mut self, coord: Coord, key: &'s str) {
+        let index = self
+            .index(coord)
+            .unwrap_or_else(|| panic!("{coord:?} is outside of the grid"));
+        self.tiles[index] = Some(key);
+    }
+
+    /// Iterates every occupied tile, x first, then y, then z.
+    pub fn iter(&self) -> impl Iterator<Item = (Coord, &'s str)> + '_ {
+        let (width, height, _) = self.size;
+        self.tiles.iter().enumerate().filter_map(move |(i, key)| {
+            key.map(|key| {
+                (
+                    (
+                        self.origin.0 + i % width,
+                        self.origin.1 + (i / width) % height,
+                        self.origin.2 + i / (width * height),
+                    ),
+                    key,
+                )
+            })
+        })
+    }
+
+    /// Converts the grid back into blocks, TGM style: one block per column.
+    /// Columns with holes in them are split into multiple blocks.
+    pub fn to_blocks(&self) -> Vec<Block<'s>> {
+        let mut blocks = vec![];
+        let max = self.max();
+        for z in self.origin.2..=max.2 {
+            for x in self.origin.0..=max.0 {
+                // Walking top to bottom, collecting runs of occupied tiles
+                let mut run: Vec<&'s str> = vec![];
+                for y in (self.origin.1..=max.1).rev() {
+                    match self.get((x, y, z)) {
+                        Some(key) => run.push(key),
+                        None if !run.is_empty() => {
+                            blocks.push(((x, y + 1, z), std::mem::take(&mut run)));
+                        }
+                        None => {}
+                    }
+                }
+                if !run.is_empty() {
+                    blocks.push(((x, self.origin.1, z), run));
+                }
+            }
+        }
+        blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_blocks() {
+        let blocks = vec![((1, 1, 1), vec!["aaab", "acad"])];
+        let grid = TileGrid::from_blocks(&blocks, 2);
+
+        assert_eq!(grid.origin, (1, 1, 1));
+        assert_eq!(grid.size, (2, 2, 1));
+        assert_eq!(grid.get((1, 2, 1)), Some("aa"));
+        assert_eq!(grid.get((2, 2, 1)), Some("ab"));
+        assert_eq!(grid.get((1, 1, 1)), Some("ac"));
+        assert_eq!(grid.get((2, 1, 1)), Some("ad"));
+        assert_eq!(grid.get((3, 1, 1)), None);
+    }
+
+    #[test]
+    fn test_tgm_blocks() {
+        let blocks = vec![
+            ((1, 1, 1), vec!["a", "b"]),
+            ((2, 1, 1), vec!["c", "d"]),
+            ((1, 1, 2), vec!["e", "f"]),
+        ];
+        let grid = TileGrid::from_blocks(&blocks, 1);
+
+        assert_eq!(grid.size, (2, 2, 2));
+        assert_eq!(grid.get((1, 2, 1)), Some("a"));
+        assert_eq!(grid.get((2, 1, 1)), Some("d"));
+        assert_eq!(grid.get((1, 1, 2)), Some("f"));
+        assert_eq!(grid.get((2, 1, 2)), None);
+        assert_eq!(
+            grid.iter().collect::<Vec<_>>(),
+            vec![
+                ((1, 1, 1), "b"),
+                ((2, 1, 1), "d"),
+                ((1, 2, 1), "a"),
+                ((2, 2, 1), "c"),
+                ((1, 1, 2), "f"),
+                ((1, 2, 2), "e"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_blocks_roundtrip() {
+        let blocks = vec![((3, 2, 1), vec!["ab", "cd", "ef"])];
+        let grid = TileGrid::from_blocks(&blocks, 1);
+
+        assert_eq!(
+            grid.to_blocks(),
+            vec![
+                ((3, 2, 1), vec!["a", "c", "e"]),
+                ((4, 2, 1), vec!["b", "d", "f"])
+            ]
+        );
+        assert_eq!(TileGrid::from_blocks(&grid.to_blocks(), 1), grid);
+    }
+
+    #[test]
+    fn test_to_blocks_with_holes() {
+        let mut grid = TileGrid::new((1, 1, 1), (1, 3, 1));
+        grid.set((1, 3, 1), "a");
+        grid.set((1, 1, 1), "b");
+
+        assert_eq!(
+            grid.to_blocks(),
+            vec![((1, 3, 1), vec!["a"]), ((1, 1, 1), vec!["b"])]
+        );
+    }
+}
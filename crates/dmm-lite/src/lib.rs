@@ -1,8 +1,22 @@
 use miette::{miette, LabeledSpan};
 use winnow::{combinator::opt, error::ContextError, Located, Parser};
 
+pub mod areas;
 pub mod block;
+pub mod canonical;
+pub mod containment;
+pub mod dmi;
+pub mod grid;
+pub mod lint;
+pub mod networks;
 pub mod prefabs;
+pub mod query;
+pub mod render;
+pub mod stats;
+pub mod transform;
+pub mod typetree;
+pub mod update_paths;
+pub mod writer;
 
 #[derive(Debug)]
 pub struct MapInfo {
@@ -34,7 +48,10 @@ impl LocatedError {
 }
 
 pub type MapData<'s> = (prefabs::Prefabs<'s>, Vec<block::Block<'s>>);
-pub fn parse_map_multithreaded(name: String, i: &str) -> Result<(MapInfo, MapData), LocatedError> {
+pub fn parse_map_multithreaded(
+    name: String,
+    i: &str,
+) -> Result<(MapInfo, MapData<'_>), LocatedError> {
     let mut i = Located::new(i);
     // just merk the dmm2tgm header
     let _ = opt(
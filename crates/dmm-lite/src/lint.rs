@@ -0,0 +1,470 @@
+//! Pluggable map lints.
+//!
+//! Each [`LintRule`] looks at a parsed map and reports [`Diagnostic`]s. Rules have a default
+//! severity, which can be changed (or the rule turned off) with a TOML config:
+//! ```toml
+//! [rules]
+//! secondary-turf = "error"
+//! strange-movable = "allow"
+//!
+//! # Rules with options take a table instead
+//! [rules.some-rule]
+//! severity = "warning"
+//! some_option = ["/turf/space"]
+//! ```
+use std::{collections::HashMap, fmt::Display, path::Path, sync::Arc};
+
+use miette::{LabeledSpan, MietteDiagnostic, NamedSource, Report};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    grid::{Coord, TileGrid},
+    prefabs::{Prefab, Prefabs},
+    typetree::TypeTree,
+    writer::compare_keys,
+};
+
+pub mod areas;
+pub mod containment;
+pub mod files;
+pub mod stacking;
+pub mod structure;
+pub mod typetree;
+pub mod vars;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The rule doesn't run at all
+    Allow,
+    #[serde(alias = "warn")]
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Allow => write!(f, "allow"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum LintConfigError {
+    #[error("Invalid lint config: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("Unknown lint rule {0:?}")]
+    UnknownRule(String),
+    #[error("Invalid options for lint rule {rule:?}: {message}")]
+    InvalidOptions { rule: String, message: String },
+}
+
+/// Just severity, or a table with severity and options for the rule.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum RuleConfig {
+    Severity(Severity),
+    Table(toml::Table),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LintConfig {
+    #[serde(default)]
+    rules: HashMap<String, RuleConfig>,
+}
+
+impl LintConfig {
+    pub fn parse(config: &str) -> Result<Self, LintConfigError> {
+        Ok(toml::from_str(config)?)
+    }
+
+    /// The configured severity, if there is one.
+    pub fn severity(&self, rule: &str) -> Result<Option<Severity>, LintConfigError> {
+        match self.rules.get(rule) {
+            None => Ok(None),
+            Some(RuleConfig::Severity(severity)) => Ok(Some(*severity)),
+            Some(RuleConfig::Table(table)) => table
+                .get("severity")
+                .map(|severity| {
+                    severity.clone().try_into().map_err(|e: toml::de::Error| {
+                        LintConfigError::InvalidOptions {
+                            rule: rule.to_owned(),
+                            message: e.message().to_owned(),
+                        }
+                    })
+                })
+                .transpose(),
+        }
+    }
+
+    /// The rule's options table, without `severity`. Empty if the rule has no table.
+    pub fn options(&self, rule: &str) -> toml::Table {
+        match self.rules.get(rule) {
+            Some(RuleConfig::Table(table)) => {
+                let mut table = table.clone();
+                table.remove("severity");
+                table
+            }
+            _ => toml::Table::new(),
+        }
+    }
+}
+
+/// Deserializes a rule's options table, for rules that implement [`LintRule::configure`].
+pub fn parse_options<'de, T: Deserialize<'de>>(
+    rule: &str,
+    options: toml::Table,
+) -> Result<T, LintConfigError> {
+    T::deserialize(options).map_err(|e| LintConfigError::InvalidOptions {
+        rule: rule.to_owned(),
+        message: e.message().to_owned(),
+    })
+}
+
+/// Something wrong with a map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub rule: &'static str,
+    /// Set by the [`Linter`] from the rule's default or configured severity
+    pub severity: Severity,
+    pub message: String,
+    /// The prefab key involved, if any
+    pub key: Option<String>,
+    /// Byte offset and length of what to point at in the map source
+    pub span: Option<(usize, usize)>,
+    pub label: Option<String>,
+    /// Tiles affected
+    pub coords: Vec<Coord>,
+}
+
+impl Diagnostic {
+    pub fn new(rule: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            rule,
+            severity: Severity::Warning,
+            message: message.into(),
+            key: None,
+            span: None,
+            label: None,
+            coords: vec![],
+        }
+    }
+
+    /// Attaches a prefab key, and every tile that uses it.
+    /// Points at the key's definition, unless something more specific gets pointed at.
+    pub fn with_key(mut self, context: &LintContext, key: &str) -> Self {
+        self.key = Some(key.to_owned());
+        self.coords = context.tiles_using(key).to_vec();
+        if self.span.is_none() {
+            self.span = context.span_of(key);
+        }
+        self
+    }
+
+    /// Points at a piece of the map source, like a path in a prefab.
+    pub fn at(mut self, context: &LintContext, text: &str, label: impl Into<String>) -> Self {
+        if let Some(span) = context.span_of(text) {
+            self.span = Some(span);
+            self.label = Some(label.into());
+        }
+        self
+    }
+
+    pub fn with_coords(mut self, coords: Vec<Coord>) -> Self {
+        self.coords = coords;
+        self
+    }
+
+    /// Renders the diagnostic against the map source, for printing.
+    pub fn to_report(&self, name: &str, source: Arc<str>) -> Report {
+        let mut diagnostic = MietteDiagnostic::new(&self.message)
+            .with_code(self.rule)
+            .with_severity(match self.severity {
+                Severity::Error => miette::Severity::Error,
+                _ => miette::Severity::Warning,
+            });
+        if let Some((offset, len)) = self.span {
+            diagnostic = diagnostic.with_label(LabeledSpan::new(self.label.clone(), offset, len));
+        }
+        if !self.coords.is_empty() {
+            const SHOWN: usize = 5;
+            let mut help = self
+                .coords
+                .iter()
+                .take(SHOWN)
+                .map(|coord| format!("{coord:?}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            if self.coords.len() > SHOWN {
+                help.push_str(&format!(" and {} more", self.coords.len() - SHOWN));
+            }
+            diagnostic = diagnostic.with_help(format!("At {help}"));
+        }
+
+        Report::new(diagnostic).with_source_code(NamedSource::new(name, source))
+    }
+}
+
+/// Everything a rule gets to look at.
+pub struct LintContext<'a, 's> {
+    /// The map file, which keys and paths are slices of
+    pub source: &'s str,
+    pub prefabs: &'a Prefabs<'s>,
+    pub grid: &'a TileGrid<'s>,
+    /// For rules that check paths and vars, which do nothing without one
+    pub type_tree: Option<&'a TypeTree>,
+    /// Where file literals are relative to, for rules that check files exist
+    pub project_root: Option<&'a Path>,
+    key_tiles: HashMap<&'s str, Vec<Coord>>,
+}
+
+impl<'a, 's> LintContext<'a, 's> {
+    pub fn new(source: &'s str, prefabs: &'a Prefabs<'s>, grid: &'a TileGrid<'s>) -> Self {
+        let mut key_tiles: HashMap<&'s str, Vec<Coord>> = HashMap::new();
+        for (coord, key) in grid.iter() {
+            key_tiles.entry(key).or_default().push(coord);
+        }
+        LintContext {
+            source,
+            prefabs,
+            grid,
+            type_tree: None,
+            project_root: None,
+            key_tiles,
+        }
+    }
+
+    pub fn with_type_tree(mut self, type_tree: &'a TypeTree) -> Self {
+        self.type_tree = Some(type_tree);
+        self
+    }
+
+    pub fn with_project_root(mut self, project_root: &'a Path) -> Self {
+        self.project_root = Some(project_root);
+        self
+    }
+
+    /// Every tile using a prefab key.
+    pub fn tiles_using(&self, key: &str) -> &[Coord] {
+        self.key_tiles
+            .get(key)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Every prefab, in key order, so diagnostics come out in a stable order.
+    pub fn sorted_prefabs(&self) -> Vec<(&'s str, &'a [Prefab<'s>])> {
+        let mut prefabs: Vec<_> = self
+            .prefabs
+            .iter()
+            .map(|(key, prefab_list)| (*key, prefab_list.as_slice()))
+            .collect();
+        prefabs.sort_by(|a, b| compare_keys(a.0, b.0));
+        prefabs
+    }
+
+    /// Every key actually used by the map, with the tiles using it.
+    pub fn used_keys(&self) -> impl Iterator<Item = (&'s str, &[Coord])> + '_ {
+        self.key_tiles
+            .iter()
+            .map(|(key, coords)| (*key, coords.as_slice()))
+    }
+
+    /// Where a slice of the source is, if it is one. Strings that came from elsewhere
+    /// (like the result of a transform) don't have a span.
+    pub fn span_of(&self, text: &str) -> Option<(usize, usize)> {
+        let start = self.source.as_ptr() as usize;
+        let offset = (text.as_ptr() as usize).checked_sub(start)?;
+        (offset + text.len() <= self.source.len()).then_some((offset, text.len()))
+    }
+}
+
+pub trait LintRule: Send + Sync {
+    /// Kebab case, used in configs and reports
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    fn default_severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    /// Takes the rule's options from the config. Rules without options can ignore this.
+    fn configure(&mut self, options: toml::Table) -> Result<(), LintConfigError> {
+        if let Some(option) = options.keys().next() {
+            return Err(LintConfigError::InvalidOptions {
+                rule: self.name().to_owned(),
+                message: format!("Unknown option {option:?}"),
+            });
+        }
+        Ok(())
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>);
+}
+
+/// Runs a set of rules, each at its configured severity.
+#[derive(Default)]
+pub struct Linter {
+    rules: Vec<(Box<dyn LintRule>, Severity)>,
+}
+
+impl Linter {
+    /// A linter with no rules at all.
+    pub fn new() -> Self {
+        Linter::default()
+    }
+
+    /// Every built-in rule, at its default severity.
+    pub fn builtin_rules() -> Vec<Box<dyn LintRule>> {
+        let mut rules = structure::rules();
+        rules.extend(typetree::rules());
+        rules.extend(files::rules());
+        rules.extend(containment::rules());
+        rules.extend(areas::rules());
+        rules.extend(stacking::rules());
+        rules.extend(vars::rules());
+        rules
+    }
+
+    /// Every built-in rule, configured by `config`.
+    pub fn from_config(config: &LintConfig) -> Result<Self, LintConfigError> {
+        let rules = Linter::builtin_rules();
+        if let Some(unknown) = config
+            .rules
+            .keys()
+            .find(|name| !rules.iter().any(|rule| rule.name() == name.as_str()))
+        {
+            return Err(LintConfigError::UnknownRule(unknown.clone()));
+        }
+
+        let mut linter = Linter::new();
+        for rule in rules {
+            linter.add_rule(rule, config)?;
+        }
+        Ok(linter)
+    }
+
+    pub fn add_rule(
+        &mut self,
+        mut rule: Box<dyn LintRule>,
+        config: &LintConfig,
+    ) -> Result<(), LintConfigError> {
+        let severity = config
+            .severity(rule.name())?
+            .unwrap_or(rule.default_severity());
+        rule.configure(config.options(rule.name()))?;
+        self.rules.push((rule, severity));
+        Ok(())
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = (&dyn LintRule, Severity)> {
+        self.rules
+            .iter()
+            .map(|(rule, severity)| (rule.as_ref(), *severity))
+    }
+
+    /// Runs every enabled rule. Diagnostics come out in rule order.
+    pub fn run(&self, context: &LintContext) -> Vec<Diagnostic> {
+        let mut all = vec![];
+        for (rule, severity) in &self.rules {
+            if *severity == Severity::Allow {
+                continue;
+            }
+
+            let mut diagnostics = vec![];
+            rule.check(context, &mut diagnostics);
+            all.extend(diagnostics.into_iter().map(|mut diagnostic| {
+                diagnostic.severity = *severity;
+                diagnostic
+            }));
+        }
+        all
+    }
+}
+
+/// Runs `rules` on `map`, configured by `config`, for testing rules.
+#[cfg(test)]
+pub(crate) fn lint_map(map: &str, rules: Vec<Box<dyn LintRule>>, config: &str) -> Vec<Diagnostic> {
+    lint_map_with(map, rules, config, None, None)
+}
+
+/// [`lint_map`], with a type tree and project root for the rules that need them.
+#[cfg(test)]
+pub(crate) fn lint_map_with(
+    map: &str,
+    rules: Vec<Box<dyn LintRule>>,
+    config: &str,
+    type_tree: Option<&TypeTree>,
+    project_root: Option<&Path>,
+) -> Vec<Diagnostic> {
+    let (_, (prefabs, blocks)) = crate::parse_map_multithreaded("test".to_owned(), map).unwrap();
+    let grid = TileGrid::from_blocks(&blocks, 1);
+    let mut context = LintContext::new(map, &prefabs, &grid);
+    context.type_tree = type_tree;
+    context.project_root = project_root;
+
+    let config = LintConfig::parse(config).unwrap();
+    let mut linter = Linter::new();
+    for rule in rules {
+        linter.add_rule(rule, &config).unwrap();
+    }
+    linter.run(&context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config = LintConfig::parse(
+            r#"
+            [rules]
+            secondary-turf = "error"
+            strange-movable = "allow"
+
+            [rules.undefined-key]
+            severity = "warn"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.severity("secondary-turf").unwrap(),
+            Some(Severity::Error)
+        );
+        assert_eq!(
+            config.severity("undefined-key").unwrap(),
+            Some(Severity::Warning)
+        );
+        assert_eq!(config.severity("turf-area-ending").unwrap(), None);
+        assert!(config.options("undefined-key").is_empty());
+
+        let linter = Linter::from_config(&config).unwrap();
+        assert!(linter.rules().any(
+            |(rule, severity)| rule.name() == "strange-movable" && severity == Severity::Allow
+        ));
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let config = LintConfig::parse("[rules]\nnot-a-rule = \"error\"").unwrap();
+        assert!(matches!(
+            Linter::from_config(&config),
+            Err(LintConfigError::UnknownRule(rule)) if rule == "not-a-rule"
+        ));
+
+        let config = LintConfig::parse("[rules.secondary-turf]\nbogus = 1").unwrap();
+        assert!(matches!(
+            Linter::from_config(&config),
+            Err(LintConfigError::InvalidOptions { .. })
+        ));
+
+        assert!(LintConfig::parse("[rules]\nsecondary-turf = \"loud\"").is_err());
+    }
+}
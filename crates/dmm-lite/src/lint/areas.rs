@@ -0,0 +1,105 @@
+//! Reports areas split into pieces, see [`crate::areas`].
+use serde::Deserialize;
+
+use crate::{
+    areas::analyze_areas,
+    lint::{parse_options, Diagnostic, LintConfigError, LintContext, LintRule},
+    query::is_subtype,
+};
+
+pub fn rules() -> Vec<Box<dyn LintRule>> {
+    vec![Box::new(SplitArea::default())]
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SplitAreaOptions {
+    /// Areas that are fine in pieces, and their subtypes
+    pub ignore_areas: Vec<String>,
+}
+
+impl Default for SplitAreaOptions {
+    fn default() -> Self {
+        SplitAreaOptions {
+            ignore_areas: vec!["/area/space".to_owned()],
+        }
+    }
+}
+
+/// Each piece of a split area still shares one APC and one set of air alarms.
+#[derive(Default)]
+pub struct SplitArea {
+    options: SplitAreaOptions,
+}
+
+impl LintRule for SplitArea {
+    fn name(&self) -> &'static str {
+        "split-area"
+    }
+
+    fn description(&self) -> &'static str {
+        "Every area should be one connected piece"
+    }
+
+    fn configure(&mut self, options: toml::Table) -> Result<(), LintConfigError> {
+        self.options = parse_options(self.name(), options)?;
+        Ok(())
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        let graph = analyze_areas(context.prefabs, context.grid);
+        for area in &graph.areas {
+            if !area.is_split()
+                || self
+                    .options
+                    .ignore_areas
+                    .iter()
+                    .any(|ignored| is_subtype(area.path, ignored))
+            {
+                continue;
+            }
+
+            // Everything except the main piece
+            let stray: Vec<_> = area.islands[1..].iter().flatten().copied().collect();
+            let mut diagnostic = Diagnostic::new(
+                self.name(),
+                format!(
+                    "{} is split into {} pieces, {} tiles are away from the main one",
+                    area.path,
+                    area.islands.len(),
+                    stray.len()
+                ),
+            );
+            if let Some(key) = context.grid.get(stray[0]) {
+                diagnostic = diagnostic.at(context, key, "Not connected to the rest of the area");
+            }
+            diagnostics.push(diagnostic.with_coords(stray));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::lint_map;
+
+    const MAP: &str = r#""a" = (/turf/floor,/area/hall)
+"c" = (/turf/floor,/area/space)
+
+(1,1,1) = {"
+aacaca
+cccccc
+"}
+"#;
+
+    #[test]
+    fn test_split_area() {
+        let diagnostics = lint_map(MAP, rules(), "");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "/area/hall is split into 3 pieces, 2 tiles are away from the main one"
+        );
+        assert_eq!(diagnostics[0].coords, vec![(4, 2, 1), (6, 2, 1)]);
+    }
+}
@@ -0,0 +1,107 @@
+//! Reports holes in the hull, see [`crate::containment`].
+use std::collections::BTreeMap;
+
+use crate::{
+    containment::{find_exposed, ExposureOptions},
+    lint::{parse_options, Diagnostic, LintConfigError, LintContext, LintRule, Severity},
+};
+
+pub fn rules() -> Vec<Box<dyn LintRule>> {
+    vec![Box::new(SpaceExposure::default())]
+}
+
+/// Floors that space can reach lose their air as soon as the round starts.
+/// Takes the same options as [`ExposureOptions`].
+#[derive(Default)]
+pub struct SpaceExposure {
+    options: ExposureOptions,
+}
+
+impl LintRule for SpaceExposure {
+    fn name(&self) -> &'static str {
+        "space-exposure"
+    }
+
+    fn description(&self) -> &'static str {
+        "Tiles outside of space areas must be sealed off from space"
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn configure(&mut self, options: toml::Table) -> Result<(), LintConfigError> {
+        self.options = parse_options(self.name(), options)?;
+        Ok(())
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        let exposed = find_exposed(context.prefabs, context.grid, &self.options);
+
+        // One diagnostic per area, there can be a lot of tiles
+        let mut by_area = BTreeMap::new();
+        for tile in exposed {
+            by_area
+                .entry(tile.area.unwrap_or("(no area)"))
+                .or_insert_with(Vec::new)
+                .push(tile);
+        }
+
+        for (area, mut tiles) in by_area {
+            tiles.sort_by_key(|tile| (tile.coord.2, tile.coord.1, tile.coord.0));
+            let first = &tiles[0];
+            diagnostics.push(
+                Diagnostic::new(
+                    self.name(),
+                    format!(
+                        "{} tiles of {area} are exposed to space, starting at {:?}",
+                        tiles.len(),
+                        first.coord
+                    ),
+                )
+                .at(context, first.key, "Exposed to space")
+                .with_coords(tiles.iter().map(|tile| tile.coord).collect()),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::{lint_map, LintConfig, Linter};
+
+    const MAP: &str = r#""a" = (/turf/space,/area/space)
+"b" = (/turf/simulated/wall,/area/station)
+"c" = (/turf/simulated/floor,/area/station)
+"d" = (/obj/structure/grille,/turf/simulated/floor,/area/station)
+
+(1,1,1) = {"
+aaaaa
+abbba
+abcba
+abcda
+abbba
+"}
+"#;
+
+    #[test]
+    fn test_space_exposure() {
+        let diagnostics = lint_map(MAP, vec![Box::new(SpaceExposure::default())], "");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].coords, vec![(3, 2, 1), (4, 2, 1), (3, 3, 1)]);
+        let (offset, len) = diagnostics[0].span.unwrap();
+        assert_eq!(&MAP[offset..offset + len], "c");
+
+        let config = r#"
+            [rules.space-exposure]
+            blocking_objects = ["/obj/structure/grille"]
+            "#;
+        assert!(lint_map(MAP, vec![Box::new(SpaceExposure::default())], config).is_empty());
+
+        let config = LintConfig::parse("[rules.space-exposure]\nblocking = []").unwrap();
+        assert!(Linter::new()
+            .add_rule(Box::new(SpaceExposure::default()), &config)
+            .is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! Checks the files a map refers to exist, when the linter knows where the project is.
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    canonical::normalize_string_escapes,
+    dmi::{read_metadata, DmiMetadata},
+    lint::{Diagnostic, LintContext, LintRule, Severity},
+    prefabs::Literal,
+};
+
+pub fn rules() -> Vec<Box<dyn LintRule>> {
+    vec![Box::new(MissingFile), Box::new(MissingIconState)]
+}
+
+/// Where a file literal points, relative to the project root.
+pub fn resolve_file(root: &Path, file: &str) -> PathBuf {
+    root.join(&*normalize_string_escapes(file))
+}
+
+/// Every file literal in a value, including inside lists.
+fn collect_files<'s>(literal: &Literal<'s>, files: &mut Vec<&'s str>) {
+    match literal {
+        Literal::File(file) => files.push(file),
+        Literal::List(list) => list.iter().for_each(|item| collect_files(item, files)),
+        Literal::AssocList(list) => {
+            for (key, value) in list {
+                collect_files(key, files);
+                collect_files(value, files);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Missing files turn into a null when loading, so the atom just shows up blank.
+pub struct MissingFile;
+
+impl LintRule for MissingFile {
+    fn name(&self) -> &'static str {
+        "missing-file"
+    }
+
+    fn description(&self) -> &'static str {
+        "Every file a map refers to must exist in the project"
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        let Some(root) = context.project_root else {
+            return;
+        };
+
+        let mut exists: HashMap<&str, bool> = HashMap::new();
+        for (key, prefab_list) in context.sorted_prefabs() {
+            for (path, vars) in prefab_list {
+                for (var, value) in vars.iter().flatten() {
+                    let mut files = vec![];
+                    collect_files(value, &mut files);
+                    for file in files {
+                        let found = *exists
+                            .entry(file)
+                            .or_insert_with(|| resolve_file(root, file).is_file());
+                        if found {
+                            continue;
+                        }
+                        diagnostics.push(
+                            Diagnostic::new(
+                                self.name(),
+                                format!(
+                                    "Prefab {key:?} sets {var} on {path} to '{file}', which doesn't exist"
+                                ),
+                            )
+                            .at(context, file, "Missing")
+                            .with_key(context, key),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// An icon_state the icon doesn't have shows up blank, like a missing icon does.
+/// Only prefabs that edit both the icon and the icon_state can be checked.
+pub struct MissingIconState;
+
+impl LintRule for MissingIconState {
+    fn name(&self) -> &'static str {
+        "missing-icon-state"
+    }
+
+    fn description(&self) -> &'static str {
+        "Edited icon_states must exist in the edited icon"
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        let Some(root) = context.project_root else {
+            return;
+        };
+
+        // None for icons that are missing (which missing-file reports) or unreadable
+        let mut icons: HashMap<&str, Option<DmiMetadata>> = HashMap::new();
+        for (key, prefab_list) in context.sorted_prefabs() {
+            for (path, vars) in prefab_list {
+                let Some(vars) = vars else {
+                    continue;
+                };
+                let var = |name| vars.iter().rev().find(|(var, _)| *var == name);
+                let (Some((_, Literal::File(icon))), Some((_, Literal::String(icon_state)))) =
+                    (var("icon"), var("icon_state"))
+                else {
+                    continue;
+                };
+                if !icon.ends_with(".dmi") {
+                    continue;
+                }
+
+                let metadata = match icons.entry(icon) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let file = resolve_file(root, icon);
+                        let metadata = match file.is_file().then(|| read_metadata(&file)) {
+                            Some(Ok(metadata)) => Some(metadata),
+                            Some(Err(e)) => {
+                                diagnostics.push(
+                                    Diagnostic::new(
+                                        self.name(),
+                                        format!("Couldn't read '{icon}': {e}"),
+                                    )
+                                    .at(context, icon, "Unreadable")
+                                    .with_key(context, key),
+                                );
+                                None
+                            }
+                            None => None,
+                        };
+                        entry.insert(metadata)
+                    }
+                };
+                let Some(metadata) = metadata else {
+                    continue;
+                };
+
+                let state = normalize_string_escapes(icon_state);
+                if metadata.state(&state).is_none() {
+                    diagnostics.push(
+                        Diagnostic::new(
+                            self.name(),
+                            format!(
+                                "Prefab {key:?} gives {path} the icon_state \"{icon_state}\", which '{icon}' doesn't have"
+                            ),
+                        )
+                        .at(context, icon_state, "Missing")
+                        .with_key(context, key),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dmi::write_test_dmi,
+        lint::{lint_map, lint_map_with},
+        render::{Color, Image},
+    };
+
+    const MAP: &str = r#""a" = (/obj/item{icon = 'icons/items.dmi'; icon_state = "gun"},/turf/floor{icon = 'icons/turfs.dmi'},/area/station)
+"b" = (/obj/item{icon = 'icons/items.dmi'; icon_state = "lasr"; sounds = list('sound/pew.ogg' = 1)},/turf/floor,/area/station)
+
+(1,1,1) = {"
+aba
+"}
+"#;
+
+    #[test]
+    fn test_file_rules() {
+        let root = std::env::temp_dir().join("dmm-lite-test-file-rules");
+        std::fs::create_dir_all(root.join("icons")).unwrap();
+        write_test_dmi(
+            &root.join("icons/items.dmi"),
+            "# BEGIN DMI\nversion = 4.0\nstate = \"gun\"\n# END DMI\n",
+            &Image::new(32, 32, Color::TRANSPARENT),
+        );
+
+        // Nothing to check against
+        assert!(lint_map(MAP, rules(), "").is_empty());
+
+        let diagnostics = lint_map_with(MAP, rules(), "", None, Some(&root));
+        let summary: Vec<_> = diagnostics
+            .iter()
+            .map(|d| {
+                let (offset, len) = d.span.unwrap();
+                (d.rule, d.key.as_deref(), &MAP[offset..offset + len])
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("missing-file", Some("a"), "icons/turfs.dmi"),
+                ("missing-file", Some("b"), "sound/pew.ogg"),
+                ("missing-icon-state", Some("b"), "lasr"),
+            ]
+        );
+        assert_eq!(diagnostics[0].coords, vec![(1, 1, 1), (3, 1, 1)]);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
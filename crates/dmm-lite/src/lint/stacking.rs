@@ -0,0 +1,217 @@
+//! Objects stacked on one tile by accident, like two grilles or two APCs.
+use serde::Deserialize;
+
+use crate::{
+    lint::{parse_options, Diagnostic, LintConfigError, LintContext, LintRule},
+    prefabs::Prefab,
+    query::is_subtype,
+};
+
+pub fn rules() -> Vec<Box<dyn LintRule>> {
+    vec![
+        Box::new(DuplicateObject::default()),
+        Box::new(StackedObjects::default()),
+    ]
+}
+
+/// The objects in a prefab, without its turf and area.
+fn movables<'p, 's>(prefab_list: &'p [Prefab<'s>]) -> &'p [Prefab<'s>] {
+    &prefab_list[..prefab_list.len().saturating_sub(2)]
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DuplicateObjectOptions {
+    /// Types, and their subtypes, that are often stacked on purpose
+    pub ignore: Vec<String>,
+}
+
+impl Default for DuplicateObjectOptions {
+    fn default() -> Self {
+        DuplicateObjectOptions {
+            ignore: [
+                "/obj/item",
+                "/obj/effect/spawner",
+                "/obj/effect/decal/cleanable",
+                "/mob",
+            ]
+            .into_iter()
+            .map(str::to_owned)
+            .collect(),
+        }
+    }
+}
+
+/// The same object with the same var edits, more than once on a tile.
+#[derive(Default)]
+pub struct DuplicateObject {
+    options: DuplicateObjectOptions,
+}
+
+impl LintRule for DuplicateObject {
+    fn name(&self) -> &'static str {
+        "duplicate-object"
+    }
+
+    fn description(&self) -> &'static str {
+        "Prefabs shouldn't have the exact same object more than once"
+    }
+
+    fn configure(&mut self, options: toml::Table) -> Result<(), LintConfigError> {
+        self.options = parse_options(self.name(), options)?;
+        Ok(())
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        for (key, prefab_list) in context.sorted_prefabs() {
+            let movables = movables(prefab_list);
+            for (index, prefab) in movables.iter().enumerate() {
+                // Reported once, at the second copy
+                let earlier = movables[..index].iter().filter(|other| *other == prefab);
+                if earlier.count() != 1
+                    || self
+                        .options
+                        .ignore
+                        .iter()
+                        .any(|ignored| is_subtype(prefab.0, ignored))
+                {
+                    continue;
+                }
+                let copies = movables.iter().filter(|other| *other == prefab).count();
+                diagnostics.push(
+                    Diagnostic::new(
+                        self.name(),
+                        format!("Prefab {key:?} has {} {copies} times", prefab.0),
+                    )
+                    .at(context, prefab.0, "Duplicate")
+                    .with_key(context, key),
+                );
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StackedObjectsOptions {
+    /// Groups of types, and their subtypes, that a tile should only have one of between them
+    pub one_per_tile: Vec<Vec<String>>,
+}
+
+impl Default for StackedObjectsOptions {
+    fn default() -> Self {
+        StackedObjectsOptions {
+            one_per_tile: [
+                "/obj/structure/grille",
+                "/obj/machinery/power/apc",
+                "/obj/machinery/door/airlock",
+            ]
+            .into_iter()
+            .map(|path| vec![path.to_owned()])
+            .collect(),
+        }
+    }
+}
+
+/// Different objects that still shouldn't share a tile, like two kinds of airlock.
+#[derive(Default)]
+pub struct StackedObjects {
+    options: StackedObjectsOptions,
+}
+
+impl LintRule for StackedObjects {
+    fn name(&self) -> &'static str {
+        "stacked-objects"
+    }
+
+    fn description(&self) -> &'static str {
+        "Tiles should have at most one of each one-per-tile group"
+    }
+
+    fn configure(&mut self, options: toml::Table) -> Result<(), LintConfigError> {
+        self.options = parse_options(self.name(), options)?;
+        Ok(())
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        for (key, prefab_list) in context.sorted_prefabs() {
+            for group in &self.options.one_per_tile {
+                let stacked: Vec<&Prefab> = movables(prefab_list)
+                    .iter()
+                    .filter(|(path, _)| group.iter().any(|parent| is_subtype(path, parent)))
+                    .collect();
+                // Exact copies are left to duplicate-object
+                if stacked.iter().all(|prefab| *prefab == stacked[0]) {
+                    continue;
+                }
+                let paths: Vec<&str> = stacked.iter().map(|(path, _)| *path).collect();
+                diagnostics.push(
+                    Diagnostic::new(
+                        self.name(),
+                        format!(
+                            "Prefab {key:?} has {} of {}, which should be one per tile: {}",
+                            paths.len(),
+                            group.join(" or "),
+                            paths.join(", ")
+                        ),
+                    )
+                    .at(context, paths[1], "Stacked")
+                    .with_key(context, key),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::lint_map;
+
+    const MAP: &str = r#""a" = (/obj/structure/grille,/obj/structure/grille,/obj/structure/grille,/turf/floor,/area/hall)
+"b" = (/obj/cable{icon_state = "1-2"},/obj/cable{icon_state = "4-8"},/obj/item/pen,/obj/item/pen,/turf/floor,/area/hall)
+"c" = (/obj/machinery/door/airlock,/obj/structure/grille,/obj/machinery/door/airlock/glass,/obj/machinery/door/firedoor,/turf/floor,/area/hall)
+"d" = (/obj/machinery/door/firedoor,/obj/structure/mineral_door,/turf/floor,/area/hall)
+
+(1,1,1) = {"
+abcd
+a
+"}
+"#;
+
+    #[test]
+    fn test_stacking() {
+        let diagnostics = lint_map(MAP, rules(), "");
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Prefab \"a\" has /obj/structure/grille 3 times",
+                "Prefab \"c\" has 2 of /obj/machinery/door/airlock, which should be one per tile: /obj/machinery/door/airlock, /obj/machinery/door/airlock/glass",
+            ]
+        );
+        assert_eq!(diagnostics[0].coords, vec![(1, 1, 1), (1, 2, 1)]);
+
+        let diagnostics = lint_map(
+            MAP,
+            rules(),
+            r#"
+            [rules.duplicate-object]
+            ignore = []
+
+            [rules.stacked-objects]
+            one_per_tile = [["/obj/machinery/door", "/obj/structure/mineral_door"]]
+            "#,
+        );
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Prefab \"a\" has /obj/structure/grille 3 times",
+                "Prefab \"b\" has /obj/item/pen 2 times",
+                "Prefab \"c\" has 3 of /obj/machinery/door or /obj/structure/mineral_door, which should be one per tile: /obj/machinery/door/airlock, /obj/machinery/door/airlock/glass, /obj/machinery/door/firedoor",
+                "Prefab \"d\" has 2 of /obj/machinery/door or /obj/structure/mineral_door, which should be one per tile: /obj/machinery/door/firedoor, /obj/structure/mineral_door",
+            ]
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! The structural rules the loader relies on, which it can only complain about at load time.
+use crate::{
+    lint::{Diagnostic, LintContext, LintRule, Severity},
+    query::is_subtype,
+};
+
+pub fn rules() -> Vec<Box<dyn LintRule>> {
+    vec![
+        Box::new(TurfAreaEnding),
+        Box::new(SecondaryTurf),
+        Box::new(StrangeMovable),
+        Box::new(UndefinedKey),
+    ]
+}
+
+/// Every prefab has to end with one turf and then one area, or the loader skips the tile.
+pub struct TurfAreaEnding;
+
+impl LintRule for TurfAreaEnding {
+    fn name(&self) -> &'static str {
+        "turf-area-ending"
+    }
+
+    fn description(&self) -> &'static str {
+        "Prefabs must end with one /turf, then one /area"
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        for (key, prefab_list) in context.sorted_prefabs() {
+            let diagnostic = match prefab_list[..] {
+                [.., _, (area, _)] if !is_subtype(area, "/area") => Diagnostic::new(
+                    self.name(),
+                    format!("Prefab {key:?} ends in {area}, instead of an area"),
+                )
+                .at(context, area, "Should be an area"),
+                [.., (turf, _), _] if !is_subtype(turf, "/turf") => Diagnostic::new(
+                    self.name(),
+                    format!("Prefab {key:?} has {turf} before its area, instead of a turf"),
+                )
+                .at(context, turf, "Should be a turf"),
+                [_, _, ..] => continue,
+                _ => Diagnostic::new(
+                    self.name(),
+                    format!("Prefab {key:?} is too short to have both a turf and an area"),
+                ),
+            };
+            diagnostics.push(diagnostic.with_key(context, key));
+        }
+    }
+}
+
+/// Only the last turf in a prefab gets loaded.
+pub struct SecondaryTurf;
+
+impl LintRule for SecondaryTurf {
+    fn name(&self) -> &'static str {
+        "secondary-turf"
+    }
+
+    fn description(&self) -> &'static str {
+        "Prefabs can only have one turf, the loader ignores any others"
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        for (key, prefab_list) in context.sorted_prefabs() {
+            let movables = &prefab_list[..prefab_list.len().saturating_sub(2)];
+            for (path, _) in movables {
+                if is_subtype(path, "/turf") {
+                    diagnostics.push(
+                        Diagnostic::new(
+                            self.name(),
+                            format!("Prefab {key:?} has a secondary turf, {path}"),
+                        )
+                        .at(context, path, "Ignored when loading")
+                        .with_key(context, key),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Anything before the turf gets created as a movable, which only makes sense for objs and mobs.
+pub struct StrangeMovable;
+
+impl LintRule for StrangeMovable {
+    fn name(&self) -> &'static str {
+        "strange-movable"
+    }
+
+    fn description(&self) -> &'static str {
+        "Everything before the turf in a prefab should be an /obj or a /mob"
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        for (key, prefab_list) in context.sorted_prefabs() {
+            let movables = &prefab_list[..prefab_list.len().saturating_sub(2)];
+            for (path, _) in movables {
+                if !is_subtype(path, "/obj")
+                    && !is_subtype(path, "/mob")
+                    && !is_subtype(path, "/turf")
+                {
+                    diagnostics.push(
+                        Diagnostic::new(
+                            self.name(),
+                            format!("Prefab {key:?} has {path}, which isn't an /obj or a /mob"),
+                        )
+                        .at(context, path, "Created as a movable")
+                        .with_key(context, key),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Keys used by the map that no prefab defines. The loader leaves those tiles alone.
+pub struct UndefinedKey;
+
+impl LintRule for UndefinedKey {
+    fn name(&self) -> &'static str {
+        "undefined-key"
+    }
+
+    fn description(&self) -> &'static str {
+        "Every key used by the map must be defined"
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        let mut undefined: Vec<_> = context
+            .used_keys()
+            .filter(|(key, _)| !context.prefabs.contains_key(key))
+            .collect();
+        undefined.sort_by_key(|(_, coords)| coords[0]);
+
+        for (key, coords) in undefined {
+            // Point at the first use, there's no definition to point at
+            let first_use = context.grid.get(coords[0]).unwrap_or(key);
+            diagnostics.push(
+                Diagnostic::new(self.name(), format!("Key {key:?} is never defined"))
+                    .at(context, first_use, "Used here")
+                    .with_coords(coords.to_vec()),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::lint_map;
+
+    const MAP: &str = r#""a" = (/obj/item,/turf/floor,/area/station)
+"b" = (/turf/floor,/obj/item)
+"c" = (/obj/item,/turf/floor)
+"d" = (/turf/wall,/area/space,/turf/floor,/area/station)
+"e" = (/area/station)
+
+(1,1,1) = {"
+abcdef
+"}
+"#;
+
+    #[test]
+    fn test_structure_rules() {
+        let diagnostics = lint_map(MAP, rules(), "");
+        let summary: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.rule, d.severity, d.key.as_deref()))
+            .collect();
+
+        assert_eq!(
+            summary,
+            vec![
+                ("turf-area-ending", Severity::Error, Some("b")),
+                ("turf-area-ending", Severity::Error, Some("c")),
+                ("turf-area-ending", Severity::Error, Some("e")),
+                ("secondary-turf", Severity::Warning, Some("d")),
+                ("strange-movable", Severity::Warning, Some("d")),
+                ("undefined-key", Severity::Error, None),
+            ]
+        );
+
+        // Points at the offending path
+        let (offset, len) = diagnostics[0].span.unwrap();
+        assert_eq!(&MAP[offset..offset + len], "/obj/item");
+        assert_eq!(diagnostics[0].coords, vec![(2, 1, 1)]);
+
+        let (offset, len) = diagnostics[5].span.unwrap();
+        assert_eq!(&MAP[offset..offset + len], "f");
+        assert_eq!(offset, MAP.find("abcdef").unwrap() + 5);
+        assert_eq!(diagnostics[5].coords, vec![(6, 1, 1)]);
+    }
+}
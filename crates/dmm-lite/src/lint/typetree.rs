@@ -0,0 +1,145 @@
+//! Checks paths and var names against a [`TypeTree`](crate::typetree::TypeTree), when the
+//! linter has one.
+use crate::lint::{Diagnostic, LintContext, LintRule, Severity};
+
+pub fn rules() -> Vec<Box<dyn LintRule>> {
+    vec![Box::new(UnknownType), Box::new(UnknownVar)]
+}
+
+fn did_you_mean(suggestion: Option<&str>) -> String {
+    match suggestion {
+        Some(suggestion) => format!("Did you mean {suggestion}?"),
+        None => "Unknown".to_owned(),
+    }
+}
+
+/// Types that don't exist come back null from text2path when loading, and nothing gets created.
+pub struct UnknownType;
+
+impl LintRule for UnknownType {
+    fn name(&self) -> &'static str {
+        "unknown-type"
+    }
+
+    fn description(&self) -> &'static str {
+        "Every path must exist in the type tree"
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        let Some(tree) = context.type_tree else {
+            return;
+        };
+
+        for (key, prefab_list) in context.sorted_prefabs() {
+            for (path, _) in prefab_list {
+                if tree.contains(path) {
+                    continue;
+                }
+                diagnostics.push(
+                    Diagnostic::new(
+                        self.name(),
+                        format!("Prefab {key:?} has {path}, which doesn't exist"),
+                    )
+                    .at(context, path, did_you_mean(tree.suggest_type(path)))
+                    .with_key(context, key),
+                );
+            }
+        }
+    }
+}
+
+/// Var edits for vars the type doesn't have are a runtime error when loading.
+pub struct UnknownVar;
+
+impl LintRule for UnknownVar {
+    fn name(&self) -> &'static str {
+        "unknown-var"
+    }
+
+    fn description(&self) -> &'static str {
+        "Every edited var must exist on its type"
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        let Some(tree) = context.type_tree else {
+            return;
+        };
+
+        for (key, prefab_list) in context.sorted_prefabs() {
+            for (path, vars) in prefab_list {
+                // Unknown types are reported by unknown-type already
+                if !tree.contains(path) {
+                    continue;
+                }
+                for (var, _) in vars.iter().flatten() {
+                    if tree.has_var(path, var) {
+                        continue;
+                    }
+                    diagnostics.push(
+                        Diagnostic::new(
+                            self.name(),
+                            format!(
+                                "Prefab {key:?} edits {var} on {path}, which doesn't have that var"
+                            ),
+                        )
+                        .at(context, var, did_you_mean(tree.suggest_var(path, var)))
+                        .with_key(context, key),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lint::{lint_map, lint_map_with},
+        typetree::TypeTree,
+    };
+
+    const MAP: &str = r#""a" = (/obj/item/gun/energy/lasr{name = "pew"},/turf/floor,/area/station)
+"b" = (/obj/machinery/door/airlock{req_acess = list(1); name = "Door"},/turf/floor,/area/station)
+
+(1,1,1) = {"
+ab
+"}
+"#;
+
+    #[test]
+    fn test_type_tree_rules() {
+        let tree = TypeTree::from_text(
+            "/atom -> name\n/obj/item/gun/energy/laser\n/obj/machinery/door -> req_access\n/obj/machinery/door/airlock\n/turf/floor\n/area/station",
+        )
+        .unwrap();
+
+        // Nothing to check against
+        assert!(lint_map(MAP, rules(), "").is_empty());
+
+        let diagnostics = lint_map_with(MAP, rules(), "", Some(&tree), None);
+        assert_eq!(diagnostics.len(), 2);
+
+        assert_eq!(diagnostics[0].rule, "unknown-type");
+        assert_eq!(
+            diagnostics[0].label.as_deref(),
+            Some("Did you mean /obj/item/gun/energy/laser?")
+        );
+
+        assert_eq!(diagnostics[1].rule, "unknown-var");
+        assert_eq!(
+            diagnostics[1].label.as_deref(),
+            Some("Did you mean req_access?")
+        );
+        let (offset, len) = diagnostics[1].span.unwrap();
+        assert_eq!(&MAP[offset..offset + len], "req_acess");
+    }
+}
@@ -0,0 +1,370 @@
+//! Var edits that are pointless, can't work, or didn't parse the way they look.
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use typed_arena::Arena;
+use winnow::{Located, Parser};
+
+use crate::{
+    canonical::canonicalize_literal,
+    lint::{parse_options, Diagnostic, LintConfigError, LintContext, LintRule, Severity},
+    prefabs::{parse_literal, parse_var_list_key, split_var_list, Literal},
+    query::is_subtype,
+    writer::write_literal,
+};
+
+pub fn rules() -> Vec<Box<dyn LintRule>> {
+    vec![
+        Box::new(RedundantVarEdit::default()),
+        Box::new(UnmappableVar),
+        Box::new(FallbackValue),
+        Box::new(UnterminatedString),
+    ]
+}
+
+/// Vars that describe the object itself, rather than settings on it.
+const UNMAPPABLE_VARS: &[&str] = &["type", "parent_type", "loc", "contents", "vars"];
+
+/// A value written out canonically, so values that mean the same thing compare equal.
+fn canonical_text(literal: &Literal) -> String {
+    let arena = Arena::new();
+    let mut out = String::new();
+    // Writing to a String can't fail
+    let _ = write_literal(&mut out, &canonicalize_literal(literal, &arena));
+    out
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedundantVarEditOptions {
+    /// Default values by type then var, as numbers, booleans or DM text like `'"airlock"'`
+    pub defaults: BTreeMap<String, BTreeMap<String, toml::Value>>,
+}
+
+/// Setting a var to its default does nothing. Needs defaults configured, since maps don't have them.
+#[derive(Default)]
+pub struct RedundantVarEdit {
+    /// Canonical text of each default, by type then var
+    defaults: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl RedundantVarEdit {
+    fn default_value(&self, path: &str, var: &str) -> Option<&str> {
+        self.defaults
+            .iter()
+            .filter(|(parent, _)| is_subtype(path, parent))
+            .filter_map(|(parent, vars)| Some((parent, vars.get(var)?)))
+            .max_by_key(|(parent, _)| parent.len())
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl LintRule for RedundantVarEdit {
+    fn name(&self) -> &'static str {
+        "redundant-var-edit"
+    }
+
+    fn description(&self) -> &'static str {
+        "Var edits shouldn't set a var to its default value"
+    }
+
+    fn configure(&mut self, options: toml::Table) -> Result<(), LintConfigError> {
+        let options: RedundantVarEditOptions = parse_options(self.name(), options)?;
+        let name = self.name();
+        let invalid =
+            |path: &str, var: &str, value: &toml::Value| LintConfigError::InvalidOptions {
+                rule: name.to_owned(),
+                message: format!("Default for {var} on {path} isn't a DM value: {value}"),
+            };
+
+        self.defaults.clear();
+        for (path, vars) in &options.defaults {
+            for (var, value) in vars {
+                let literal = match value {
+                    toml::Value::Integer(n) => Literal::Number(*n as f32),
+                    toml::Value::Float(n) => Literal::Number(*n as f32),
+                    toml::Value::Boolean(b) => Literal::Number(*b as u8 as f32),
+                    toml::Value::String(text) => {
+                        let mut input = Located::new(text.trim());
+                        match parse_literal.parse_next(&mut input) {
+                            Ok(Literal::Fallback(_)) | Err(_) => {
+                                return Err(invalid(path, var, value))
+                            }
+                            Ok(_) if !input.is_empty() => return Err(invalid(path, var, value)),
+                            Ok(literal) => literal,
+                        }
+                    }
+                    _ => return Err(invalid(path, var, value)),
+                };
+                self.defaults
+                    .entry(path.clone())
+                    .or_default()
+                    .insert(var.clone(), canonical_text(&literal));
+            }
+        }
+        Ok(())
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        if self.defaults.is_empty() {
+            return;
+        }
+
+        for (key, prefab_list) in context.sorted_prefabs() {
+            for (path, vars) in prefab_list {
+                for (var, value) in vars.iter().flatten() {
+                    let Some(default) = self.default_value(path, var) else {
+                        continue;
+                    };
+                    if canonical_text(value) != default {
+                        continue;
+                    }
+                    diagnostics.push(
+                        Diagnostic::new(
+                            self.name(),
+                            format!(
+                                "Prefab {key:?} sets {var} on {path} to {default}, which is already its default"
+                            ),
+                        )
+                        .at(context, var, "Does nothing")
+                        .with_key(context, key),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Vars like `type` and `contents` can't be set from a map, the loader just errors.
+pub struct UnmappableVar;
+
+impl LintRule for UnmappableVar {
+    fn name(&self) -> &'static str {
+        "unmappable-var"
+    }
+
+    fn description(&self) -> &'static str {
+        "Var edits can't set vars like type, loc, contents or vars"
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        for (key, prefab_list) in context.sorted_prefabs() {
+            for (path, vars) in prefab_list {
+                for (var, _) in vars.iter().flatten() {
+                    if !UNMAPPABLE_VARS.contains(var) {
+                        continue;
+                    }
+                    diagnostics.push(
+                        Diagnostic::new(
+                            self.name(),
+                            format!("Prefab {key:?} edits {var} on {path}, which can't be set"),
+                        )
+                        .at(context, var, "Not mappable")
+                        .with_key(context, key),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// A var list in the map source, split up the way the parser does it.
+struct VarList<'s> {
+    /// Offset of the `{` in the source
+    start: usize,
+    vars: Vec<Located<&'s str>>,
+    /// Strings the parser had to recover from, as offsets from the `{`
+    unterminated: Vec<usize>,
+}
+
+/// The var list after a path, if it has one.
+fn var_list_after<'s>(context: &LintContext<'_, 's>, path: &str) -> Option<VarList<'s>> {
+    let (offset, len) = context.span_of(path)?;
+    let start = offset + len;
+    let source = context.source;
+    if !source[start..].starts_with('{') {
+        return None;
+    }
+    let (vars, unterminated) = split_var_list(&mut Located::new(&source[start..])).ok()?;
+    Some(VarList {
+        start,
+        vars,
+        unterminated,
+    })
+}
+
+/// Values the parser kept as raw text. Bare keys in assoc lists are fine, they're just strings.
+fn collect_fallbacks<'s>(literal: &Literal<'s>, fallbacks: &mut Vec<&'s str>) {
+    match literal {
+        Literal::Fallback(text) => fallbacks.push(text),
+        Literal::List(list) => list
+            .iter()
+            .for_each(|item| collect_fallbacks(item, fallbacks)),
+        Literal::AssocList(list) => list
+            .iter()
+            .for_each(|(_, value)| collect_fallbacks(value, fallbacks)),
+        _ => {}
+    }
+}
+
+/// Values the parser kept as text, which the loader can't do anything sensible with.
+pub struct FallbackValue;
+
+impl LintRule for FallbackValue {
+    fn name(&self) -> &'static str {
+        "fallback-value"
+    }
+
+    fn description(&self) -> &'static str {
+        "Var edits should be numbers, strings, paths, files, null or lists"
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        for (key, prefab_list) in context.sorted_prefabs() {
+            for (path, _) in prefab_list {
+                // Parsed again from the source, to see if any of the value was left over
+                let Some(var_list) = var_list_after(context, path) else {
+                    continue;
+                };
+                for mut text in var_list.vars {
+                    let Ok((var, value)) =
+                        (parse_var_list_key, parse_literal).parse_next(&mut text)
+                    else {
+                        continue;
+                    };
+
+                    let mut fallbacks = vec![];
+                    collect_fallbacks(&value, &mut fallbacks);
+                    if fallbacks.is_empty() && !text.is_empty() {
+                        let rest: &str = &text;
+                        diagnostics.push(
+                            Diagnostic::new(
+                                self.name(),
+                                format!(
+                                    "Prefab {key:?} sets {var} on {path} to a value that's only partly understood, `{rest}` is dropped"
+                                ),
+                            )
+                            .at(context, rest, "Dropped")
+                            .with_key(context, key),
+                        );
+                    }
+                    for fallback in fallbacks {
+                        diagnostics.push(
+                            Diagnostic::new(
+                                self.name(),
+                                format!(
+                                    "Prefab {key:?} sets {var} on {path} to `{fallback}`, which isn't a value the loader understands"
+                                ),
+                            )
+                            .at(context, fallback, "Not a value")
+                            .with_key(context, key),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A string cut off by a line break, which loses its whole var edit.
+pub struct UnterminatedString;
+
+impl LintRule for UnterminatedString {
+    fn name(&self) -> &'static str {
+        "unterminated-string"
+    }
+
+    fn description(&self) -> &'static str {
+        "Strings in var edits must end on the line they start on"
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check(&self, context: &LintContext, diagnostics: &mut Vec<Diagnostic>) {
+        for (key, prefab_list) in context.sorted_prefabs() {
+            for (path, _) in prefab_list {
+                let Some(var_list) = var_list_after(context, path) else {
+                    continue;
+                };
+                let start = var_list.start;
+                for quote in var_list.unterminated {
+                    diagnostics.push(
+                        Diagnostic::new(
+                            self.name(),
+                            format!(
+                                "Prefab {key:?} has an unterminated string on {path}, the var it's in is dropped"
+                            ),
+                        )
+                        .at(
+                            context,
+                            &context.source[start + quote..start + quote + 1],
+                            "Start of unterminated string",
+                        )
+                        .with_key(context, key),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::lint_map;
+
+    const MAP: &str = r#""a" = (/obj/machinery/door/airlock{name = "airlock"; density = 1},/turf/floor,/area/hall)
+"b" = (/obj/machinery/door/airlock/glass{name = "glass airlock"; loc = null},/turf/floor,/area/hall)
+"c" = (/obj/item{req_access = list(a = 1, b = foo); dir = EAST; network = list("ss13")},/turf/floor,/area/hall)
+"d" = (/obj/item{name = "broken
+	desc = "fine"},/turf/floor,/area/hall)
+
+(1,1,1) = {"
+abcd
+"}
+"#;
+
+    #[test]
+    fn test_vars() {
+        let diagnostics = lint_map(
+            MAP,
+            rules(),
+            r#"
+            [rules.redundant-var-edit.defaults."/obj/machinery/door"]
+            name = '"airlock"'
+            density = true
+
+            [rules.redundant-var-edit.defaults."/obj/machinery/door/airlock/glass"]
+            name = '"glass airlock"'
+            "#,
+        );
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Prefab \"a\" sets name on /obj/machinery/door/airlock to \"airlock\", which is already its default",
+                "Prefab \"a\" sets density on /obj/machinery/door/airlock to 1, which is already its default",
+                "Prefab \"b\" sets name on /obj/machinery/door/airlock/glass to \"glass airlock\", which is already its default",
+                "Prefab \"b\" edits loc on /obj/machinery/door/airlock/glass, which can't be set",
+                "Prefab \"c\" sets req_access on /obj/item to a value that's only partly understood, `b = foo)` is dropped",
+                "Prefab \"c\" sets dir on /obj/item to `EAST`, which isn't a value the loader understands",
+                "Prefab \"d\" has an unterminated string on /obj/item, the var it's in is dropped",
+            ]
+        );
+        let unterminated = diagnostics.last().unwrap().span.unwrap();
+        assert!(MAP[unterminated.0..].starts_with("\"broken\n"));
+
+        // Without defaults, only the other rules say anything
+        assert_eq!(lint_map(MAP, rules(), "").len(), 4);
+
+        let mut rule = RedundantVarEdit::default();
+        let options = toml::from_str("[defaults.\"/obj\"]\nname = \"not quoted\"").unwrap();
+        assert!(rule.configure(options).is_err());
+    }
+}
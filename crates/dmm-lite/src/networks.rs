@@ -0,0 +1,498 @@
+//! Traces networks of connected objects, like cables and pipes, across a map.
+//!
+//! What makes up each network is described by a rules file, because every codebase does it a
+//! little differently:
+//! ```toml
+//! [[network]]
+//! name = "power"
+//!
+//! # Objects that make up the network, and how to work out which ways they connect
+//! [[network.carrier]]
+//! type = "/obj/structure/cable"
+//! connections = "icon-state"
+//!
+//! # Objects that have to be attached to the network to work
+//! [[network.machine]]
+//! type = "/obj/machinery/power/apc"
+//! connections = "center"
+//! ```
+//!
+//! The ways of reading connections are:
+//! - `icon-state`: cable style icon states like `"1-2"`, where each number is a direction and 0
+//!   is a knot in the middle of the tile
+//! - `straight`: simple pipes, towards `dir` and away from it, or both halves of a diagonal `dir`
+//! - `single`: only towards `dir`, like vents and connectors
+//! - `manifold`: every way except `dir`
+//! - `all`: smart cables and pipes, which join whatever is around them
+//! - `center`: only to other things on the same tile with a knot or `all`
+//!
+//! Objects on neighbouring tiles are connected when they point at each other. If the type
+//! matches more than one rule, the most specific rule wins.
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    grid::{Coord, TileGrid},
+    prefabs::{Literal, Prefab, Prefabs},
+    query::is_subtype,
+    transform::{EAST, NORTH, SOUTH, WEST},
+};
+
+/// Not a BYOND direction, it stands in for the middle of the tile
+pub const CENTER: u8 = 16;
+const CARDINALS: [u8; 4] = [NORTH, SOUTH, EAST, WEST];
+
+#[derive(Debug, Error)]
+#[error("Invalid network rules: {0}")]
+pub struct NetworkRulesError(#[from] toml::de::Error);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConnectionMode {
+    IconState,
+    Straight,
+    Single,
+    Manifold,
+    All,
+    Center,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeRule {
+    #[serde(rename = "type")]
+    pub path: String,
+    pub connections: ConnectionMode,
+    /// The type's `dir` when the map doesn't edit it
+    #[serde(default = "default_dir")]
+    pub default_dir: u8,
+    /// The type's `icon_state` when the map doesn't edit it
+    #[serde(default)]
+    pub default_icon_state: Option<String>,
+}
+
+fn default_dir() -> u8 {
+    SOUTH
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkRule {
+    pub name: String,
+    #[serde(default, rename = "carrier")]
+    pub carriers: Vec<NodeRule>,
+    #[serde(default, rename = "machine")]
+    pub machines: Vec<NodeRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkRules {
+    #[serde(default, rename = "network")]
+    pub networks: Vec<NetworkRule>,
+}
+
+impl NetworkRules {
+    pub fn parse(rules: &str) -> Result<Self, NetworkRulesError> {
+        Ok(toml::from_str(rules)?)
+    }
+}
+
+pub fn opposite(dir: u8) -> u8 {
+    match dir {
+        NORTH => SOUTH,
+        SOUTH => NORTH,
+        EAST => WEST,
+        WEST => EAST,
+        other => other,
+    }
+}
+
+pub fn dir_name(dir: u8) -> &'static str {
+    match dir {
+        NORTH => "north",
+        SOUTH => "south",
+        EAST => "east",
+        WEST => "west",
+        CENTER => "center",
+        _ => "unknown",
+    }
+}
+
+fn step((x, y, z): Coord, dir: u8) -> Option<Coord> {
+    match dir {
+        NORTH => Some((x, y + 1, z)),
+        SOUTH => Some((x, y.checked_sub(1)?, z)),
+        EAST => Some((x + 1, y, z)),
+        WEST => Some((x.checked_sub(1)?, y, z)),
+        _ => None,
+    }
+}
+
+/// Directions out of a BYOND `dir`, splitting diagonals into their two halves.
+fn dir_components(dir: u8) -> u8 {
+    dir & (NORTH | SOUTH | EAST | WEST)
+}
+
+/// Which ways an object connects, as a mask of directions and [`CENTER`].
+fn connections((_, vars): &Prefab, rule: &NodeRule) -> u8 {
+    let var = |name: &str| {
+        vars.iter()
+            .flatten()
+            .rev()
+            .find(|(var, _)| *var == name)
+            .map(|(_, value)| value)
+    };
+    let dir = match var("dir") {
+        Some(Literal::Number(dir)) => *dir as u8,
+        _ => rule.default_dir,
+    };
+
+    match rule.connections {
+        ConnectionMode::IconState => {
+            let icon_state = match var("icon_state") {
+                Some(Literal::String(icon_state)) => Some(*icon_state),
+                _ => rule.default_icon_state.as_deref(),
+            };
+            icon_state
+                .unwrap_or_default()
+                .split('-')
+                .filter_map(|part| part.parse::<u8>().ok())
+                .map(|dir| match dir {
+                    0 => CENTER,
+                    dir if CARDINALS.contains(&dir) => dir,
+                    // Diagonal cables don't connect to anything we can see
+                    _ => 0,
+                })
+                .fold(0, |mask, dir| mask | dir)
+        }
+        ConnectionMode::Straight if CARDINALS.contains(&dir) => dir | opposite(dir),
+        ConnectionMode::Straight => dir_components(dir),
+        ConnectionMode::Single => dir_components(dir),
+        ConnectionMode::Manifold => (NORTH | SOUTH | EAST | WEST) & !dir_components(dir),
+        ConnectionMode::All => NORTH | SOUTH | EAST | WEST | CENTER,
+        ConnectionMode::Center => CENTER,
+    }
+}
+
+/// Something on the map that's part of a network.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkNode<'s> {
+    pub coord: Coord,
+    pub key: &'s str,
+    pub path: &'s str,
+    pub machine: bool,
+    /// Directions and [`CENTER`]
+    pub connections: u8,
+    /// Joins whatever's around it, so it never has loose ends
+    pub flexible: bool,
+}
+
+/// A connection that doesn't lead anywhere.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DanglingEnd {
+    /// Index into [`NetworkReport::nodes`]
+    pub node: usize,
+    pub dir: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkReport<'s> {
+    pub name: String,
+    pub nodes: Vec<NetworkNode<'s>>,
+    /// Groups of connected nodes, as indexes into `nodes`, largest first
+    pub networks: Vec<Vec<usize>>,
+    pub dangling: Vec<DanglingEnd>,
+}
+
+impl<'s> NetworkReport<'s> {
+    /// Networks made of nothing but carriers, which don't do anything.
+    /// Only meaningful when the rule has machines.
+    pub fn isolated_networks(&self) -> impl Iterator<Item = &[usize]> + '_ {
+        self.networks
+            .iter()
+            .filter(|network| !network.iter().any(|node| self.nodes[*node].machine))
+            .map(Vec::as_slice)
+    }
+
+    /// Machines that aren't connected to any carrier.
+    pub fn unattached_machines(&self) -> impl Iterator<Item = &NetworkNode<'s>> + '_ {
+        self.networks
+            .iter()
+            .filter(|network| network.iter().all(|node| self.nodes[*node].machine))
+            .flatten()
+            .map(|node| &self.nodes[*node])
+    }
+}
+
+fn find_root(parents: &mut [usize], mut node: usize) -> usize {
+    while parents[node] != node {
+        parents[node] = parents[parents[node]];
+        node = parents[node];
+    }
+    node
+}
+
+/// Traces one kind of network over the whole map.
+pub fn trace_network<'s>(
+    prefabs: &Prefabs<'s>,
+    grid: &TileGrid<'s>,
+    rule: &NetworkRule,
+) -> NetworkReport<'s> {
+    // Most specific rule first
+    let mut node_rules: Vec<(&NodeRule, bool)> = rule
+        .carriers
+        .iter()
+        .map(|node_rule| (node_rule, false))
+        .chain(rule.machines.iter().map(|node_rule| (node_rule, true)))
+        .collect();
+    node_rules.sort_by_key(|(node_rule, _)| std::cmp::Reverse(node_rule.path.len()));
+
+    let mut nodes = vec![];
+    let mut by_tile: HashMap<Coord, Vec<usize>> = HashMap::new();
+    for (coord, key) in grid.iter() {
+        let Some(prefab_list) = prefabs.get(key) else {
+            continue;
+        };
+        for prefab in prefab_list {
+            let Some((node_rule, machine)) = node_rules
+                .iter()
+                .find(|(node_rule, _)| is_subtype(prefab.0, &node_rule.path))
+            else {
+                continue;
+            };
+            by_tile.entry(coord).or_default().push(nodes.len());
+            nodes.push(NetworkNode {
+                coord,
+                key,
+                path: prefab.0,
+                machine: *machine,
+                connections: connections(prefab, node_rule),
+                flexible: node_rule.connections == ConnectionMode::All,
+            });
+        }
+    }
+
+    let mut parents: Vec<usize> = (0..nodes.len()).collect();
+    let mut dangling = vec![];
+    for (index, node) in nodes.iter().enumerate() {
+        let same_tile = &by_tile[&node.coord];
+        if node.connections & CENTER != 0 {
+            for other in same_tile {
+                if *other != index && nodes[*other].connections & CENTER != 0 {
+                    let (a, b) = (
+                        find_root(&mut parents, index),
+                        find_root(&mut parents, *other),
+                    );
+                    parents[a] = b;
+                }
+            }
+        }
+
+        for dir in CARDINALS {
+            if node.connections & dir == 0 {
+                continue;
+            }
+            let neighbours = step(node.coord, dir).and_then(|coord| by_tile.get(&coord));
+            let mut connected = false;
+            for other in neighbours.into_iter().flatten() {
+                if nodes[*other].connections & opposite(dir) != 0 {
+                    connected = true;
+                    let (a, b) = (
+                        find_root(&mut parents, index),
+                        find_root(&mut parents, *other),
+                    );
+                    parents[a] = b;
+                }
+            }
+            if !connected && !node.flexible {
+                dangling.push(DanglingEnd { node: index, dir });
+            }
+        }
+    }
+
+    let mut networks: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for index in 0..nodes.len() {
+        let root = find_root(&mut parents, index);
+        networks.entry(root).or_default().push(index);
+    }
+    let mut networks: Vec<_> = networks.into_values().collect();
+    // Stable, and nodes are in grid order, so networks of the same size stay in grid order
+    networks.sort_by_key(|network| std::cmp::Reverse(network.len()));
+
+    NetworkReport {
+        name: rule.name.clone(),
+        nodes,
+        networks,
+        dangling,
+    }
+}
+
+/// Traces every network in the rules.
+pub fn trace_networks<'s>(
+    prefabs: &Prefabs<'s>,
+    grid: &TileGrid<'s>,
+    rules: &NetworkRules,
+) -> Vec<NetworkReport<'s>> {
+    rules
+        .networks
+        .iter()
+        .map(|rule| trace_network(prefabs, grid, rule))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grid::key_len, parse_map_multithreaded};
+
+    const RULES: &str = r#"
+[[network]]
+name = "power"
+
+[[network.carrier]]
+type = "/obj/structure/cable"
+connections = "icon-state"
+
+[[network.machine]]
+type = "/obj/machinery/power"
+connections = "center"
+
+[[network]]
+name = "air"
+
+[[network.carrier]]
+type = "/obj/machinery/atmospherics/pipe/simple"
+connections = "straight"
+
+[[network.carrier]]
+type = "/obj/machinery/atmospherics/pipe/manifold"
+connections = "manifold"
+
+[[network.machine]]
+type = "/obj/machinery/atmospherics/unary"
+connections = "single"
+"#;
+
+    // The APC is wired up, the SMES isn't, and the cable in the top right goes nowhere.
+    // The pipe runs from a vent, through a manifold, into a dead end.
+    const MAP: &str = r#""a" = (/turf/floor,/area/station)
+"b" = (/obj/machinery/power/apc,/obj/structure/cable{icon_state = "0-4"},/turf/floor,/area/station)
+"c" = (/obj/structure/cable{icon_state = "4-8"},/turf/floor,/area/station)
+"d" = (/obj/structure/cable{icon_state = "0-8"},/obj/machinery/power/terminal,/turf/floor,/area/station)
+"e" = (/obj/machinery/power/smes,/turf/floor,/area/station)
+"f" = (/obj/structure/cable{icon_state = "1-2"},/turf/floor,/area/station)
+"g" = (/obj/machinery/atmospherics/unary/vent{dir = 4},/turf/floor,/area/station)
+"h" = (/obj/machinery/atmospherics/pipe/simple{dir = 4},/turf/floor,/area/station)
+"i" = (/obj/machinery/atmospherics/pipe/manifold{dir = 2},/turf/floor,/area/station)
+
+(1,1,1) = {"
+aaaaf
+ghiaa
+bcdae
+"}
+"#;
+
+    #[test]
+    fn test_connections() {
+        let rule = |connections| NodeRule {
+            path: "/obj".to_owned(),
+            connections,
+            default_dir: SOUTH,
+            default_icon_state: None,
+        };
+        let edited = |var, value| ("/obj", Some(vec![(var, value)]));
+
+        assert_eq!(
+            connections(
+                &edited("icon_state", Literal::String("0-4")),
+                &rule(ConnectionMode::IconState)
+            ),
+            CENTER | EAST
+        );
+        assert_eq!(
+            connections(&("/obj", None), &rule(ConnectionMode::IconState)),
+            0
+        );
+        assert_eq!(
+            connections(&("/obj", None), &rule(ConnectionMode::Straight)),
+            NORTH | SOUTH
+        );
+        assert_eq!(
+            connections(
+                &edited("dir", Literal::Number(6.)),
+                &rule(ConnectionMode::Straight)
+            ),
+            SOUTH | EAST
+        );
+        assert_eq!(
+            connections(
+                &edited("dir", Literal::Number(1.)),
+                &rule(ConnectionMode::Manifold)
+            ),
+            SOUTH | EAST | WEST
+        );
+        assert_eq!(
+            connections(
+                &edited("dir", Literal::Number(8.)),
+                &rule(ConnectionMode::Single)
+            ),
+            WEST
+        );
+    }
+
+    #[test]
+    fn test_trace_networks() {
+        let rules = NetworkRules::parse(RULES).unwrap();
+        let (_, (prefabs, blocks)) = parse_map_multithreaded("test".to_owned(), MAP).unwrap();
+        let grid = TileGrid::from_blocks(&blocks, key_len(&prefabs));
+        let reports = trace_networks(&prefabs, &grid, &rules);
+
+        let power = &reports[0];
+        assert_eq!(power.name, "power");
+        let paths = |network: &[usize]| -> Vec<_> {
+            network.iter().map(|node| power.nodes[*node].path).collect()
+        };
+        assert_eq!(power.networks.len(), 3);
+        assert_eq!(
+            paths(&power.networks[0]),
+            vec![
+                "/obj/machinery/power/apc",
+                "/obj/structure/cable",
+                "/obj/structure/cable",
+                "/obj/structure/cable",
+                "/obj/machinery/power/terminal",
+            ]
+        );
+        let unattached: Vec<_> = power.unattached_machines().map(|node| node.path).collect();
+        assert_eq!(unattached, vec!["/obj/machinery/power/smes"]);
+        let isolated: Vec<_> = power.isolated_networks().collect();
+        assert_eq!(isolated.len(), 1);
+        assert_eq!(power.nodes[isolated[0][0]].coord, (5, 3, 1));
+        let dangling: Vec<_> = power
+            .dangling
+            .iter()
+            .map(|end| (power.nodes[end.node].coord, end.dir))
+            .collect();
+        assert_eq!(dangling, vec![((5, 3, 1), NORTH), ((5, 3, 1), SOUTH)]);
+
+        let air = &reports[1];
+        assert_eq!(air.networks.len(), 1);
+        assert_eq!(air.unattached_machines().count(), 0);
+        // Nothing is attached to the manifold's north and east ends
+        let dangling: Vec<_> = air
+            .dangling
+            .iter()
+            .map(|end| (air.nodes[end.node].path, dir_name(end.dir)))
+            .collect();
+        assert_eq!(
+            dangling,
+            vec![
+                ("/obj/machinery/atmospherics/pipe/manifold", "north"),
+                ("/obj/machinery/atmospherics/pipe/manifold", "east"),
+            ]
+        );
+    }
+}
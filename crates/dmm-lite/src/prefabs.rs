@@ -123,7 +123,7 @@ pub fn get_prefab_locations(i: &str) -> Vec<usize> {
 }
 
 pub type Prefabs<'s> = HashMap<&'s str, Vec<(&'s str, Option<Vec<(&'s str, Literal<'s>)>>)>>;
-pub fn multithreaded_parse_map_prefabs(i: Located<&str>) -> Result<Prefabs, LocatedError> {
+pub fn multithreaded_parse_map_prefabs(i: Located<&str>) -> Result<Prefabs<'_>, LocatedError> {
     let locations = get_prefab_locations(&i);
 
     locations
@@ -148,6 +148,26 @@ pub fn multithreaded_parse_map_prefabs(i: Located<&str>) -> Result<Prefabs, Loca
 /// Post-processing: Separate each variable kv pair in the list
 /// {var1="derp"; var2; var3=7} -> ["var1=\"derp\"", "var2", "var3=7"]
 pub fn separate_var_list<'s>(i: &mut Located<&'s str>) -> PResult<Vec<Located<&'s str>>> {
+    let source: &str = i;
+    let (vars, unterminated) = split_var_list(i)?;
+    for quote in unterminated {
+        let report = miette!(
+            severity = Severity::Warning,
+            labels = vec![LabeledSpan::at_offset(
+                quote,
+                "Start of unterminated string"
+            )],
+            "WARNING: Unterminated string literal terminated by line break"
+        );
+        eprintln!("{:?}", report.with_source_code(source.to_owned()));
+    }
+    Ok(vars)
+}
+
+/// [`separate_var_list`], but returning unterminated strings (as offsets from the `{`) instead of printing them.
+pub fn split_var_list<'s>(
+    i: &mut Located<&'s str>,
+) -> PResult<(Vec<Located<&'s str>>, Vec<usize>)> {
     let mut count: usize = 0;
     let mut in_str = false;
 
@@ -160,6 +180,7 @@ pub fn separate_var_list<'s>(i: &mut Located<&'s str>) -> PResult<Vec<Located<&'
     // From this point forward, we are committed until we find a matching `}`.
 
     let mut vars = vec![];
+    let mut unterminated = vec![];
     let mut checkpoint = i.checkpoint();
     let first_checkpoint = i.checkpoint();
     let mut last_quote = 0;
@@ -213,18 +234,8 @@ pub fn separate_var_list<'s>(i: &mut Located<&'s str>) -> PResult<Vec<Located<&'
             Ok("\n") => {
                 count += 1;
                 if in_str {
-                    let report = miette!(
-                        severity = Severity::Warning,
-                        labels = vec![LabeledSpan::at_offset(
-                            last_quote,
-                            "Start of unterminated string"
-                        )],
-                        "WARNING: Unterminated string literal terminated by line break"
-                    );
-                    i.reset(&first_checkpoint);
-                    let report = report.with_source_code(i.to_string());
-
-                    eprintln!("{:?}", report);
+                    // Plus one for the `{`
+                    unterminated.push(last_quote + 1);
 
                     // To recover, we pretend we hit a `;`.
                     i.reset(&checkpoint);
@@ -251,7 +262,7 @@ pub fn separate_var_list<'s>(i: &mut Located<&'s str>) -> PResult<Vec<Located<&'
                         // Eat the }
                         let _ = '}'.parse_next(i)?;
                     }
-                    return Ok(vars);
+                    return Ok((vars, unterminated));
                 } else {
                     count += 1;
                 }
@@ -358,7 +369,7 @@ pub fn parse_literal_list<'s>(i: &mut Located<&'s str>) -> PResult<Literal<'s>>
     "list(".parse_next(i)?;
 
     // Special case: Empty lists
-    if **i == ")" {
+    if opt(')').parse_next(i)?.is_some() {
         return Ok(Literal::List(vec![]));
     }
 
@@ -567,6 +578,17 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_split_var_list() {
+        let mut broken = Located::new("{name = \"broken\n\tdesc = \"fine\"; x = \"also\n}");
+        let (vars, unterminated) = split_var_list.parse_next(&mut broken).unwrap();
+        assert_eq!(
+            vars.iter().map(|s| **s).collect::<Vec<_>>(),
+            vec!["desc = \"fine\""]
+        );
+        assert_eq!(unterminated, vec![8, 36]);
+    }
+
     #[test]
     fn test_identifier() {
         let mut valid_identifier = Located::new("abc1 = ");
@@ -757,6 +779,16 @@ mod tests {
                     ("starts_with", Literal::List(vec![]))
                 ])
             )]
-        )
+        );
+
+        let mut nested = Located::new("list(list(), 1)");
+        assert_eq!(
+            parse_literal.parse_next(&mut nested),
+            Ok(Literal::List(vec![
+                Literal::List(vec![]),
+                Literal::Number(1.)
+            ]))
+        );
+        assert_eq!(*nested, "");
     }
 }
@@ -0,0 +1,327 @@
+//! Finds objects on a map, by path, var edits and area.
+//!
+//! Queries can be built in code, or parsed from a small expression syntax:
+//! - `path:/obj/item` matches objects of exactly that type
+//! - `istype:/obj/item` matches objects of that type, or any subtype
+//! - `var:name` matches objects with `name` edited, `var:dir=4` with `dir` edited to 4
+//! - `area:/area/station` matches objects on tiles in that area, or any subtype of it
+//! - `not`, `and`, `or` and parentheses combine them, in that order of precedence
+//!
+//! For example: `istype:/obj/machinery/door/airlock and var:req_access and not area:/area/maintenance`
+use std::collections::HashMap;
+
+use thiserror::Error;
+use winnow::{
+    ascii::{multispace0, multispace1},
+    combinator::{alt, delimited, opt, preceded, separated},
+    prelude::*,
+    Located,
+};
+
+use crate::{
+    grid::{Coord, TileGrid},
+    prefabs::{
+        parse_identifier, parse_literal_file, parse_literal_list, parse_literal_number,
+        parse_literal_string, parse_path, Literal, Prefab, Prefabs,
+    },
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query<'q> {
+    /// Exactly this path
+    Path(&'q str),
+    /// This path or any of its subtypes
+    Subtype(&'q str),
+    /// The var is edited, optionally to a specific value
+    Var(&'q str, Option<Literal<'q>>),
+    /// The tile's area is this area or any of its subtypes
+    Area(&'q str),
+    Not(Box<Query<'q>>),
+    And(Vec<Query<'q>>),
+    Or(Vec<Query<'q>>),
+}
+
+#[derive(Debug, Error)]
+#[error("Invalid query at offset {offset}: {message}")]
+pub struct QueryError {
+    pub offset: usize,
+    pub message: String,
+}
+
+/// A tile with at least one object matching a query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryMatch<'a, 's> {
+    pub coord: Coord,
+    pub key: &'s str,
+    pub area: Option<&'s str>,
+    /// Everything on the tile
+    pub prefab_list: &'a [Prefab<'s>],
+    /// Just the objects that matched
+    pub matches: Vec<&'a Prefab<'s>>,
+}
+
+/// True if `path` is `parent`, or a subtype of it.
+pub fn is_subtype(path: &str, parent: &str) -> bool {
+    let parent = parent.trim_end_matches('/');
+    path.strip_prefix(parent)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// The area of a tile. BYOND puts it last, but we don't rely on that.
+pub fn tile_area<'s>(prefab_list: &[Prefab<'s>]) -> Option<&'s str> {
+    prefab_list
+        .iter()
+        .rev()
+        .map(|(path, _)| *path)
+        .find(|path| is_subtype(path, "/area"))
+}
+
+/// The turf of a tile. Maps only have one, but BYOND would use the first.
+pub fn tile_turf<'s>(prefab_list: &[Prefab<'s>]) -> Option<&'s str> {
+    prefab_list
+        .iter()
+        .map(|(path, _)| *path)
+        .find(|path| is_subtype(path, "/turf"))
+}
+
+fn parse_value<'q>(i: &mut Located<&'q str>) -> PResult<Literal<'q>> {
+    alt((
+        parse_literal_number.map(Literal::Number),
+        parse_literal_string.map(Literal::String),
+        parse_literal_list,
+        parse_path.map(Literal::Path),
+        parse_literal_file.map(Literal::File),
+        "null".map(|_| Literal::Null),
+    ))
+    .parse_next(i)
+}
+
+fn parse_term<'q>(i: &mut Located<&'q str>) -> PResult<Query<'q>> {
+    alt((
+        preceded("path:", parse_path).map(Query::Path),
+        preceded("istype:", parse_path).map(Query::Subtype),
+        preceded("area:", parse_path).map(Query::Area),
+        preceded("var:", (parse_identifier, opt(preceded('=', parse_value))))
+            .map(|(var, value)| Query::Var(var, value)),
+        delimited(('(', multispace0), parse_or, (multispace0, ')')),
+        preceded(("not", multispace1), parse_term).map(|q| Query::Not(Box::new(q))),
+    ))
+    .parse_next(i)
+}
+
+fn flatten<'q>(mut queries: Vec<Query<'q>>, wrap: fn(Vec<Query<'q>>) -> Query<'q>) -> Query<'q> {
+    if queries.len() == 1 {
+        queries.pop().unwrap()
+    } else {
+        wrap(queries)
+    }
+}
+
+fn parse_and<'q>(i: &mut Located<&'q str>) -> PResult<Query<'q>> {
+    separated(1.., parse_term, (multispace1, "and", multispace1))
+        .map(|queries| flatten(queries, Query::And))
+        .parse_next(i)
+}
+
+fn parse_or<'q>(i: &mut Located<&'q str>) -> PResult<Query<'q>> {
+    separated(1.., parse_and, (multispace1, "or", multispace1))
+        .map(|queries| flatten(queries, Query::Or))
+        .parse_next(i)
+}
+
+impl<'q> Query<'q> {
+    pub fn parse(query: &'q str) -> Result<Self, QueryError> {
+        delimited(multispace0, parse_or, multispace0)
+            .parse(Located::new(query))
+            .map_err(|e| {
+                let message = match e.inner().to_string() {
+                    message if message.is_empty() => match &query[e.offset()..] {
+                        "" => "Unexpected end of query".to_owned(),
+                        rest => format!("Unexpected {rest:?}"),
+                    },
+                    message => message,
+                };
+                QueryError {
+                    offset: e.offset(),
+                    message,
+                }
+            })
+    }
+
+    pub fn and(self, other: Query<'q>) -> Self {
+        match self {
+            Query::And(mut queries) => {
+                queries.push(other);
+                Query::And(queries)
+            }
+            query => Query::And(vec![query, other]),
+        }
+    }
+
+    pub fn or(self, other: Query<'q>) -> Self {
+        match self {
+            Query::Or(mut queries) => {
+                queries.push(other);
+                Query::Or(queries)
+            }
+            query => Query::Or(vec![query, other]),
+        }
+    }
+
+    /// Checks a single object, which is on a tile in `area`.
+    pub fn matches(&self, prefab: &Prefab, area: Option<&str>) -> bool {
+        let (path, vars) = prefab;
+        match self {
+            Query::Path(expected) => path == expected,
+            Query::Subtype(parent) => is_subtype(path, parent),
+            Query::Var(var, value) => vars.iter().flatten().any(|(key, actual)| {
+                key == var
+                    && match value {
+                        Some(value) => value == actual,
+                        None => true,
+                    }
+            }),
+            Query::Area(parent) => area.is_some_and(|area| is_subtype(area, parent)),
+            Query::Not(query) => !query.matches(prefab, area),
+            Query::And(queries) => queries.iter().all(|q| q.matches(prefab, area)),
+            Query::Or(queries) => queries.iter().any(|q| q.matches(prefab, area)),
+        }
+    }
+
+    /// The objects in a prefab list that match.
+    pub fn matching_objects<'a, 's>(&self, prefab_list: &'a [Prefab<'s>]) -> Vec<&'a Prefab<'s>> {
+        let area = tile_area(prefab_list);
+        prefab_list
+            .iter()
+            .filter(|prefab| self.matches(prefab, area))
+            .collect()
+    }
+
+    /// Every tile with a matching object, x first, then y, then z.
+    pub fn run<'a, 's>(
+        &self,
+        prefabs: &'a Prefabs<'s>,
+        grid: &TileGrid<'s>,
+    ) -> Vec<QueryMatch<'a, 's>> {
+        // Each prefab only needs checking once, no matter how many tiles use it
+        let matching_keys: HashMap<&'s str, Vec<&'a Prefab<'s>>> = prefabs
+            .iter()
+            .filter_map(|(key, prefab_list)| {
+                let matches = self.matching_objects(prefab_list);
+                (!matches.is_empty()).then_some((*key, matches))
+            })
+            .collect();
+
+        grid.iter()
+            .filter_map(|(coord, key)| {
+                let matches = matching_keys.get(key)?;
+                let prefab_list = &prefabs[key];
+                Some(QueryMatch {
+                    coord,
+                    key,
+                    area: tile_area(prefab_list),
+                    prefab_list,
+                    matches: matches.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_subtype() {
+        assert!(is_subtype("/obj/item", "/obj/item"));
+        assert!(is_subtype("/obj/item/tool", "/obj/item"));
+        assert!(is_subtype("/obj/item/tool", "/obj/item/"));
+        assert!(!is_subtype("/obj/items", "/obj/item"));
+        assert!(!is_subtype("/obj", "/obj/item"));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Query::parse("path:/obj/item").unwrap(),
+            Query::Path("/obj/item")
+        );
+        assert_eq!(
+            Query::parse(r#" istype:/obj/machinery/door and var:req_access or var:name="meow" "#)
+                .unwrap(),
+            Query::Or(vec![
+                Query::And(vec![
+                    Query::Subtype("/obj/machinery/door"),
+                    Query::Var("req_access", None)
+                ]),
+                Query::Var("name", Some(Literal::String("meow")))
+            ])
+        );
+        assert_eq!(
+            Query::parse("not (area:/area/maintenance or var:dir=4)").unwrap(),
+            Query::Not(Box::new(Query::Or(vec![
+                Query::Area("/area/maintenance"),
+                Query::Var("dir", Some(Literal::Number(4.)))
+            ])))
+        );
+
+        assert!(Query::parse("").is_err());
+        assert!(Query::parse("path:obj").is_err());
+        assert!(Query::parse("path:/obj and").is_err());
+        assert_eq!(Query::parse("var:dir=4 nonsense").unwrap_err().offset, 10);
+    }
+
+    #[test]
+    fn test_run() {
+        let mut prefabs = Prefabs::new();
+        prefabs.insert(
+            "a",
+            vec![
+                (
+                    "/obj/machinery/door/airlock/command",
+                    Some(vec![(
+                        "req_access",
+                        Literal::List(vec![Literal::Number(19.)]),
+                    )]),
+                ),
+                ("/obj/machinery/door/firedoor", None),
+                ("/turf/floor", None),
+                ("/area/station/bridge", None),
+            ],
+        );
+        prefabs.insert(
+            "b",
+            vec![
+                ("/obj/machinery/door/airlock", None),
+                ("/turf/floor", None),
+                ("/area/station/maintenance", None),
+            ],
+        );
+        let grid = TileGrid::from_blocks(&[((1, 1, 1), vec!["ab", "ba"])], 1);
+
+        let query = Query::Subtype("/obj/machinery/door/airlock");
+        assert_eq!(query.run(&prefabs, &grid).len(), 4);
+
+        let query = query.and(Query::Var("req_access", None));
+        let matches = query.run(&prefabs, &grid);
+        assert_eq!(
+            matches.iter().map(|m| m.coord).collect::<Vec<_>>(),
+            vec![(2, 1, 1), (1, 2, 1)]
+        );
+        assert_eq!(matches[0].area, Some("/area/station/bridge"));
+        assert_eq!(matches[0].matches, vec![&prefabs["a"][0]]);
+        assert_eq!(matches[0].prefab_list.len(), 4);
+
+        let query =
+            Query::parse("istype:/obj/machinery/door and area:/area/station/maintenance").unwrap();
+        assert_eq!(
+            query
+                .run(&prefabs, &grid)
+                .iter()
+                .map(|m| m.key)
+                .collect::<Vec<_>>(),
+            vec!["b", "b"]
+        );
+    }
+}
@@ -0,0 +1,365 @@
+//! Drawing maps to images.
+//!
+//! [`minimap`] draws each tile as a block of colour, which only needs the map itself.
+//! [`icons`] draws the real sprites, from the project's .dmi files.
+use std::{fmt::Display, io::Write, str::FromStr};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+pub mod icons;
+pub mod minimap;
+
+#[derive(Debug, Error)]
+pub enum RenderError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    PngEncoding(#[from] png::EncodingError),
+    #[error("Invalid colour {0:?}, expected #rrggbb or #rrggbbaa")]
+    InvalidColor(String),
+    #[error("Invalid render config: {0}")]
+    Config(#[from] toml::de::Error),
+    #[error("There's nothing on z-level {0}")]
+    EmptyZLevel(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const TRANSPARENT: Color = Color::rgba(0, 0, 0, 0);
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b, a: 255 }
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color { r, g, b, a }
+    }
+
+    /// A stable colour for any string, so the same area is always the same colour.
+    /// Kept reasonably bright and saturated, so neighbours are easy to tell apart.
+    pub fn from_hash(text: &str) -> Self {
+        // FNV-1a, which is stable across runs and platforms unlike the std hasher
+        let hash = text.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        let hue = (hash % 360) as f32;
+        let saturation = 0.5 + ((hash >> 16) % 40) as f32 / 100.;
+        let value = 0.6 + ((hash >> 32) % 35) as f32 / 100.;
+        Color::from_hsv(hue, saturation, value)
+    }
+
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let chroma = value * saturation;
+        let x = chroma * (1. - ((hue / 60.) % 2. - 1.).abs());
+        let (r, g, b) = match hue as u32 / 60 {
+            0 => (chroma, x, 0.),
+            1 => (x, chroma, 0.),
+            2 => (0., chroma, x),
+            3 => (0., x, chroma),
+            4 => (x, 0., chroma),
+            _ => (chroma, 0., x),
+        };
+        let m = value - chroma;
+        let channel = |c: f32| ((c + m) * 255.).round() as u8;
+        Color::rgb(channel(r), channel(g), channel(b))
+    }
+
+    /// Alpha blends `self` over `below`.
+    pub fn over(self, below: Color) -> Color {
+        let alpha = self.a as u32;
+        if alpha == 255 {
+            return self;
+        }
+        let inverse = (255 - alpha) * below.a as u32 / 255;
+        let out_alpha = alpha + inverse;
+        if out_alpha == 0 {
+            return Color::TRANSPARENT;
+        }
+        let blend = |top: u8, bottom: u8| {
+            ((top as u32 * alpha + bottom as u32 * inverse) / out_alpha) as u8
+        };
+        Color::rgba(
+            blend(self.r, below.r),
+            blend(self.g, below.g),
+            blend(self.b, below.b),
+            out_alpha as u8,
+        )
+    }
+}
+
+impl FromStr for Color {
+    type Err = RenderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || RenderError::InvalidColor(s.to_owned());
+        let hex = s.strip_prefix('#').ok_or_else(invalid)?;
+        if !hex.is_ascii() || !(hex.len() == 6 || hex.len() == 8) {
+            return Err(invalid());
+        }
+        let channel = |index: usize| {
+            hex.get(index * 2..index * 2 + 2)
+                .map(|channel| u8::from_str_radix(channel, 16).map_err(|_| invalid()))
+        };
+        Ok(Color::rgba(
+            channel(0).ok_or_else(invalid)??,
+            channel(1).ok_or_else(invalid)??,
+            channel(2).ok_or_else(invalid)??,
+            channel(3).unwrap_or(Ok(255))?,
+        ))
+    }
+}
+
+impl Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)?;
+        if self.a != 255 {
+            write!(f, "{:02x}", self.a)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// An RGBA image, top row first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32, fill: Color) -> Self {
+        Image {
+            width,
+            height,
+            pixels: vec![fill; width as usize * height as usize],
+        }
+    }
+
+    /// From tightly packed RGBA bytes, top row first.
+    pub fn from_rgba(width: u32, height: u32, data: &[u8]) -> Self {
+        Image {
+            width,
+            height,
+            pixels: data
+                .chunks_exact(4)
+                .map(|pixel| Color::rgba(pixel[0], pixel[1], pixel[2], pixel[3]))
+                .collect(),
+        }
+    }
+
+    pub fn to_rgba(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|color| [color.r, color.g, color.b, color.a])
+            .collect()
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Option<Color> {
+        (x < self.width && y < self.height).then(|| self.pixels[(x + y * self.width) as usize])
+    }
+
+    /// Sets a pixel, ignoring anything outside the image.
+    pub fn set(&mut self, x: u32, y: u32, color: Color) {
+        if x < self.width && y < self.height {
+            self.pixels[(x + y * self.width) as usize] = color;
+        }
+    }
+
+    /// Fills a rectangle, clipped to the image.
+    pub fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: Color) {
+        for y in y..(y + height).min(self.height) {
+            for x in x..(x + width).min(self.width) {
+                self.set(x, y, color);
+            }
+        }
+    }
+
+    /// Draws another image on top of this one, blending by alpha.
+    /// The offset can be negative or hang off the edge, anything outside is clipped.
+    pub fn blend(&mut self, other: &Image, offset_x: i64, offset_y: i64) {
+        for y in 0..other.height {
+            for x in 0..other.width {
+                let (target_x, target_y) = (x as i64 + offset_x, y as i64 + offset_y);
+                if target_x < 0 || target_y < 0 {
+                    continue;
+                }
+                let (target_x, target_y) = (target_x as u32, target_y as u32);
+                if let Some(below) = self.get(target_x, target_y) {
+                    let top = other.pixels[(x + y * other.width) as usize];
+                    self.set(target_x, target_y, top.over(below));
+                }
+            }
+        }
+    }
+
+    /// Copies out part of the image. Anything outside the image comes out transparent.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Image {
+        let mut cropped = Image::new(width, height, Color::TRANSPARENT);
+        for target_y in 0..height {
+            for target_x in 0..width {
+                if let Some(color) = self.get(x + target_x, y + target_y) {
+                    cropped.set(target_x, target_y, color);
+                }
+            }
+        }
+        cropped
+    }
+
+    /// Multiplies every pixel by a colour, like BYOND's `color` var does.
+    pub fn tint(&mut self, tint: Color) {
+        let multiply = |channel: u8, by: u8| (channel as u32 * by as u32 / 255) as u8;
+        for pixel in &mut self.pixels {
+            *pixel = Color::rgba(
+                multiply(pixel.r, tint.r),
+                multiply(pixel.g, tint.g),
+                multiply(pixel.b, tint.b),
+                multiply(pixel.a, tint.a),
+            );
+        }
+    }
+
+    /// Shrinks the image by a whole factor, averaging each block of pixels.
+    pub fn downscale(&self, factor: u32) -> Image {
+        let factor = factor.max(1);
+        let mut scaled = Image::new(
+            self.width.div_ceil(factor),
+            self.height.div_ceil(factor),
+            Color::TRANSPARENT,
+        );
+        for y in 0..scaled.height {
+            for x in 0..scaled.width {
+                let mut sum = [0u32; 4];
+                let mut count = 0;
+                for source_y in y * factor..((y + 1) * factor).min(self.height) {
+                    for source_x in x * factor..((x + 1) * factor).min(self.width) {
+                        let color = self.pixels[(source_x + source_y * self.width) as usize];
+                        // Weighted by alpha, so transparent pixels don't darken the edges
+                        sum[0] += color.r as u32 * color.a as u32;
+                        sum[1] += color.g as u32 * color.a as u32;
+                        sum[2] += color.b as u32 * color.a as u32;
+                        sum[3] += color.a as u32;
+                        count += 1;
+                    }
+                }
+                let [r, g, b, alpha] = sum;
+                let channel = |sum: u32| sum.checked_div(alpha).unwrap_or(0) as u8;
+                scaled.set(
+                    x,
+                    y,
+                    Color::rgba(channel(r), channel(g), channel(b), (alpha / count) as u8),
+                );
+            }
+        }
+        scaled
+    }
+
+    /// Puts two images next to each other with a gap between, for before and after views.
+    pub fn side_by_side(left: &Image, right: &Image, gap: u32) -> Image {
+        let mut combined = Image::new(
+            left.width + gap + right.width,
+            left.height.max(right.height),
+            Color::TRANSPARENT,
+        );
+        combined.blend(left, 0, 0);
+        combined.blend(right, (left.width + gap) as i64, 0);
+        combined
+    }
+
+    pub fn write_png<W: Write>(&self, writer: W) -> Result<(), RenderError> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgba())?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    pub fn save_png(&self, path: &std::path::Path) -> Result<(), RenderError> {
+        let file = std::fs::File::create(path)?;
+        self.write_png(std::io::BufWriter::new(file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color() {
+        assert_eq!("#ff8000".parse::<Color>().unwrap(), Color::rgb(255, 128, 0));
+        assert_eq!(
+            "#ff800080".parse::<Color>().unwrap(),
+            Color::rgba(255, 128, 0, 128)
+        );
+        assert!("ff8000".parse::<Color>().is_err());
+        assert!("#ff80".parse::<Color>().is_err());
+        assert!("#gg8000".parse::<Color>().is_err());
+        assert_eq!(Color::rgba(255, 128, 0, 128).to_string(), "#ff800080");
+
+        assert_eq!(Color::from_hash("/area/bar"), Color::from_hash("/area/bar"));
+        assert_ne!(
+            Color::from_hash("/area/bar"),
+            Color::from_hash("/area/hall")
+        );
+
+        let half_red = Color::rgba(255, 0, 0, 128);
+        assert_eq!(half_red.over(Color::BLACK), Color::rgb(128, 0, 0));
+        assert_eq!(half_red.over(Color::TRANSPARENT), half_red);
+    }
+
+    #[test]
+    fn test_image() {
+        let mut left = Image::new(2, 2, Color::BLACK);
+        left.fill_rect(1, 0, 5, 5, Color::rgb(255, 255, 255));
+        assert_eq!(left.get(0, 0), Some(Color::BLACK));
+        assert_eq!(left.get(1, 1), Some(Color::rgb(255, 255, 255)));
+        assert_eq!(left.get(2, 0), None);
+
+        let right = Image::new(1, 3, Color::rgb(0, 255, 0));
+        let combined = Image::side_by_side(&left, &right, 1);
+        assert_eq!((combined.width, combined.height), (4, 3));
+        assert_eq!(combined.get(2, 0), Some(Color::TRANSPARENT));
+        assert_eq!(combined.get(3, 2), Some(Color::rgb(0, 255, 0)));
+        assert_eq!(combined.get(0, 2), Some(Color::TRANSPARENT));
+
+        let mut cropped = combined.crop(2, 1, 3, 3);
+        assert_eq!((cropped.width, cropped.height), (3, 3));
+        assert_eq!(cropped.get(1, 1), Some(Color::rgb(0, 255, 0)));
+        assert_eq!(cropped.get(1, 2), Some(Color::TRANSPARENT));
+        cropped.tint(Color::rgb(255, 128, 0));
+        assert_eq!(cropped.get(1, 0), Some(Color::rgb(0, 128, 0)));
+        assert_eq!(
+            Image::from_rgba(cropped.width, cropped.height, &cropped.to_rgba()),
+            cropped
+        );
+
+        let scaled = left.downscale(2);
+        assert_eq!((scaled.width, scaled.height), (1, 1));
+        assert_eq!(scaled.get(0, 0), Some(Color::rgb(127, 127, 127)));
+
+        let mut png = vec![];
+        combined.write_png(&mut png).unwrap();
+        let decoder = png::Decoder::new(png.as_slice());
+        let info = decoder.read_info().unwrap();
+        assert_eq!(info.info().width, 4);
+    }
+}
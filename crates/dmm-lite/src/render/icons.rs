@@ -0,0 +1,368 @@
+//! Map previews drawn with the real sprites, read from the project's .dmi files.
+//!
+//! Maps only have var edits, so what each type looks like to begin with comes from a TOML mapping:
+//! ```toml
+//! # The world's icon_size, in pixels
+//! icon_size = 32
+//!
+//! # Subtypes use the same values, unless they set their own
+//! [types."/turf/open/floor"]
+//! icon = "icons/turf/floors.dmi"
+//! icon_state = "floor"
+//!
+//! [types."/obj/machinery/door/airlock"]
+//! icon = "icons/obj/doors/airlocks/station/public.dmi"
+//! icon_state = "closed"
+//! layer = 2.7
+//! ```
+//! Types without an icon in the mapping or their var edits aren't drawn.
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::PathBuf,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    canonical::normalize_string_escapes,
+    dmi::Dmi,
+    grid::TileGrid,
+    lint::files::resolve_file,
+    prefabs::{Literal, Prefab, Prefabs},
+    query::is_subtype,
+    render::{Color, Image, RenderError},
+};
+
+/// What the mapping file says about a type. Anything left out is inherited from the closest
+/// parent that sets it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IconDefaults {
+    pub icon: Option<String>,
+    pub icon_state: Option<String>,
+    pub dir: Option<u8>,
+    pub layer: Option<f32>,
+    pub plane: Option<f32>,
+    pub pixel_x: Option<i32>,
+    pub pixel_y: Option<i32>,
+    pub color: Option<Color>,
+}
+
+impl IconDefaults {
+    /// Fills in anything this doesn't set from `parent`.
+    fn inherit(&mut self, parent: &IconDefaults) {
+        self.icon = self.icon.take().or_else(|| parent.icon.clone());
+        self.icon_state = self.icon_state.take().or_else(|| parent.icon_state.clone());
+        self.dir = self.dir.or(parent.dir);
+        self.layer = self.layer.or(parent.layer);
+        self.plane = self.plane.or(parent.plane);
+        self.pixel_x = self.pixel_x.or(parent.pixel_x);
+        self.pixel_y = self.pixel_y.or(parent.pixel_y);
+        self.color = self.color.or(parent.color);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IconMapping {
+    /// Pixels per tile, in each direction
+    pub icon_size: u32,
+    pub types: BTreeMap<String, IconDefaults>,
+}
+
+impl Default for IconMapping {
+    fn default() -> Self {
+        IconMapping {
+            icon_size: 32,
+            types: BTreeMap::new(),
+        }
+    }
+}
+
+impl IconMapping {
+    pub fn parse(config: &str) -> Result<Self, RenderError> {
+        Ok(toml::from_str(config)?)
+    }
+
+    /// Everything the mapping says about a path, merged from its most specific entry up.
+    pub fn defaults_for(&self, path: &str) -> IconDefaults {
+        let mut parents: Vec<_> = self
+            .types
+            .iter()
+            .filter(|(parent, _)| is_subtype(path, parent))
+            .collect();
+        parents.sort_by_key(|(parent, _)| std::cmp::Reverse(parent.len()));
+
+        let mut defaults = IconDefaults::default();
+        for (_, parent) in parents {
+            defaults.inherit(parent);
+        }
+        defaults
+    }
+
+    /// How a prefab looks, or None if it has no icon.
+    pub fn appearance(&self, (path, vars): &Prefab) -> Option<Appearance> {
+        let defaults = self.defaults_for(path);
+        let mut icon = defaults.icon;
+        let mut appearance = Appearance {
+            icon: String::new(),
+            icon_state: defaults.icon_state.unwrap_or_default(),
+            dir: defaults.dir.unwrap_or(2),
+            layer: defaults.layer.unwrap_or_else(|| default_layer(path)),
+            plane: defaults.plane.unwrap_or(0.0),
+            pixel_x: defaults.pixel_x.unwrap_or(0),
+            pixel_y: defaults.pixel_y.unwrap_or(0),
+            color: defaults.color,
+        };
+
+        for (var, value) in vars.iter().flatten() {
+            match (*var, value) {
+                ("icon", Literal::File(file)) => icon = Some(normalize_string_escapes(file).into()),
+                ("icon", Literal::Null) => icon = None,
+                ("icon_state", Literal::String(state)) => {
+                    appearance.icon_state = normalize_string_escapes(state).into()
+                }
+                ("color", Literal::String(color)) => appearance.color = color.parse().ok(),
+                ("color", Literal::Null) => appearance.color = None,
+                ("dir", value) => {
+                    appearance.dir = number(value).map_or(appearance.dir, |n| n as u8)
+                }
+                ("layer", value) => appearance.layer = number(value).unwrap_or(appearance.layer),
+                ("plane", value) => appearance.plane = number(value).unwrap_or(appearance.plane),
+                ("pixel_x", value) => {
+                    appearance.pixel_x = number(value).map_or(appearance.pixel_x, |n| n as i32)
+                }
+                ("pixel_y", value) => {
+                    appearance.pixel_y = number(value).map_or(appearance.pixel_y, |n| n as i32)
+                }
+                _ => {}
+            }
+        }
+
+        appearance.icon = icon?;
+        Some(appearance)
+    }
+}
+
+/// How one object looks, after var edits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Appearance {
+    pub icon: String,
+    pub icon_state: String,
+    pub dir: u8,
+    pub layer: f32,
+    pub plane: f32,
+    pub pixel_x: i32,
+    pub pixel_y: i32,
+    pub color: Option<Color>,
+}
+
+/// The layer BYOND gives each kind of atom when nothing sets one.
+fn default_layer(path: &str) -> f32 {
+    if is_subtype(path, "/area") {
+        1.0
+    } else if is_subtype(path, "/turf") {
+        2.0
+    } else if is_subtype(path, "/mob") {
+        4.0
+    } else {
+        3.0
+    }
+}
+
+fn number(value: &Literal) -> Option<f32> {
+    match value {
+        Literal::Number(number) => Some(*number),
+        _ => None,
+    }
+}
+
+/// Draws maps with the icons from a project, keeping each .dmi around once it's been read.
+pub struct IconRenderer {
+    pub root: PathBuf,
+    pub mapping: IconMapping,
+    /// Icons that don't exist or couldn't be read, with why
+    pub missing_icons: BTreeMap<String, String>,
+    /// Icon states that aren't in their icon, as (icon, icon_state)
+    pub missing_states: BTreeSet<(String, String)>,
+    cache: HashMap<String, Option<Dmi>>,
+}
+
+impl IconRenderer {
+    pub fn new(root: impl Into<PathBuf>, mapping: IconMapping) -> Self {
+        IconRenderer {
+            root: root.into(),
+            mapping,
+            missing_icons: BTreeMap::new(),
+            missing_states: BTreeSet::new(),
+            cache: HashMap::new(),
+        }
+    }
+
+    fn sprite(&mut self, appearance: &Appearance) -> Option<Image> {
+        if !self.cache.contains_key(&appearance.icon) {
+            let dmi = match Dmi::read(&resolve_file(&self.root, &appearance.icon)) {
+                Ok(dmi) => Some(dmi),
+                Err(e) => {
+                    self.missing_icons
+                        .insert(appearance.icon.clone(), e.to_string());
+                    None
+                }
+            };
+            self.cache.insert(appearance.icon.clone(), dmi);
+        }
+
+        let dmi = self.cache[&appearance.icon].as_ref()?;
+        let Some(mut sprite) = dmi.icon(&appearance.icon_state, appearance.dir, 0) else {
+            self.missing_states
+                .insert((appearance.icon.clone(), appearance.icon_state.clone()));
+            return None;
+        };
+        if let Some(color) = appearance.color {
+            sprite.tint(color);
+        }
+        Some(sprite)
+    }
+
+    /// Draws one z-level at full size. North is up, like in game.
+    pub fn render_z(
+        &mut self,
+        prefabs: &Prefabs,
+        grid: &TileGrid,
+        z: usize,
+    ) -> Result<Image, RenderError> {
+        if grid.is_empty() || z < grid.origin.2 || z > grid.max().2 {
+            return Err(RenderError::EmptyZLevel(z));
+        }
+
+        let size = self.mapping.icon_size.max(1);
+        let (width, height) = (grid.size.0 as u32, grid.size.1 as u32);
+        let max = grid.max();
+
+        // Drawn by plane, then layer, then from the top of the map down so things further south
+        // cover things behind them, then in map order
+        let mut appearances = vec![];
+        for y in grid.origin.1..=max.1 {
+            for x in grid.origin.0..=max.0 {
+                let Some(prefab_list) = grid.get((x, y, z)).and_then(|key| prefabs.get(key)) else {
+                    continue;
+                };
+                let tile = ((x - grid.origin.0) as u32, (max.1 - y) as u32);
+                for prefab in prefab_list {
+                    if let Some(appearance) = self.mapping.appearance(prefab) {
+                        appearances.push((tile, appearance));
+                    }
+                }
+            }
+        }
+        appearances.sort_by(|(a_tile, a), (b_tile, b)| {
+            a.plane
+                .total_cmp(&b.plane)
+                .then(a.layer.total_cmp(&b.layer))
+                .then(a_tile.1.cmp(&b_tile.1))
+        });
+
+        let mut image = Image::new(width * size, height * size, Color::TRANSPARENT);
+        for ((tile_x, tile_y), appearance) in appearances {
+            let Some(sprite) = self.sprite(&appearance) else {
+                continue;
+            };
+            // Big icons stick up and to the right of their tile, like in game
+            let x = (tile_x * size) as i64 + appearance.pixel_x as i64;
+            let y = ((tile_y + 1) * size) as i64 - sprite.height as i64 - appearance.pixel_y as i64;
+            image.blend(&sprite, x, y);
+        }
+
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dmi::write_test_dmi, parse_map_multithreaded};
+
+    const MAP: &str = r##""a" = (/turf/floor,/area/hall)
+"b" = (/obj/sign{pixel_y = 1; color = "#ff0000"},/turf/floor,/area/hall)
+"c" = (/obj/sign{icon_state = "missing"},/turf/wall,/area/hall)
+
+(1,1,1) = {"
+ab
+cc
+"}
+"##;
+
+    #[test]
+    fn test_defaults_for() {
+        let mapping = IconMapping::parse(
+            r#"
+            [types."/obj"]
+            icon = "obj.dmi"
+            layer = 3.5
+
+            [types."/obj/sign"]
+            icon_state = "sign"
+            "#,
+        )
+        .unwrap();
+        let defaults = mapping.defaults_for("/obj/sign/big");
+        assert_eq!(defaults.icon.as_deref(), Some("obj.dmi"));
+        assert_eq!(defaults.icon_state.as_deref(), Some("sign"));
+        assert_eq!(defaults.layer, Some(3.5));
+        assert_eq!(mapping.defaults_for("/turf"), IconDefaults::default());
+        assert!(IconMapping::parse("[types.\"/obj\"]\nsize = 1").is_err());
+    }
+
+    #[test]
+    fn test_render_z() {
+        let root = std::env::temp_dir().join("dmm-lite-test-render-icons");
+        std::fs::create_dir_all(&root).unwrap();
+        // 2x2 icons: a grey floor, a white wall and a white 1x1 sign in the bottom left corner
+        let metadata = "# BEGIN DMI\nversion = 4.0\n\twidth = 2\n\theight = 2\nstate = \"floor\"\nstate = \"wall\"\nstate = \"sign\"\n# END DMI\n";
+        let mut icons = Image::new(6, 2, Color::TRANSPARENT);
+        icons.fill_rect(0, 0, 2, 2, Color::rgb(128, 128, 128));
+        icons.fill_rect(2, 0, 2, 2, Color::rgb(255, 255, 255));
+        icons.set(4, 1, Color::rgb(255, 255, 255));
+        write_test_dmi(&root.join("icons.dmi"), metadata, &icons);
+
+        let mapping = IconMapping::parse(
+            r#"
+            icon_size = 2
+
+            [types."/turf"]
+            icon = "icons.dmi"
+
+            [types."/turf/floor"]
+            icon_state = "floor"
+
+            [types."/turf/wall"]
+            icon_state = "wall"
+
+            [types."/obj/sign"]
+            icon = "icons.dmi"
+            icon_state = "sign"
+            "#,
+        )
+        .unwrap();
+        let (_, (prefabs, blocks)) = parse_map_multithreaded("test".to_owned(), MAP).unwrap();
+        let grid = TileGrid::from_blocks(&blocks, 1);
+        let mut renderer = IconRenderer::new(&root, mapping);
+        let image = renderer.render_z(&prefabs, &grid, 1).unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+        assert_eq!((image.width, image.height), (4, 4));
+
+        let grey = Color::rgb(128, 128, 128);
+        let white = Color::rgb(255, 255, 255);
+        // Floor, with the sign drawn over it, tinted red and a pixel up
+        assert_eq!(image.get(0, 1), Some(grey));
+        assert_eq!(image.get(2, 1), Some(grey));
+        assert_eq!(image.get(2, 0), Some(Color::rgb(255, 0, 0)));
+        // Walls, with the sign that's missing its icon_state not drawn
+        assert_eq!(image.get(0, 3), Some(white));
+        assert_eq!(
+            renderer.missing_states,
+            BTreeSet::from([("icons.dmi".to_owned(), "missing".to_owned())])
+        );
+        assert!(renderer.missing_icons.is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+//! Minimaps, with every tile drawn as a block of colour picked from its area or turf.
+//!
+//! Colours and overlays can be set with a TOML config:
+//! ```toml
+//! color_by = "area"
+//! tile_size = 4
+//!
+//! # Subtypes get the same colour, unless they have their own
+//! [palette]
+//! "/area/station/engineering" = "#ffcc00"
+//! "/area/station/medical" = "#66ccff"
+//!
+//! # Drawn in the middle of tiles with the type on them, in order
+//! [[overlay]]
+//! type = "/obj/machinery/door/airlock"
+//! color = "#ff0000"
+//! ```
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::{
+    grid::TileGrid,
+    prefabs::Prefabs,
+    query::{is_subtype, tile_area, tile_turf},
+    render::{Color, Image, RenderError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorBy {
+    #[default]
+    Area,
+    Turf,
+}
+
+impl std::str::FromStr for ColorBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "area" => Ok(ColorBy::Area),
+            "turf" => Ok(ColorBy::Turf),
+            _ => Err(format!("Expected area or turf, got {s:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Overlay {
+    #[serde(rename = "type")]
+    pub path: String,
+    pub color: Color,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MinimapOptions {
+    pub color_by: ColorBy,
+    /// Pixels per tile, in each direction
+    pub tile_size: u32,
+    /// Colours for types and their subtypes. Anything not in here gets a colour from its path.
+    pub palette: BTreeMap<String, Color>,
+    #[serde(rename = "overlay")]
+    pub overlays: Vec<Overlay>,
+}
+
+impl Default for MinimapOptions {
+    fn default() -> Self {
+        MinimapOptions {
+            color_by: ColorBy::Area,
+            tile_size: 4,
+            palette: BTreeMap::new(),
+            overlays: vec![],
+        }
+    }
+}
+
+impl MinimapOptions {
+    pub fn parse(config: &str) -> Result<Self, RenderError> {
+        Ok(toml::from_str(config)?)
+    }
+
+    /// The colour for a path, from the most specific palette entry or its hash.
+    pub fn color_of(&self, path: &str) -> Color {
+        self.palette
+            .iter()
+            .filter(|(parent, _)| is_subtype(path, parent))
+            .max_by_key(|(parent, _)| parent.len())
+            .map(|(_, color)| *color)
+            .unwrap_or_else(|| Color::from_hash(path))
+    }
+}
+
+/// Draws one z-level. North is up, like in game.
+pub fn render_minimap(
+    prefabs: &Prefabs,
+    grid: &TileGrid,
+    z: usize,
+    options: &MinimapOptions,
+) -> Result<Image, RenderError> {
+    if grid.is_empty() || z < grid.origin.2 || z > grid.max().2 {
+        return Err(RenderError::EmptyZLevel(z));
+    }
+
+    let size = options.tile_size.max(1);
+    let (width, height) = (grid.size.0 as u32, grid.size.1 as u32);
+    let mut image = Image::new(width * size, height * size, Color::TRANSPARENT);
+    // Overlays go in the middle half of the tile, or over all of it if tiles are tiny
+    let inset = size / 4;
+    let max = grid.max();
+
+    for y in grid.origin.1..=max.1 {
+        for x in grid.origin.0..=max.0 {
+            let Some(prefab_list) = grid.get((x, y, z)).and_then(|key| prefabs.get(key)) else {
+                continue;
+            };
+            let pixel_x = (x - grid.origin.0) as u32 * size;
+            let pixel_y = (max.1 - y) as u32 * size;
+
+            let path = match options.color_by {
+                ColorBy::Area => tile_area(prefab_list),
+                ColorBy::Turf => tile_turf(prefab_list),
+            };
+            if let Some(path) = path {
+                image.fill_rect(pixel_x, pixel_y, size, size, options.color_of(path));
+            }
+
+            for overlay in &options.overlays {
+                if prefab_list
+                    .iter()
+                    .any(|(path, _)| is_subtype(path, &overlay.path))
+                {
+                    let overlay_size = size - inset * 2;
+                    // Blended rather than filled, so overlays can be see-through
+                    let tile = Image::new(overlay_size, overlay_size, overlay.color);
+                    image.blend(&tile, (pixel_x + inset) as i64, (pixel_y + inset) as i64);
+                }
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_map_multithreaded;
+
+    const MAP: &str = r#""a" = (/turf/floor,/area/hall)
+"b" = (/obj/machinery/door/airlock,/turf/floor,/area/bar)
+"c" = (/turf/wall,/area/bar)
+
+(1,1,1) = {"
+ab
+cc
+"}
+"#;
+
+    #[test]
+    fn test_render_minimap() {
+        let (_, (prefabs, blocks)) = parse_map_multithreaded("test".to_owned(), MAP).unwrap();
+        let grid = TileGrid::from_blocks(&blocks, 1);
+        let options = MinimapOptions::parse(
+            r##"
+            tile_size = 4
+
+            [palette]
+            "/area" = "#000000"
+            "/area/bar" = "#0000ff"
+
+            [[overlay]]
+            type = "/obj/machinery/door"
+            color = "#ff0000"
+            "##,
+        )
+        .unwrap();
+        let image = render_minimap(&prefabs, &grid, 1, &options).unwrap();
+        assert_eq!((image.width, image.height), (8, 8));
+
+        let blue = Color::rgb(0, 0, 255);
+        // Top left is the hall
+        assert_eq!(image.get(0, 0), Some(Color::BLACK));
+        // Top right is the bar, with a door in the middle
+        assert_eq!(image.get(4, 0), Some(blue));
+        assert_eq!(image.get(5, 1), Some(Color::rgb(255, 0, 0)));
+        assert_eq!(image.get(6, 2), Some(Color::rgb(255, 0, 0)));
+        assert_eq!(image.get(7, 3), Some(blue));
+        // The bottom row is all bar
+        assert_eq!(image.get(0, 7), Some(blue));
+
+        let by_turf = MinimapOptions {
+            color_by: ColorBy::Turf,
+            tile_size: 1,
+            ..Default::default()
+        };
+        let image = render_minimap(&prefabs, &grid, 1, &by_turf).unwrap();
+        assert_eq!(image.get(0, 0), Some(Color::from_hash("/turf/floor")));
+        assert_eq!(image.get(1, 1), Some(Color::from_hash("/turf/wall")));
+
+        assert!(render_minimap(&prefabs, &grid, 2, &options).is_err());
+        assert!(MinimapOptions::parse("color_by = \"objects\"").is_err());
+        assert_eq!("turf".parse(), Ok(ColorBy::Turf));
+        assert!("objects".parse::<ColorBy>().is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Counts what's on a map, to keep an eye on how big maps get over time.
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    grid::{key_len, TileGrid},
+    prefabs::Prefabs,
+    query::{is_subtype, tile_area},
+    writer::write_var_list,
+};
+
+/// The occupied part of one z-level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZLevelStats {
+    pub z: usize,
+    pub min: (usize, usize),
+    pub max: (usize, usize),
+    pub tiles: usize,
+}
+
+impl ZLevelStats {
+    pub fn width(&self) -> usize {
+        self.max.0 - self.min.0 + 1
+    }
+
+    pub fn height(&self) -> usize {
+        self.max.1 - self.min.1 + 1
+    }
+}
+
+/// All counts are of instances on the map, not of prefabs, and are sorted from most to least common.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MapStats<'s> {
+    pub key_length: usize,
+    pub prefabs: usize,
+    pub z_levels: Vec<ZLevelStats>,
+    /// Every distinct path, including turfs and areas
+    pub unique_paths: usize,
+    /// Objects (and mobs) by type
+    pub objects_by_type: Vec<(&'s str, usize)>,
+    /// Objects (and mobs) by the area they're in
+    pub objects_by_area: Vec<(&'s str, usize)>,
+    pub turfs_by_area: Vec<(&'s str, usize)>,
+    /// For each type, how often each set of var edits shows up, written like `{dir = 4; name = "meow"}`.
+    /// A set of var edits used a lot is a good candidate for a subtype.
+    pub var_edits_by_type: Vec<(&'s str, Vec<(String, usize)>)>,
+}
+
+fn sorted_counts<K: Ord>(counts: HashMap<K, usize>) -> Vec<(K, usize)> {
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+pub fn map_stats<'s>(prefabs: &Prefabs<'s>, grid: &TileGrid<'s>) -> MapStats<'s> {
+    let mut z_levels: Vec<ZLevelStats> = vec![];
+    let mut key_uses: HashMap<&'s str, usize> = HashMap::new();
+    for ((x, y, z), key) in grid.iter() {
+        *key_uses.entry(key).or_default() += 1;
+
+        // Tiles come out ordered by z, so we only ever need to look at the last level
+        match z_levels.last_mut() {
+            Some(level) if level.z == z => {
+                level.min = (level.min.0.min(x), level.min.1.min(y));
+                level.max = (level.max.0.max(x), level.max.1.max(y));
+                level.tiles += 1;
+            }
+            _ => z_levels.push(ZLevelStats {
+                z,
+                min: (x, y),
+                max: (x, y),
+                tiles: 1,
+            }),
+        }
+    }
+
+    let mut unique_paths = HashSet::new();
+    let mut objects_by_type: HashMap<&'s str, usize> = HashMap::new();
+    let mut objects_by_area: HashMap<&'s str, usize> = HashMap::new();
+    let mut turfs_by_area: HashMap<&'s str, usize> = HashMap::new();
+    let mut var_edits: HashMap<&'s str, HashMap<String, usize>> = HashMap::new();
+    for (key, prefab_list) in prefabs {
+        unique_paths.extend(prefab_list.iter().map(|(path, _)| *path));
+
+        let Some(&uses) = key_uses.get(key) else {
+            continue;
+        };
+        let area = tile_area(prefab_list).unwrap_or("<no area>");
+        for prefab in prefab_list {
+            let (path, vars) = prefab;
+            if let Some(vars) = vars {
+                let mut written = String::new();
+                write_var_list(&mut written, vars, false).expect("Writing to a String can't fail");
+                *var_edits
+                    .entry(path)
+                    .or_default()
+                    .entry(written)
+                    .or_default() += uses;
+            }
+
+            if is_subtype(path, "/turf") {
+                *turfs_by_area.entry(area).or_default() += uses;
+            } else if !is_subtype(path, "/area") {
+                *objects_by_type.entry(path).or_default() += uses;
+                *objects_by_area.entry(area).or_default() += uses;
+            }
+        }
+    }
+
+    let mut var_edits_by_type: Vec<_> = var_edits
+        .into_iter()
+        .map(|(path, counts)| (path, sorted_counts(counts)))
+        .collect();
+    var_edits_by_type.sort_by(|a, b| {
+        let total = |counts: &[(String, usize)]| counts.iter().map(|(_, n)| n).sum::<usize>();
+        total(&b.1).cmp(&total(&a.1)).then_with(|| a.0.cmp(b.0))
+    });
+
+    MapStats {
+        key_length: key_len(prefabs),
+        prefabs: prefabs.len(),
+        z_levels,
+        unique_paths: unique_paths.len(),
+        objects_by_type: sorted_counts(objects_by_type),
+        objects_by_area: sorted_counts(objects_by_area),
+        turfs_by_area: sorted_counts(turfs_by_area),
+        var_edits_by_type,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prefabs::Literal;
+
+    #[test]
+    fn test_map_stats() {
+        let mut prefabs = Prefabs::new();
+        prefabs.insert(
+            "a",
+            vec![
+                ("/obj/item", Some(vec![("dir", Literal::Number(4.))])),
+                ("/obj/item", None),
+                ("/turf/floor", None),
+                ("/area/station", None),
+            ],
+        );
+        prefabs.insert("b", vec![("/turf/space", None), ("/area/space", None)]);
+        prefabs.insert("c", vec![("/turf/unused", None), ("/area/space", None)]);
+        let blocks = vec![((1, 1, 1), vec!["ab", "bb"]), ((2, 2, 2), vec!["a"])];
+        let grid = TileGrid::from_blocks(&blocks, 1);
+
+        let stats = map_stats(&prefabs, &grid);
+        assert_eq!(stats.key_length, 1);
+        assert_eq!(stats.prefabs, 3);
+        assert_eq!(
+            stats.z_levels,
+            vec![
+                ZLevelStats {
+                    z: 1,
+                    min: (1, 1),
+                    max: (2, 2),
+                    tiles: 4
+                },
+                ZLevelStats {
+                    z: 2,
+                    min: (2, 2),
+                    max: (2, 2),
+                    tiles: 1
+                }
+            ]
+        );
+        assert_eq!(stats.unique_paths, 6);
+        assert_eq!(stats.objects_by_type, vec![("/obj/item", 4)]);
+        assert_eq!(stats.objects_by_area, vec![("/area/station", 4)]);
+        assert_eq!(
+            stats.turfs_by_area,
+            vec![("/area/space", 3), ("/area/station", 2)]
+        );
+        assert_eq!(
+            stats.var_edits_by_type,
+            vec![("/obj/item", vec![("{dir = 4}".to_owned(), 2)])]
+        );
+    }
+}
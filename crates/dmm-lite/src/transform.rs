@@ -0,0 +1,373 @@
+//! Rotating and mirroring parsed maps.
+//!
+//! Transforming a map moves tiles around inside the map's bounding box (which keeps its bottom left corner),
+//! and rewrites the var edits that care about orientation: `dir`, `pixel_x` and `pixel_y`.
+use crate::{
+    grid::{key_len, Coord, TileGrid},
+    prefabs::{Literal, Prefab},
+    MapData,
+};
+
+pub const NORTH: u8 = 1;
+pub const SOUTH: u8 = 2;
+pub const EAST: u8 = 4;
+pub const WEST: u8 = 8;
+
+/// A rotation or mirroring of a map. Rotations are clockwise, looking at the map with north up.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MapTransform {
+    #[default]
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Mirrors east and west
+    FlipHorizontal,
+    /// Mirrors north and south
+    FlipVertical,
+}
+
+impl MapTransform {
+    /// Converts a clockwise rotation in degrees, which must be a multiple of 90 (negative is fine).
+    pub fn from_degrees(degrees: i32) -> Option<Self> {
+        match degrees.rem_euclid(360) {
+            0 => Some(MapTransform::Identity),
+            90 => Some(MapTransform::Rotate90),
+            180 => Some(MapTransform::Rotate180),
+            270 => Some(MapTransform::Rotate270),
+            _ => None,
+        }
+    }
+
+    /// True if width and height trade places.
+    pub fn swaps_axes(self) -> bool {
+        matches!(self, MapTransform::Rotate90 | MapTransform::Rotate270)
+    }
+
+    /// Transforms a (width, height) size.
+    pub fn transform_size(self, (width, height): (usize, usize)) -> (usize, usize) {
+        if self.swaps_axes() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Transforms a 0-based (x, y) offset from the bottom left of a `size` sized grid,
+    /// giving its offset in the transformed grid.
+    pub fn transform_offset(
+        self,
+        (x, y): (usize, usize),
+        (width, height): (usize, usize),
+    ) -> (usize, usize) {
+        match self {
+            MapTransform::Identity => (x, y),
+            MapTransform::Rotate90 => (y, width - 1 - x),
+            MapTransform::Rotate180 => (width - 1 - x, height - 1 - y),
+            MapTransform::Rotate270 => (height - 1 - y, x),
+            MapTransform::FlipHorizontal => (width - 1 - x, y),
+            MapTransform::FlipVertical => (x, height - 1 - y),
+        }
+    }
+
+    /// Transforms bounds in (minx, miny, minz, maxx, maxy, maxz) form, keeping the bottom left corner in place.
+    pub fn transform_bounds(
+        self,
+        bounds: (usize, usize, usize, usize, usize, usize),
+    ) -> (usize, usize, usize, usize, usize, usize) {
+        let (minx, miny, minz, maxx, maxy, maxz) = bounds;
+        if self.swaps_axes() {
+            (
+                minx,
+                miny,
+                minz,
+                minx + (maxy - miny),
+                miny + (maxx - minx),
+                maxz,
+            )
+        } else {
+            bounds
+        }
+    }
+
+    /// Transforms a single cardinal direction
+    fn transform_cardinal(self, dir: u8) -> u8 {
+        match (self, dir) {
+            (MapTransform::Identity, dir) => dir,
+            (MapTransform::Rotate90, NORTH) => EAST,
+            (MapTransform::Rotate90, EAST) => SOUTH,
+            (MapTransform::Rotate90, SOUTH) => WEST,
+            (MapTransform::Rotate90, WEST) => NORTH,
+            (MapTransform::Rotate180 | MapTransform::FlipVertical, NORTH) => SOUTH,
+            (MapTransform::Rotate180 | MapTransform::FlipVertical, SOUTH) => NORTH,
+            (MapTransform::Rotate180 | MapTransform::FlipHorizontal, EAST) => WEST,
+            (MapTransform::Rotate180 | MapTransform::FlipHorizontal, WEST) => EAST,
+            (MapTransform::Rotate270, NORTH) => WEST,
+            (MapTransform::Rotate270, WEST) => SOUTH,
+            (MapTransform::Rotate270, SOUTH) => EAST,
+            (MapTransform::Rotate270, EAST) => NORTH,
+            (_, dir) => dir,
+        }
+    }
+
+    /// Transforms a BYOND direction (including diagonals). UP and DOWN are left alone.
+    pub fn transform_dir(self, dir: u8) -> u8 {
+        [NORTH, SOUTH, EAST, WEST]
+            .into_iter()
+            .filter(|cardinal| dir & cardinal != 0)
+            .fold(dir & !(NORTH | SOUTH | EAST | WEST), |acc, cardinal| {
+                acc | self.transform_cardinal(cardinal)
+            })
+    }
+
+    /// Transforms a (pixel_x, pixel_y) offset.
+    pub fn transform_pixel_offset(self, (x, y): (f32, f32)) -> (f32, f32) {
+        let (x, y) = match self {
+            MapTransform::Identity => (x, y),
+            MapTransform::Rotate90 => (y, -x),
+            MapTransform::Rotate180 => (-x, -y),
+            MapTransform::Rotate270 => (-y, x),
+            MapTransform::FlipHorizontal => (-x, y),
+            MapTransform::FlipVertical => (x, -y),
+        };
+        // Adding zero turns -0 into 0, which is nicer to write back out
+        (x + 0.0, y + 0.0)
+    }
+
+    /// Rewrites a prefab's orientation dependent var edits.
+    pub fn transform_prefab<'s>(self, prefab: &Prefab<'s>) -> Prefab<'s> {
+        let (path, vars) = prefab;
+        let Some(vars) = vars else {
+            return (path, None);
+        };
+        if self == MapTransform::Identity {
+            return (path, Some(vars.clone()));
+        }
+
+        let mut vars = vars.clone();
+        // None if either offset isn't a plain number, like a define, which can't be rotated
+        let mut pixel_offset = Some((0., 0.));
+        for (key, value) in vars.iter_mut() {
+            match (*key, value) {
+                ("dir", Literal::Number(dir)) => {
+                    *dir = self.transform_dir(*dir as u8) as f32;
+                }
+                ("pixel_x", Literal::Number(x)) => {
+                    pixel_offset = pixel_offset.map(|(_, y)| (*x, y));
+                }
+                ("pixel_y", Literal::Number(y)) => {
+                    pixel_offset = pixel_offset.map(|(x, _)| (x, *y));
+                }
+                ("pixel_x" | "pixel_y", _) => pixel_offset = None,
+                _ => {}
+            }
+        }
+
+        if let Some(pixel_offset) = pixel_offset {
+            let (new_x, new_y) = self.transform_pixel_offset(pixel_offset);
+            set_pixel_var(&mut vars, "pixel_x", new_x);
+            set_pixel_var(&mut vars, "pixel_y", new_y);
+        }
+
+        (path, Some(vars))
+    }
+
+    /// Transforms a whole map. Keys are kept as they are, blocks come out TGM style, one column per block.
+    pub fn transform_map<'s>(self, (prefabs, blocks): &MapData<'s>) -> MapData<'s> {
+        let key_len = key_len(prefabs);
+        let grid = TileGrid::from_blocks(blocks, key_len);
+
+        let prefabs = prefabs
+            .iter()
+            .map(|(key, prefab_list)| {
+                (
+                    *key,
+                    prefab_list
+                        .iter()
+                        .map(|prefab| self.transform_prefab(prefab))
+                        .collect(),
+                )
+            })
+            .collect();
+
+        (prefabs, self.transform_grid(&grid).to_blocks())
+    }
+
+    /// Moves every tile in a grid, keeping the grid's origin in place.
+    pub fn transform_grid<'s>(self, grid: &TileGrid<'s>) -> TileGrid<'s> {
+        let (width, height) = self.transform_size((grid.size.0, grid.size.1));
+        let mut transformed = TileGrid::new(grid.origin, (width, height, grid.size.2));
+        for (coord, key) in grid.iter() {
+            transformed.set(self.transform_coord(coord, grid), key);
+        }
+        transformed
+    }
+
+    /// Transforms a coordinate inside of `grid`.
+    pub fn transform_coord(self, coord: Coord, grid: &TileGrid) -> Coord {
+        let (x, y) = self.transform_offset(
+            (coord.0 - grid.origin.0, coord.1 - grid.origin.1),
+            (grid.size.0, grid.size.1),
+        );
+        (grid.origin.0 + x, grid.origin.1 + y, coord.2)
+    }
+}
+
+/// Sets a pixel offset var, only adding it if it wasn't there before when it has to be nonzero.
+fn set_pixel_var<'s>(vars: &mut Vec<(&'s str, Literal<'s>)>, name: &'s str, value: f32) {
+    if let Some((_, existing)) = vars.iter_mut().find(|(key, _)| *key == name) {
+        *existing = Literal::Number(value);
+    } else if value != 0. {
+        vars.push((name, Literal::Number(value)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_degrees() {
+        assert_eq!(MapTransform::from_degrees(0), Some(MapTransform::Identity));
+        assert_eq!(MapTransform::from_degrees(90), Some(MapTransform::Rotate90));
+        assert_eq!(
+            MapTransform::from_degrees(-90),
+            Some(MapTransform::Rotate270)
+        );
+        assert_eq!(
+            MapTransform::from_degrees(540),
+            Some(MapTransform::Rotate180)
+        );
+        assert_eq!(MapTransform::from_degrees(45), None);
+    }
+
+    #[test]
+    fn test_transform_dir() {
+        assert_eq!(MapTransform::Rotate90.transform_dir(NORTH), EAST);
+        assert_eq!(
+            MapTransform::Rotate90.transform_dir(NORTH | EAST),
+            SOUTH | EAST
+        );
+        assert_eq!(
+            MapTransform::Rotate180.transform_dir(SOUTH | WEST),
+            NORTH | EAST
+        );
+        assert_eq!(MapTransform::Rotate270.transform_dir(EAST), NORTH);
+        assert_eq!(
+            MapTransform::FlipHorizontal.transform_dir(NORTH | WEST),
+            NORTH | EAST
+        );
+        assert_eq!(
+            MapTransform::FlipVertical.transform_dir(NORTH | WEST),
+            SOUTH | WEST
+        );
+        // UP
+        assert_eq!(MapTransform::Rotate90.transform_dir(16), 16);
+    }
+
+    #[test]
+    fn test_transform_offset() {
+        // Top left corner of a 4x2 grid
+        let corner = (0, 1);
+        assert_eq!(
+            MapTransform::Rotate90.transform_offset(corner, (4, 2)),
+            (1, 3)
+        );
+        assert_eq!(
+            MapTransform::Rotate180.transform_offset(corner, (4, 2)),
+            (3, 0)
+        );
+        assert_eq!(
+            MapTransform::Rotate270.transform_offset(corner, (4, 2)),
+            (0, 0)
+        );
+        assert_eq!(
+            MapTransform::FlipHorizontal.transform_offset(corner, (4, 2)),
+            (3, 1)
+        );
+        assert_eq!(
+            MapTransform::FlipVertical.transform_offset(corner, (4, 2)),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn test_transform_prefab() {
+        let prefab: Prefab = (
+            "/obj/machinery/light",
+            Some(vec![
+                ("dir", Literal::Number(1.)),
+                ("pixel_x", Literal::Number(5.)),
+                ("name", Literal::String("meow")),
+            ]),
+        );
+
+        assert_eq!(
+            MapTransform::Rotate90.transform_prefab(&prefab),
+            (
+                "/obj/machinery/light",
+                Some(vec![
+                    ("dir", Literal::Number(4.)),
+                    ("pixel_x", Literal::Number(0.)),
+                    ("name", Literal::String("meow")),
+                    ("pixel_y", Literal::Number(-5.)),
+                ])
+            )
+        );
+        assert_eq!(
+            MapTransform::FlipHorizontal.transform_prefab(&prefab),
+            (
+                "/obj/machinery/light",
+                Some(vec![
+                    ("dir", Literal::Number(1.)),
+                    ("pixel_x", Literal::Number(-5.)),
+                    ("name", Literal::String("meow")),
+                ])
+            )
+        );
+        assert_eq!(
+            MapTransform::Rotate90.transform_prefab(&("/turf", None)),
+            ("/turf", None)
+        );
+
+        // Offsets that aren't numbers are left as they are
+        let prefab: Prefab = (
+            "/obj/machinery/light",
+            Some(vec![
+                ("dir", Literal::Number(1.)),
+                ("pixel_x", Literal::Fallback("SOME_DEFINE")),
+                ("pixel_y", Literal::Number(5.)),
+            ]),
+        );
+        assert_eq!(
+            MapTransform::Rotate90.transform_prefab(&prefab),
+            (
+                "/obj/machinery/light",
+                Some(vec![
+                    ("dir", Literal::Number(4.)),
+                    ("pixel_x", Literal::Fallback("SOME_DEFINE")),
+                    ("pixel_y", Literal::Number(5.)),
+                ])
+            )
+        );
+    }
+
+    #[test]
+    fn test_transform_map() {
+        let mut prefabs = crate::prefabs::Prefabs::new();
+        prefabs.insert("a", vec![("/turf", None)]);
+        prefabs.insert("b", vec![("/turf", None)]);
+        let blocks = vec![((1, 1, 1), vec!["aab", "bbb"])];
+
+        let (_, rotated) = MapTransform::Rotate90.transform_map(&(prefabs, blocks));
+        let grid = TileGrid::from_blocks(&rotated, 1);
+
+        assert_eq!(grid.origin, (1, 1, 1));
+        assert_eq!(grid.size, (2, 3, 1));
+        // The top left corner ends up in the top right
+        assert_eq!(grid.get((2, 3, 1)), Some("a"));
+        assert_eq!(grid.get((2, 2, 1)), Some("a"));
+        assert_eq!(grid.get((2, 1, 1)), Some("b"));
+        assert_eq!(grid.get((1, 3, 1)), Some("b"));
+    }
+}
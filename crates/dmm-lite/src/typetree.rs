@@ -0,0 +1,264 @@
+//! A description of the object tree a map is meant to be loaded into, so paths and var names
+//! can be checked without loading the map.
+//!
+//! Two formats are supported. JSON, as a tree of nodes like SpacemanDMM's object tree dumps:
+//! ```json
+//! { "path": "/obj", "vars": ["name", { "name": "desc" }], "children": [ { "path": "/obj/item" } ] }
+//! ```
+//! Or plain text, one type per line with the vars it declares:
+//! ```text
+//! # Comments and blank lines are ignored
+//! /atom -> name, desc, icon, icon_state, dir
+//! /obj/item/gun -> fire_sound
+//! /obj/item/gun/energy
+//! ```
+//! Types inherit vars from their parents, and the parents of listed types are implied.
+use std::collections::{HashMap, HashSet};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TypeTreeError {
+    #[error("Invalid type tree JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Line {line}: {message}")]
+    Text { line: usize, message: String },
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypeTree {
+    /// Each type, with the vars it declares itself
+    types: HashMap<String, HashSet<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonVar {
+    Name(String),
+    Object { name: String },
+}
+
+#[derive(Deserialize)]
+struct JsonNode {
+    path: String,
+    #[serde(default)]
+    vars: Vec<JsonVar>,
+    #[serde(default)]
+    children: Vec<JsonNode>,
+}
+
+/// The parent type of a path. The built in types have parents that aren't part of their path.
+pub fn parent_type(path: &str) -> Option<&str> {
+    match path {
+        "/datum" | "/" | "" => None,
+        "/atom" => Some("/datum"),
+        "/atom/movable" | "/turf" | "/area" => Some("/atom"),
+        "/obj" | "/mob" => Some("/atom/movable"),
+        _ => match path.rfind('/') {
+            Some(0) => Some("/datum"),
+            Some(index) => Some(&path[..index]),
+            None => None,
+        },
+    }
+}
+
+/// Only suggest things that are reasonably close.
+fn closest<'a>(target: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (target.len() / 4).max(2);
+    candidates
+        .map(|candidate| (strsim::levenshtein(target, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+impl TypeTree {
+    pub fn new() -> Self {
+        TypeTree::default()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, TypeTreeError> {
+        fn add_node(tree: &mut TypeTree, node: JsonNode) {
+            tree.add_type(
+                &node.path,
+                node.vars.into_iter().map(|var| match var {
+                    JsonVar::Name(name) | JsonVar::Object { name } => name,
+                }),
+            );
+            for child in node.children {
+                add_node(tree, child);
+            }
+        }
+
+        let mut tree = TypeTree::new();
+        // Either a single root node, or a list of them
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Root {
+            One(JsonNode),
+            Many(Vec<JsonNode>),
+        }
+        match serde_json::from_str(json)? {
+            Root::One(node) => add_node(&mut tree, node),
+            Root::Many(nodes) => nodes.into_iter().for_each(|node| add_node(&mut tree, node)),
+        }
+        Ok(tree)
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, TypeTreeError> {
+        let mut tree = TypeTree::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (path, vars) = match line.split_once("->") {
+                Some((path, vars)) => (path.trim(), vars),
+                None => (line, ""),
+            };
+            if !path.starts_with('/') {
+                return Err(TypeTreeError::Text {
+                    line: index + 1,
+                    message: format!("Expected a path, got {path:?}"),
+                });
+            }
+            tree.add_type(
+                path,
+                vars.split(',')
+                    .map(str::trim)
+                    .filter(|var| !var.is_empty())
+                    .map(str::to_owned),
+            );
+        }
+        Ok(tree)
+    }
+
+    /// Adds a type and the vars it declares. Its parents are added too, if they weren't already.
+    pub fn add_type(&mut self, path: &str, vars: impl IntoIterator<Item = String>) {
+        let path = path.trim_end_matches('/');
+        self.types.entry(path.to_owned()).or_default().extend(vars);
+
+        let mut parent = parent_type(path);
+        while let Some(path) = parent {
+            if self.types.contains_key(path) {
+                break;
+            }
+            self.types.insert(path.to_owned(), HashSet::new());
+            parent = parent_type(path);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.types.contains_key(path)
+    }
+
+    /// Whether the type, or any of its parents, declares the var.
+    pub fn has_var(&self, path: &str, var: &str) -> bool {
+        self.vars_of(path).any(|declared| declared == var)
+    }
+
+    /// Every var the type has, including inherited ones.
+    pub fn vars_of<'a>(&'a self, path: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        std::iter::successors(Some(path), |path| parent_type(path))
+            .filter_map(|path| self.types.get(path))
+            .flatten()
+            .map(String::as_str)
+    }
+
+    /// The closest known type to an unknown one.
+    pub fn suggest_type(&self, path: &str) -> Option<&str> {
+        closest(path, self.types.keys().map(String::as_str))
+    }
+
+    /// The closest var the type has to an unknown one.
+    pub fn suggest_var<'a>(&'a self, path: &'a str, var: &str) -> Option<&'a str> {
+        closest(var, self.vars_of(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parent_type() {
+        assert_eq!(parent_type("/obj/item/gun"), Some("/obj/item"));
+        assert_eq!(parent_type("/obj"), Some("/atom/movable"));
+        assert_eq!(parent_type("/turf"), Some("/atom"));
+        assert_eq!(parent_type("/atom"), Some("/datum"));
+        assert_eq!(parent_type("/image"), Some("/datum"));
+        assert_eq!(parent_type("/datum"), None);
+    }
+
+    #[test]
+    fn test_from_text() {
+        let tree = TypeTree::from_text(
+            "# Types\n/atom -> name, desc, icon_state\n\n/obj/item/gun -> fire_sound\n/obj/item/gun/energy -> charge_cost\n",
+        )
+        .unwrap();
+
+        assert!(tree.contains("/obj/item/gun/energy"));
+        assert!(tree.contains("/obj/item"));
+        assert!(tree.contains("/obj"));
+        assert!(!tree.contains("/obj/item/gun/energy/lasr"));
+
+        assert!(tree.has_var("/obj/item/gun/energy", "charge_cost"));
+        assert!(tree.has_var("/obj/item/gun/energy", "fire_sound"));
+        assert!(tree.has_var("/obj/item/gun/energy", "name"));
+        assert!(!tree.has_var("/obj/item/gun", "charge_cost"));
+
+        assert!(TypeTree::from_text("obj/item").is_err());
+    }
+
+    #[test]
+    fn test_from_json() {
+        let tree = TypeTree::from_json(
+            r#"{"path": "/datum", "vars": ["tag"], "children": [
+                {"path": "/atom", "vars": [{"name": "name", "value": "null"}], "children": [
+                    {"path": "/obj/item/gun/energy/laser", "vars": ["charge_cost"]}
+                ]}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert!(tree.contains("/obj/item/gun/energy/laser"));
+        assert!(tree.has_var("/obj/item/gun/energy/laser", "tag"));
+        assert!(tree.has_var("/obj/item/gun/energy/laser", "name"));
+
+        assert!(
+            TypeTree::from_json(r#"[{"path": "/obj"}, {"path": "/mob"}]"#)
+                .unwrap()
+                .contains("/mob")
+        );
+        assert!(TypeTree::from_json("{}").is_err());
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let tree = TypeTree::from_text(
+            "/atom -> name\n/obj/machinery/door -> req_access, req_one_access\n/obj/item/gun/energy/laser",
+        )
+        .unwrap();
+
+        assert_eq!(
+            tree.suggest_type("/obj/item/gun/energy/lasr"),
+            Some("/obj/item/gun/energy/laser")
+        );
+        assert_eq!(tree.suggest_type("/mob/living/carbon/human"), None);
+        assert_eq!(
+            tree.suggest_var("/obj/machinery/door/airlock", "req_acess"),
+            Some("req_access")
+        );
+        assert_eq!(tree.suggest_var("/obj/machinery/door", "zzzzzzzz"), None);
+    }
+}
@@ -0,0 +1,589 @@
+//! Applies UpdatePaths scripts (as used by /tg/station's `tools/UpdatePaths`) to parsed maps.
+//!
+//! Each line of a script is a rule, `<match> : <replacement>`:
+//! - `/obj/old : /obj/new` renames a type, dropping its var edits
+//! - `/obj/old : /obj/new{@OLD}` renames a type, keeping its var edits
+//! - `/obj/old/@SUBTYPES : /obj/new/@SUBTYPES{@OLD}` also renames subtypes, keeping the rest of their path
+//! - `/obj/old{dir=4} : ...` only matches objects with that var edit. `@UNSET` matches objects without
+//!   the var edited, `@SET` (or `@OLD`) matches objects with any value
+//! - `/obj/old : /obj/new{@OLD; name=@SKIP; icon_state="meow"}` drops `name` and sets `icon_state`
+//! - `/obj/old : /obj/new{desc=@OLD:name}` sets `desc` to the old value of `name`
+//! - `/obj/old : /obj/new{@OLD}, /obj/other` splits an object into several
+//! - `/obj/old : @DELETE` removes the object
+//!
+//! Blank lines and lines starting with `#` are ignored. Rules are applied in order.
+use std::collections::HashMap;
+
+use thiserror::Error;
+use typed_arena::Arena;
+use winnow::{ascii::space0, Located, Parser};
+
+use crate::{
+    grid::{key_len, TileGrid},
+    prefabs::{parse_identifier, parse_literal, separate_var_list, Literal, Prefab},
+    writer::{compare_keys, write_prefab_list},
+    MapData,
+};
+
+const SUBTYPES: &str = "/@SUBTYPES";
+
+#[derive(Debug, Error)]
+#[error("Line {line}: {message}")]
+pub struct UpdateScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// A condition on a var edit, from the left hand side of a rule.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VarFilter<'s> {
+    /// The var must be edited to exactly this value
+    Equals(Literal<'s>),
+    /// The var must be edited to something
+    Set,
+    /// The var must not be edited
+    Unset,
+}
+
+/// What happens to one var, from the right hand side of a rule.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VarEdit<'s> {
+    /// `var=value`
+    Set(Literal<'s>),
+    /// `var=@SKIP`: Drop the var edit
+    Skip,
+    /// `var=@OLD`, or `var=@OLD:other_var` to take the value of a different var
+    Old(Option<&'s str>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateOutput<'s> {
+    pub path: &'s str,
+    /// Append whatever came after the matched path, for `/@SUBTYPES`
+    pub subtypes: bool,
+    /// `{@OLD}`: Start from the old var edits, instead of none
+    pub keep_old: bool,
+    pub edits: Vec<(&'s str, VarEdit<'s>)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdateAction<'s> {
+    Delete,
+    Replace(Vec<UpdateOutput<'s>>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateRule<'s> {
+    pub path: &'s str,
+    pub subtypes: bool,
+    pub filters: Vec<(&'s str, VarFilter<'s>)>,
+    pub action: UpdateAction<'s>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UpdateScript<'s> {
+    pub rules: Vec<UpdateRule<'s>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UpdateSummary {
+    /// Prefabs that had at least one object changed
+    pub changed_prefabs: usize,
+    /// Prefabs that ended up identical to another prefab, and were merged into it
+    pub merged_prefabs: usize,
+}
+
+/// Splits on `separator`, except inside strings, files, var lists and lists.
+fn split_outside(s: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if let Some(q) = quote {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                _ if c == q => quote = None,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '{' | '(' => depth += 1,
+            '}' | ')' => depth -= 1,
+            _ if c == separator && depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// A path, with `/@SUBTYPES` and the `{...}` var list split off.
+/// Var list entries are returned as either `@DIRECTIVE` or `(var, value)`.
+type PathSpec<'s> = (&'s str, bool, Vec<(Option<&'s str>, &'s str)>);
+
+fn parse_path_spec(spec: &str) -> Result<PathSpec<'_>, String> {
+    let spec = spec.trim();
+    let (path, vars) = match spec.find('{') {
+        Some(brace) => (spec[..brace].trim_end(), Some(&spec[brace..])),
+        None => (spec, None),
+    };
+
+    let (path, subtypes) = match path.strip_suffix(SUBTYPES) {
+        Some(path) => (path, true),
+        None => (path, false),
+    };
+    if !path.starts_with('/')
+        || !path
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '/')
+    {
+        return Err(format!("Invalid path {path:?}"));
+    }
+
+    let mut entries = vec![];
+    if let Some(vars) = vars {
+        let mut i = Located::new(vars);
+        let pieces =
+            separate_var_list(&mut i).map_err(|e| format!("Invalid var list {vars:?}: {e}"))?;
+        if !i.trim().is_empty() {
+            return Err(format!("Unexpected {:?} after var list", *i));
+        }
+        for mut piece in pieces {
+            if piece.starts_with('@') {
+                entries.push((None, *piece));
+                continue;
+            }
+            let key = (parse_identifier, space0, '=', space0)
+                .map(|(key, ..)| key)
+                .parse_next(&mut piece)
+                .map_err(|_| format!("Expected `var=value`, got {:?}", *piece))?;
+            entries.push((Some(key), piece.trim()));
+        }
+    }
+
+    Ok((path, subtypes, entries))
+}
+
+fn parse_value(value: &str) -> Result<Literal<'_>, String> {
+    let mut i = Located::new(value);
+    let literal = parse_literal(&mut i).map_err(|e| format!("Invalid value {value:?}: {e}"))?;
+    if !i.is_empty() {
+        return Err(format!("Unexpected {:?} after value", *i));
+    }
+    Ok(literal)
+}
+
+fn parse_rule(line: &str) -> Result<UpdateRule<'_>, String> {
+    let [left, right] = split_outside(line, ':')[..] else {
+        return Err("Expected `<match> : <replacement>`".to_owned());
+    };
+
+    let (path, subtypes, entries) = parse_path_spec(left)?;
+    let filters = entries
+        .into_iter()
+        .map(|(key, value)| {
+            let key = key.ok_or_else(|| format!("Unexpected {value} in match"))?;
+            let filter = match value {
+                "@UNSET" => VarFilter::Unset,
+                "@SET" | "@OLD" => VarFilter::Set,
+                _ => VarFilter::Equals(parse_value(value)?),
+            };
+            Ok((key, filter))
+        })
+        .collect::<Result<_, String>>()?;
+
+    let action = if right.trim() == "@DELETE" {
+        UpdateAction::Delete
+    } else {
+        let outputs = split_outside(right, ',')
+            .into_iter()
+            .map(|output| {
+                let (path, output_subtypes, entries) = parse_path_spec(output)?;
+                if output_subtypes && !subtypes {
+                    return Err(format!(
+                        "{path}{SUBTYPES} needs the match to use {SUBTYPES} too"
+                    ));
+                }
+
+                let mut keep_old = false;
+                let mut edits = vec![];
+                for (key, value) in entries {
+                    match (key, value) {
+                        (None, "@OLD") => keep_old = true,
+                        (None, _) => return Err(format!("Unexpected {value} in replacement")),
+                        (Some(key), "@SKIP") => edits.push((key, VarEdit::Skip)),
+                        (Some(key), "@OLD") => edits.push((key, VarEdit::Old(None))),
+                        (Some(key), _) => match value.strip_prefix("@OLD:") {
+                            Some(from) => edits.push((key, VarEdit::Old(Some(from.trim())))),
+                            None => edits.push((key, VarEdit::Set(parse_value(value)?))),
+                        },
+                    }
+                }
+
+                Ok(UpdateOutput {
+                    path,
+                    subtypes: output_subtypes,
+                    keep_old,
+                    edits,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        UpdateAction::Replace(outputs)
+    };
+
+    Ok(UpdateRule {
+        path,
+        subtypes,
+        filters,
+        action,
+    })
+}
+
+impl<'s> UpdateScript<'s> {
+    pub fn parse(script: &'s str) -> Result<Self, UpdateScriptError> {
+        let rules = script
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(index, line)| {
+                parse_rule(line).map_err(|message| UpdateScriptError {
+                    line: index + 1,
+                    message,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(UpdateScript { rules })
+    }
+
+    /// Runs every rule over a list of objects, in order. Returns `None` if nothing matched.
+    pub fn apply_to_prefab_list(
+        &self,
+        prefab_list: &[Prefab<'s>],
+        arena: &'s Arena<String>,
+    ) -> Option<Vec<Prefab<'s>>> {
+        let mut current: Option<Vec<Prefab<'s>>> = None;
+        for rule in &self.rules {
+            let list = current.as_deref().unwrap_or(prefab_list);
+            if let Some(updated) = rule.apply_to_prefab_list(list, arena) {
+                current = Some(updated);
+            }
+        }
+        current
+    }
+
+    /// Runs the script over a whole map, then merges any prefabs that became identical.
+    pub fn apply_to_map(
+        &self,
+        (prefabs, blocks): MapData<'s>,
+        arena: &'s Arena<String>,
+    ) -> (MapData<'s>, UpdateSummary) {
+        let mut summary = UpdateSummary::default();
+        let mut changed_keys = vec![];
+        let mut prefabs = prefabs;
+        for (key, prefab_list) in prefabs.iter_mut() {
+            if let Some(updated) = self.apply_to_prefab_list(prefab_list, arena) {
+                *prefab_list = updated;
+                changed_keys.push(*key);
+            }
+        }
+        summary.changed_prefabs = changed_keys.len();
+        if changed_keys.is_empty() {
+            return ((prefabs, blocks), summary);
+        }
+
+        // Written out, identical prefabs are identical strings
+        let mut by_contents: HashMap<String, Vec<&'s str>> = HashMap::new();
+        for (key, prefab_list) in prefabs.iter() {
+            let mut contents = String::new();
+            write_prefab_list(&mut contents, prefab_list, false)
+                .expect("Writing to a String can't fail");
+            by_contents.entry(contents).or_default().push(key);
+        }
+
+        let mut replacements: HashMap<&'s str, &'s str> = HashMap::new();
+        for mut keys in by_contents.into_values() {
+            if keys.len() < 2 || !keys.iter().any(|key| changed_keys.contains(key)) {
+                continue;
+            }
+            keys.sort_by(|a, b| compare_keys(a, b));
+            for duplicate in &keys[1..] {
+                replacements.insert(duplicate, keys[0]);
+            }
+        }
+        if replacements.is_empty() {
+            return ((prefabs, blocks), summary);
+        }
+        summary.merged_prefabs = replacements.len();
+
+        let mut grid = TileGrid::from_blocks(&blocks, key_len(&prefabs));
+        for (coord, key) in grid.iter().collect::<Vec<_>>() {
+            if let Some(replacement) = replacements.get(key) {
+                grid.set(coord, replacement);
+            }
+        }
+        prefabs.retain(|key, _| !replacements.contains_key(key));
+
+        ((prefabs, grid.to_blocks()), summary)
+    }
+}
+
+impl<'s> UpdateRule<'s> {
+    /// If the path matches, returns what comes after the matched part: empty for an exact match,
+    /// or the rest of the path for a subtype.
+    fn match_path(&self, path: &'s str) -> Option<&'s str> {
+        let rest = path.strip_prefix(self.path)?;
+        if rest.is_empty() || (self.subtypes && rest.starts_with('/')) {
+            Some(rest)
+        } else {
+            None
+        }
+    }
+
+    fn matches_vars(&self, vars: &[(&'s str, Literal<'s>)]) -> bool {
+        self.filters.iter().all(|(key, filter)| {
+            let value = vars.iter().find(|(var, _)| var == key).map(|(_, v)| v);
+            match (filter, value) {
+                (VarFilter::Equals(expected), Some(value)) => expected == value,
+                (VarFilter::Equals(_), None) => false,
+                (VarFilter::Set, value) => value.is_some(),
+                (VarFilter::Unset, value) => value.is_none(),
+            }
+        })
+    }
+
+    /// Applies this rule to every matching object. Returns `None` if nothing matched.
+    pub fn apply_to_prefab_list(
+        &self,
+        prefab_list: &[Prefab<'s>],
+        arena: &'s Arena<String>,
+    ) -> Option<Vec<Prefab<'s>>> {
+        let mut matched = false;
+        let mut updated = Vec::with_capacity(prefab_list.len());
+        for prefab in prefab_list {
+            let (path, vars) = prefab;
+            let old_vars = vars.as_deref().unwrap_or_default();
+            let suffix = match self.match_path(path) {
+                Some(suffix) if self.matches_vars(old_vars) => suffix,
+                _ => {
+                    updated.push(prefab.clone());
+                    continue;
+                }
+            };
+            matched = true;
+
+            let UpdateAction::Replace(outputs) = &self.action else {
+                continue;
+            };
+            for output in outputs {
+                let new_path: &'s str = if output.subtypes && !suffix.is_empty() {
+                    arena.alloc(format!("{}{suffix}", output.path))
+                } else {
+                    output.path
+                };
+                updated.push((new_path, output.apply_to_vars(old_vars)));
+            }
+        }
+
+        matched.then_some(updated)
+    }
+}
+
+impl<'s> UpdateOutput<'s> {
+    fn apply_to_vars(
+        &self,
+        old_vars: &[(&'s str, Literal<'s>)],
+    ) -> Option<Vec<(&'s str, Literal<'s>)>> {
+        let old_value = |key: &str| {
+            old_vars
+                .iter()
+                .find(|(var, _)| *var == key)
+                .map(|(_, value)| value.clone())
+        };
+
+        let mut vars = if self.keep_old {
+            old_vars.to_vec()
+        } else {
+            vec![]
+        };
+        for (key, edit) in &self.edits {
+            let value = match edit {
+                VarEdit::Set(value) => Some(value.clone()),
+                VarEdit::Skip => None,
+                VarEdit::Old(from) => old_value(from.unwrap_or(key)),
+            };
+            let existing = vars.iter().position(|(var, _)| var == key);
+            match (existing, value) {
+                (Some(index), Some(value)) => vars[index].1 = value,
+                (Some(index), None) => {
+                    vars.remove(index);
+                }
+                (None, Some(value)) => vars.push((key, value)),
+                (None, None) => {}
+            }
+        }
+
+        (!vars.is_empty()).then_some(vars)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prefabs::Prefabs;
+
+    #[test]
+    fn test_parse_script() {
+        let script = UpdateScript::parse(
+            "# Comment\n\n/obj/old/@SUBTYPES{dir=4; name=@UNSET} : /obj/new/@SUBTYPES{@OLD; desc=@OLD:name; icon_state=@SKIP}, /obj/other\n/obj/gone : @DELETE\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            script.rules,
+            vec![
+                UpdateRule {
+                    path: "/obj/old",
+                    subtypes: true,
+                    filters: vec![
+                        ("dir", VarFilter::Equals(Literal::Number(4.))),
+                        ("name", VarFilter::Unset)
+                    ],
+                    action: UpdateAction::Replace(vec![
+                        UpdateOutput {
+                            path: "/obj/new",
+                            subtypes: true,
+                            keep_old: true,
+                            edits: vec![
+                                ("desc", VarEdit::Old(Some("name"))),
+                                ("icon_state", VarEdit::Skip)
+                            ],
+                        },
+                        UpdateOutput {
+                            path: "/obj/other",
+                            subtypes: false,
+                            keep_old: false,
+                            edits: vec![],
+                        }
+                    ]),
+                },
+                UpdateRule {
+                    path: "/obj/gone",
+                    subtypes: false,
+                    filters: vec![],
+                    action: UpdateAction::Delete,
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_script_errors() {
+        let error = UpdateScript::parse("/obj/fine : /obj/new\nobj/old : /obj/new").unwrap_err();
+        assert_eq!(error.line, 2);
+
+        assert!(UpdateScript::parse("/obj/old /obj/new").is_err());
+        assert!(UpdateScript::parse("/obj/old : /obj/new/@SUBTYPES").is_err());
+        assert!(UpdateScript::parse("/obj/old : /obj/new{@DELETE}").is_err());
+    }
+
+    #[test]
+    fn test_strings_containing_separators() {
+        let script = UpdateScript::parse(r#"/obj/old : /obj/new{name="a: b, c; \"d\""}"#).unwrap();
+        let UpdateAction::Replace(outputs) = &script.rules[0].action else {
+            panic!("Expected a replacement");
+        };
+        assert_eq!(
+            outputs[0].edits,
+            vec![("name", VarEdit::Set(Literal::String(r#"a: b, c; \"d\""#)))]
+        );
+    }
+
+    #[test]
+    fn test_apply_to_prefab_list() {
+        let arena = Arena::new();
+        let script = UpdateScript::parse(
+            "/obj/old/@SUBTYPES{dir=4} : /obj/new/@SUBTYPES{@OLD; dir=@SKIP; desc=@OLD:name}\n/obj/gone : @DELETE",
+        )
+        .unwrap();
+
+        let prefab_list = vec![
+            (
+                "/obj/old/thing",
+                Some(vec![
+                    ("dir", Literal::Number(4.)),
+                    ("name", Literal::String("meow")),
+                ]),
+            ),
+            ("/obj/old", Some(vec![("dir", Literal::Number(8.))])),
+            ("/obj/older", Some(vec![("dir", Literal::Number(4.))])),
+            ("/obj/gone", None),
+            ("/turf", None),
+            ("/area", None),
+        ];
+
+        assert_eq!(
+            script.apply_to_prefab_list(&prefab_list, &arena),
+            Some(vec![
+                (
+                    "/obj/new/thing",
+                    Some(vec![
+                        ("name", Literal::String("meow")),
+                        ("desc", Literal::String("meow")),
+                    ]),
+                ),
+                ("/obj/old", Some(vec![("dir", Literal::Number(8.))])),
+                ("/obj/older", Some(vec![("dir", Literal::Number(4.))])),
+                ("/turf", None),
+                ("/area", None),
+            ])
+        );
+
+        assert_eq!(
+            script.apply_to_prefab_list(&[("/turf", None), ("/area", None)], &arena),
+            None
+        );
+    }
+
+    #[test]
+    fn test_apply_to_map_merges() {
+        let arena = Arena::new();
+        let script = UpdateScript::parse("/obj/old : /obj/new").unwrap();
+
+        let mut prefabs = Prefabs::new();
+        prefabs.insert("a", vec![("/obj/new", None), ("/turf", None)]);
+        prefabs.insert(
+            "b",
+            vec![
+                ("/obj/old", Some(vec![("name", Literal::String("meow"))])),
+                ("/turf", None),
+            ],
+        );
+        prefabs.insert("c", vec![("/turf", None)]);
+        let blocks = vec![((1, 1, 1), vec!["abc"])];
+
+        let ((prefabs, blocks), summary) = script.apply_to_map((prefabs, blocks), &arena);
+
+        assert_eq!(
+            summary,
+            UpdateSummary {
+                changed_prefabs: 1,
+                merged_prefabs: 1
+            }
+        );
+        assert_eq!(prefabs.len(), 2);
+        assert!(!prefabs.contains_key("b"));
+        let grid = TileGrid::from_blocks(&blocks, 1);
+        assert_eq!(grid.get((1, 1, 1)), Some("a"));
+        assert_eq!(grid.get((2, 1, 1)), Some("a"));
+        assert_eq!(grid.get((3, 1, 1)), Some("c"));
+    }
+}
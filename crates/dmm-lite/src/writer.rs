@@ -0,0 +1,334 @@
+//! Writes parsed maps back out, in either DMM or TGM format.
+use std::{cmp::Ordering, fmt::Write};
+
+use thiserror::Error;
+
+use crate::{
+    grid::{key_len, Coord, TileGrid},
+    prefabs::{Literal, Prefab},
+    MapData,
+};
+
+/// The header dmm2tgm.py (and mapmerge after it) puts at the top of TGM files.
+pub const TGM_HEADER: &str =
+    "//MAP CONVERTED BY dmm2tgm.py THIS HEADER COMMENT PREVENTS RECONVERSION, DO NOT REMOVE";
+
+/// The order characters are used in when generating keys, so we sort keys the same way.
+const KEY_CHARACTERS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+#[derive(Debug, Error)]
+pub enum WriteError {
+    #[error("No tile at {0:?}, DMM blocks must cover the whole map")]
+    MissingTile(Coord),
+    #[error("Prefab key {0:?} is used by the map but never defined")]
+    UndefinedKey(String),
+    #[error(transparent)]
+    Fmt(#[from] std::fmt::Error),
+}
+
+/// Compares two prefab keys in key generation order (`a` through `z`, then `A` through `Z`).
+pub fn compare_keys(a: &str, b: &str) -> Ordering {
+    let position = |c: char| KEY_CHARACTERS.find(c).unwrap_or(usize::MAX);
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.chars().map(position).cmp(b.chars().map(position)))
+}
+
+/// Writes a whole map. Prefabs are sorted by key.
+pub fn write_map(is_tgm: bool, (prefabs, blocks): &MapData) -> Result<String, WriteError> {
+    let mut out = String::new();
+    if is_tgm {
+        writeln!(out, "{TGM_HEADER}")?;
+    }
+
+    let mut keys: Vec<&&str> = prefabs.keys().collect();
+    keys.sort_by(|a, b| compare_keys(a, b));
+    for key in keys {
+        write!(out, "\"{key}\" = ")?;
+        write_prefab_list(&mut out, &prefabs[*key], is_tgm)?;
+        out.push('\n');
+    }
+    out.push('\n');
+
+    let grid = TileGrid::from_blocks(blocks, key_len(prefabs));
+    if let Some((_, key)) = grid.iter().find(|(_, key)| !prefabs.contains_key(key)) {
+        return Err(WriteError::UndefinedKey(key.to_owned()));
+    }
+    if is_tgm {
+        write_tgm_blocks(&mut out, &grid)?;
+    } else {
+        write_dmm_blocks(&mut out, &grid)?;
+    }
+
+    Ok(out)
+}
+
+/// TGM: One block per column
+fn write_tgm_blocks(out: &mut String, grid: &TileGrid) -> Result<(), WriteError> {
+    for ((x, y, z), column) in grid.to_blocks() {
+        writeln!(out, "({x},{y},{z}) = {{\"")?;
+        for key in column {
+            writeln!(out, "{key}")?;
+        }
+        writeln!(out, "\"}}")?;
+    }
+    Ok(())
+}
+
+/// DMM: One block per z-level
+fn write_dmm_blocks(out: &mut String, grid: &TileGrid) -> Result<(), WriteError> {
+    if grid.is_empty() {
+        return Ok(());
+    }
+    let (min_x, min_y, min_z) = grid.origin;
+    let (max_x, max_y, max_z) = grid.max();
+    for z in min_z..=max_z {
+        writeln!(out, "({min_x},{min_y},{z}) = {{\"")?;
+        for y in (min_y..=max_y).rev() {
+            for x in min_x..=max_x {
+                let key = grid
+                    .get((x, y, z))
+                    .ok_or(WriteError::MissingTile((x, y, z)))?;
+                out.push_str(key);
+            }
+            out.push('\n');
+        }
+        writeln!(out, "\"}}")?;
+    }
+    Ok(())
+}
+
+/// Writes the `(...)` half of a prefab definition.
+pub fn write_prefab_list<W: Write>(
+    out: &mut W,
+    prefab_list: &[Prefab],
+    is_tgm: bool,
+) -> std::fmt::Result {
+    out.write_char('(')?;
+    for (i, prefab) in prefab_list.iter().enumerate() {
+        if i > 0 {
+            out.write_char(',')?;
+        }
+        if is_tgm {
+            out.write_char('\n')?;
+        }
+        write_prefab(out, prefab, is_tgm)?;
+    }
+    out.write_char(')')
+}
+
+/// Writes a path and its var edits.
+pub fn write_prefab<W: Write>(
+    out: &mut W,
+    (path, vars): &Prefab,
+    is_tgm: bool,
+) -> std::fmt::Result {
+    out.write_str(path)?;
+    match vars {
+        Some(vars) => write_var_list(out, vars, is_tgm),
+        None => Ok(()),
+    }
+}
+
+/// Writes the `{...}` var edits of a prefab.
+pub fn write_var_list<W: Write>(
+    out: &mut W,
+    vars: &[(&str, Literal)],
+    is_tgm: bool,
+) -> std::fmt::Result {
+    out.write_char('{')?;
+    for (i, (key, value)) in vars.iter().enumerate() {
+        match (is_tgm, i) {
+            (true, 0) => out.write_str("\n\t")?,
+            (true, _) => out.write_str(";\n\t")?,
+            (false, 0) => {}
+            (false, _) => out.write_str("; ")?,
+        }
+        write!(out, "{key} = ")?;
+        write_literal(out, value)?;
+    }
+    if is_tgm {
+        out.write_str("\n\t")?;
+    }
+    out.write_char('}')
+}
+
+/// Writes a value. Strings and files are written as they were parsed, which means they keep their escapes.
+pub fn write_literal<W: Write>(out: &mut W, literal: &Literal) -> std::fmt::Result {
+    match literal {
+        Literal::Number(n) => write_number(out, *n),
+        Literal::String(s) => write!(out, "\"{s}\""),
+        Literal::Path(p) => out.write_str(p),
+        Literal::File(f) => write!(out, "'{f}'"),
+        Literal::Null => out.write_str("null"),
+        Literal::Fallback(s) => out.write_str(s),
+        Literal::List(list) => {
+            out.write_str("list(")?;
+            for (i, item) in list.iter().enumerate() {
+                if i > 0 {
+                    out.write_char(',')?;
+                }
+                write_literal(out, item)?;
+            }
+            out.write_char(')')
+        }
+        Literal::AssocList(list) => {
+            out.write_str("list(")?;
+            for (i, (key, value)) in list.iter().enumerate() {
+                if i > 0 {
+                    out.write_char(',')?;
+                }
+                write_literal(out, key)?;
+                out.write_char('=')?;
+                write_literal(out, value)?;
+            }
+            out.write_char(')')
+        }
+    }
+}
+
+/// Writes a number the way BYOND does: integers as-is, and big or tiny numbers with a three digit exponent, like `5e+006`.
+pub fn write_number<W: Write>(out: &mut W, n: f32) -> std::fmt::Result {
+    let magnitude = n.abs();
+    if magnitude != 0. && !(1e-4..1e6).contains(&magnitude) {
+        // Six significant figures, like printf's %g
+        let formatted = format!("{n:.5e}");
+        let (mantissa, exponent) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+        let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+        let exponent: i32 = exponent.parse().unwrap_or(0);
+        let sign = if exponent < 0 { '-' } else { '+' };
+        write!(out, "{mantissa}e{sign}{:03}", exponent.abs())
+    } else if n.fract() == 0. {
+        write!(out, "{}", n as i64)
+    } else {
+        write!(out, "{n}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prefabs::Prefabs;
+
+    #[test]
+    fn test_compare_keys() {
+        assert_eq!(compare_keys("aa", "ab"), Ordering::Less);
+        assert_eq!(compare_keys("az", "aA"), Ordering::Less);
+        assert_eq!(compare_keys("aZ", "ba"), Ordering::Less);
+        assert_eq!(compare_keys("Z", "aa"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_write_number() {
+        let number = |n: f32| {
+            let mut out = String::new();
+            write_number(&mut out, n).unwrap();
+            out
+        };
+
+        assert_eq!(number(4.), "4");
+        assert_eq!(number(-7.), "-7");
+        assert_eq!(number(2.6), "2.6");
+        assert_eq!(number(0.), "0");
+        assert_eq!(number(5e6), "5e+006");
+        assert_eq!(number(2.5e6), "2.5e+006");
+        assert_eq!(number(1.5e-5), "1.5e-005");
+    }
+
+    #[test]
+    fn test_write_literal() {
+        let literal = |l: Literal| {
+            let mut out = String::new();
+            write_literal(&mut out, &l).unwrap();
+            out
+        };
+
+        assert_eq!(literal(Literal::String(r#"me\"ow"#)), r#""me\"ow""#);
+        assert_eq!(literal(Literal::File("icons/obj.dmi")), "'icons/obj.dmi'");
+        assert_eq!(
+            literal(Literal::List(vec![
+                Literal::String("ss13"),
+                Literal::Number(1.)
+            ])),
+            r#"list("ss13",1)"#
+        );
+        assert_eq!(
+            literal(Literal::AssocList(vec![(
+                Literal::String("n2_sensor"),
+                Literal::Path("/obj/item")
+            )])),
+            r#"list("n2_sensor"=/obj/item)"#
+        );
+    }
+
+    #[test]
+    fn test_write_map() {
+        let mut prefabs = Prefabs::new();
+        prefabs.insert(
+            "b",
+            vec![
+                (
+                    "/obj/thing",
+                    Some(vec![
+                        ("dir", Literal::Number(4.)),
+                        ("name", Literal::String("meow")),
+                    ]),
+                ),
+                ("/turf", None),
+                ("/area", None),
+            ],
+        );
+        prefabs.insert("a", vec![("/turf", None), ("/area", None)]);
+        let data = (prefabs, vec![((1, 1, 1), vec!["ab", "ba"])]);
+
+        assert_eq!(
+            write_map(false, &data).unwrap(),
+            r#""a" = (/turf,/area)
+"b" = (/obj/thing{dir = 4; name = "meow"},/turf,/area)
+
+(1,1,1) = {"
+ab
+ba
+"}
+"#
+        );
+        assert_eq!(
+            write_map(true, &data).unwrap(),
+            format!(
+                r#"{TGM_HEADER}
+"a" = (
+/turf,
+/area)
+"b" = (
+/obj/thing{{
+	dir = 4;
+	name = "meow"
+	}},
+/turf,
+/area)
+
+(1,1,1) = {{"
+a
+b
+"}}
+(2,1,1) = {{"
+b
+a
+"}}
+"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_write_map_missing_key() {
+        let mut prefabs = Prefabs::new();
+        prefabs.insert("a", vec![("/turf", None), ("/area", None)]);
+        let data = (prefabs, vec![((1, 1, 1), vec!["ab"])]);
+
+        assert!(matches!(
+            write_map(false, &data),
+            Err(WriteError::UndefinedKey(key)) if key == "b"
+        ));
+    }
+}
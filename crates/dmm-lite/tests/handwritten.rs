@@ -1,7 +1,11 @@
 use dmm_lite::{
     block::{get_block_locations, parse_block},
+    canonical::format_map,
+    grid::TileGrid,
     parse_map_multithreaded,
     prefabs::{detect_tgm, get_prefab_locations, parse_prefab_line, Literal},
+    transform::MapTransform,
+    writer::write_map,
 };
 use winnow::{Located, Parser};
 
@@ -179,3 +183,71 @@ fn full_parse() {
     assert_eq!(tgm_prefabs.len(), 3);
     assert_eq!(tgm_blocks.len(), 3);
 }
+
+#[test]
+fn full_parse_rotated() {
+    let meow = std::fs::read_to_string("./tests/maps/handwritten.dmm").unwrap();
+
+    let (_meta, data) = parse_map_multithreaded("Meow".to_owned(), &meow).unwrap();
+    let (prefabs, blocks) = MapTransform::Rotate90.transform_map(&data);
+    assert_eq!(prefabs, data.0);
+
+    // The top row ends up as the right column
+    let grid = TileGrid::from_blocks(&blocks, 3);
+    assert_eq!(grid.size, (3, 3, 1));
+    assert_eq!(grid.get((3, 3, 1)), Some("aaa"));
+    assert_eq!(grid.get((3, 2, 1)), Some("aab"));
+    assert_eq!(grid.get((3, 1, 1)), Some("aac"));
+    assert_eq!(grid.get((1, 1, 1)), Some("aac"));
+}
+
+#[test]
+fn write_roundtrip() {
+    let meow = std::fs::read_to_string("./tests/maps/handwritten.dmm").unwrap();
+    let meow_tgm = std::fs::read_to_string("./tests/maps/handwritten-tgm.dmm").unwrap();
+
+    // The handwritten TGM map is already in the format we write
+    let (_, tgm_data) = parse_map_multithreaded("meow".to_owned(), &meow_tgm).unwrap();
+    assert_eq!(write_map(true, &tgm_data).unwrap(), meow_tgm);
+
+    let (_, (prefabs, blocks)) = parse_map_multithreaded("meow".to_owned(), &meow).unwrap();
+    let written = write_map(false, &(prefabs.clone(), blocks.clone())).unwrap();
+    assert_eq!(
+        written,
+        r#""aaa" = (/turf/space{name = "meow"},/area/space)
+"aab" = (/turf/simulated/floor,/area/space)
+"aac" = (/turf/simulated/floor/tile,/area/space)
+
+(1,1,1) = {"
+aaaaabaac
+aaaaabaac
+aaaaabaac
+"}
+"#
+    );
+
+    let (meta, (written_prefabs, written_blocks)) =
+        parse_map_multithreaded("meow".to_owned(), &written).unwrap();
+    assert!(!meta.is_tgm);
+    assert_eq!(written_prefabs, prefabs);
+    assert_eq!(
+        TileGrid::from_blocks(&written_blocks, 3),
+        TileGrid::from_blocks(&blocks, 3)
+    );
+}
+
+#[test]
+fn format_idempotent() {
+    for file in [
+        "./tests/maps/handwritten.dmm",
+        "./tests/maps/handwritten-tgm.dmm",
+        "./tests/maps/MetaStation.dmm",
+    ] {
+        let map = std::fs::read_to_string(file).unwrap();
+        let (meta, data) = parse_map_multithreaded("meow".to_owned(), &map).unwrap();
+        let formatted = format_map(meta.is_tgm, &data).unwrap();
+
+        let (meta, data) = parse_map_multithreaded("meow".to_owned(), &formatted).unwrap();
+        assert_eq!(format_map(meta.is_tgm, &data).unwrap(), formatted, "{file}");
+    }
+}
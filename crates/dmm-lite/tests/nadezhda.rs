@@ -1,7 +1,9 @@
 use dmm_lite::{
     block::{get_block_locations, parse_block},
+    grid::TileGrid,
     parse_map_multithreaded,
     prefabs::{detect_tgm, get_prefab_locations, parse_prefab_line, Literal},
+    writer::write_map,
 };
 use winnow::{Located, Parser as _};
 
@@ -209,3 +211,24 @@ fn full_parse() {
     assert_eq!(tgm_prefabs.len(), 14980);
     assert_eq!(tgm_blocks.len(), 200 * 3);
 }
+
+#[test]
+fn write_roundtrip() {
+    for file in ["./tests/maps/nadezhda.dmm", "./tests/maps/nadezhda-tgm.dmm"] {
+        let map = std::fs::read_to_string(file).unwrap();
+        let (meta, (prefabs, blocks)) =
+            parse_map_multithreaded("nadezhda".to_owned(), &map).unwrap();
+
+        let written = write_map(meta.is_tgm, &(prefabs.clone(), blocks.clone())).unwrap();
+        let (written_meta, (written_prefabs, written_blocks)) =
+            parse_map_multithreaded("nadezhda".to_owned(), &written).unwrap();
+
+        assert_eq!(written_meta.is_tgm, meta.is_tgm);
+        assert_eq!(written_prefabs, prefabs, "{file} prefabs didn't roundtrip");
+        assert_eq!(
+            TileGrid::from_blocks(&written_blocks, 3),
+            TileGrid::from_blocks(&blocks, 3),
+            "{file} blocks didn't roundtrip"
+        );
+    }
+}
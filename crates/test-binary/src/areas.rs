@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+
+use dmm_lite::{
+    areas::analyze_areas,
+    grid::{key_len, TileGrid},
+    parse_map_multithreaded,
+};
+
+use serde_json::{json, Value};
+
+use crate::{is_map, json::print_json, map_name};
+
+pub fn run(files: &[PathBuf], dot: bool, json: bool) -> anyhow::Result<()> {
+    let mut results = vec![];
+    for file in files {
+        if !is_map(file) {
+            continue;
+        }
+
+        let string = std::fs::read_to_string(file)?;
+        let (_, (prefabs, blocks)) = match parse_map_multithreaded(map_name(file), &string) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!("\x1b[31mFAILED Parsing {file:#?}\x1b[0m");
+                e.debug_print(&string);
+                continue;
+            }
+        };
+
+        let grid = TileGrid::from_blocks(&blocks, key_len(&prefabs));
+        let graph = analyze_areas(&prefabs, &grid);
+        if dot {
+            print!("{}", graph.to_dot(&map_name(file)));
+            continue;
+        }
+        if json {
+            results.push(json!({
+                "map": file,
+                "areas": graph.areas,
+                "borders": graph.borders,
+            }));
+            continue;
+        }
+
+        println!(
+            "{file:#?}: {} areas, {} borders",
+            graph.areas.len(),
+            graph.borders.len()
+        );
+        for area in graph.areas.iter().filter(|area| area.is_split()) {
+            let sizes: Vec<_> = area.islands.iter().map(|island| island.len()).collect();
+            println!(
+                "  {} is split into {} pieces of {sizes:?} tiles",
+                area.path,
+                area.islands.len()
+            );
+            let isolated: Vec<_> = area.isolated_tiles().collect();
+            if !isolated.is_empty() {
+                println!("    Isolated tiles: {isolated:?}");
+            }
+        }
+    }
+
+    if json {
+        print_json(&Value::Array(results), true)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail};
+use dmm_lite::{canonical::format_map, parse_map_multithreaded};
+
+use crate::{is_map, map_name};
+
+pub fn run(files: &[PathBuf], check: bool) -> anyhow::Result<()> {
+    let mut unformatted = 0;
+    for file in files {
+        if !is_map(file) {
+            continue;
+        }
+
+        let string = std::fs::read_to_string(file)?;
+        let (info, data) = match parse_map_multithreaded(map_name(file), &string) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!("\x1b[31mFAILED Parsing {file:#?}\x1b[0m");
+                e.debug_print(&string);
+                bail!("Failed to parse {file:#?}");
+            }
+        };
+
+        let formatted = format_map(info.is_tgm, &data)
+            .map_err(|e| anyhow!("Failed to write {file:#?}: {e}"))?;
+        if formatted == string {
+            continue;
+        }
+
+        unformatted += 1;
+        if check {
+            println!("\x1b[33m{file:#?} is not formatted\x1b[0m");
+        } else {
+            std::fs::write(file, formatted)?;
+            println!("\x1b[32mFormatted {file:#?}\x1b[0m");
+        }
+    }
+
+    if check && unformatted > 0 {
+        bail!("{unformatted} maps are not formatted");
+    }
+    Ok(())
+}
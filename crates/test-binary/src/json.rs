@@ -0,0 +1,30 @@
+use dmm_lite::{
+    prefabs::{Literal, Prefab},
+    writer::write_literal,
+};
+use serde_json::{json, Map, Value};
+
+/// Values are kept as DM source text, so strings, paths and files stay distinguishable.
+pub fn literal_to_json(literal: &Literal) -> Value {
+    let mut out = String::new();
+    write_literal(&mut out, literal).expect("Writing to a String can't fail");
+    Value::String(out)
+}
+
+pub fn prefab_to_json((path, vars): &Prefab) -> Value {
+    let vars: Map<String, Value> = vars
+        .iter()
+        .flatten()
+        .map(|(key, value)| (key.to_string(), literal_to_json(value)))
+        .collect();
+    json!({ "path": path, "vars": vars })
+}
+
+pub fn print_json(value: &Value, pretty: bool) -> anyhow::Result<()> {
+    if pretty {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    } else {
+        println!("{}", serde_json::to_string(value)?);
+    }
+    Ok(())
+}
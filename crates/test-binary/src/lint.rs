@@ -0,0 +1,87 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context};
+use dmm_lite::{
+    grid::{key_len, TileGrid},
+    lint::{LintConfig, LintContext, Linter, Severity},
+    parse_map_multithreaded,
+    typetree::TypeTree,
+};
+
+use crate::{is_map, map_name};
+
+fn load_type_tree(path: &Path) -> anyhow::Result<TypeTree> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read type tree {path:#?}"))?;
+    let tree = if path.extension().is_some_and(|ext| ext == "json") {
+        TypeTree::from_json(&text)?
+    } else {
+        TypeTree::from_text(&text)?
+    };
+    Ok(tree)
+}
+
+pub fn run(
+    files: &[PathBuf],
+    config: Option<&Path>,
+    types: Option<&Path>,
+    root: Option<&Path>,
+) -> anyhow::Result<()> {
+    let config = match config {
+        Some(path) => LintConfig::parse(
+            &std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read lint config {path:#?}"))?,
+        )?,
+        None => LintConfig::default(),
+    };
+    let linter = Linter::from_config(&config)?;
+    let type_tree = types.map(load_type_tree).transpose()?;
+
+    let mut errors = 0;
+    let mut warnings = 0;
+    for file in files {
+        if !is_map(file) {
+            continue;
+        }
+
+        let string = std::fs::read_to_string(file)?;
+        let (_, (prefabs, blocks)) = match parse_map_multithreaded(map_name(file), &string) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!("\x1b[31mFAILED Parsing {file:#?}\x1b[0m");
+                e.debug_print(&string);
+                errors += 1;
+                continue;
+            }
+        };
+
+        let grid = TileGrid::from_blocks(&blocks, key_len(&prefabs));
+        let mut context = LintContext::new(&string, &prefabs, &grid);
+        if let Some(tree) = &type_tree {
+            context = context.with_type_tree(tree);
+        }
+        if let Some(root) = root {
+            context = context.with_project_root(root);
+        }
+        let diagnostics = linter.run(&context);
+
+        let source: Arc<str> = Arc::from(string.as_str());
+        let name = file.to_string_lossy();
+        for diagnostic in &diagnostics {
+            match diagnostic.severity {
+                Severity::Error => errors += 1,
+                _ => warnings += 1,
+            }
+            eprintln!("{:?}", diagnostic.to_report(&name, source.clone()));
+        }
+    }
+
+    println!("{errors} errors, {warnings} warnings");
+    if errors > 0 {
+        bail!("Lint failed with {errors} errors");
+    }
+    Ok(())
+}
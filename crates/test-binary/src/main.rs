@@ -1,34 +1,184 @@
 use std::path::PathBuf;
 
-use clap::Parser;
-use dmm_lite::parse_map_multithreaded;
+use clap::{Parser, Subcommand};
+use dmm_lite::{parse_map_multithreaded, render::minimap::ColorBy};
+
+mod areas;
+mod format;
+mod json;
+mod lint;
+mod networks;
+mod query;
+mod render;
+mod stats;
+mod update_paths;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Maps to test parsing on, when no subcommand is given
     files: Vec<PathBuf>,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Applies an UpdatePaths script to maps, rewriting them in place
+    UpdatePaths {
+        script: PathBuf,
+        files: Vec<PathBuf>,
+    },
+    /// Rewrites maps in canonical form
+    Fmt {
+        files: Vec<PathBuf>,
+        /// Don't write anything, just fail if any map isn't in canonical form
+        #[arg(long)]
+        check: bool,
+    },
+    /// Finds objects on maps, printing the matching tiles as JSON
+    ///
+    /// Example: `istype:/obj/machinery/door/airlock and var:req_access and not area:/area/maintenance`
+    Query {
+        query: String,
+        files: Vec<PathBuf>,
+        /// Pretty print the JSON
+        #[arg(long)]
+        pretty: bool,
+    },
+    /// Lints maps, failing if any errors are found
+    Lint {
+        files: Vec<PathBuf>,
+        /// TOML file setting rule severities and options
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Type tree to check paths and vars against, as JSON or `path -> vars` text
+        #[arg(long)]
+        types: Option<PathBuf>,
+        /// Project directory that file literals are relative to, to check they exist
+        #[arg(long)]
+        root: Option<PathBuf>,
+    },
+    /// Shows which areas border which, and which areas are split into pieces
+    Areas {
+        files: Vec<PathBuf>,
+        /// Print each area graph in Graphviz's DOT format, named after its map
+        #[arg(long, conflicts_with = "json")]
+        dot: bool,
+        /// Print the area graphs as one JSON array, with the map each came from
+        #[arg(long)]
+        json: bool,
+    },
+    /// Traces cables, pipes and other networks, reporting loose ends and unconnected machines
+    Networks {
+        /// TOML file describing what each network is made of
+        rules: PathBuf,
+        files: Vec<PathBuf>,
+    },
+    /// Draws a PNG of each z-level, as a minimap coloured by area or turf, or with real icons
+    Render {
+        files: Vec<PathBuf>,
+        /// Directory to write the PNGs to, named after the map and z-level
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+        /// TOML file with the colour palette and overlays
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// What to colour tiles by (area or turf), instead of what the config says
+        #[arg(long)]
+        by: Option<ColorBy>,
+        /// Pixels per tile, instead of what the config says
+        #[arg(long)]
+        tile_size: Option<u32>,
+        /// An older version of the map, drawn to the left of the new one
+        #[arg(long)]
+        before: Option<PathBuf>,
+        /// TOML file saying which icon each type uses, to draw real sprites instead of a minimap
+        #[arg(long, conflicts_with_all = ["config", "by", "tile_size"])]
+        icons: Option<PathBuf>,
+        /// Project directory that icon paths are relative to
+        #[arg(long, requires = "icons")]
+        root: Option<PathBuf>,
+        /// Shrink the images by this much
+        #[arg(long)]
+        scale: Option<u32>,
+    },
+    /// Reports what's on maps: sizes, object counts, var edits and parse times
+    Stats {
+        files: Vec<PathBuf>,
+        /// Print JSON instead of tables
+        #[arg(long)]
+        json: bool,
+        /// How many rows to show in each table
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+}
+
+fn is_map(file: &std::path::Path) -> bool {
+    file.extension().is_some_and(|s| s == "dmm")
+}
+
+fn map_name(file: &std::path::Path) -> String {
+    file.file_name()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or(std::borrow::Cow::Owned("<unk filename>".to_owned()))
+        .to_string()
+}
+
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
-    for file in args.files {
-        if let Some(s) = file.extension().map(|s| s.to_string_lossy()) {
-            if s != "dmm" {
-                continue;
-            }
-        } else {
+    match args.command {
+        Some(Command::UpdatePaths { script, files }) => update_paths::run(&script, &files),
+        Some(Command::Fmt { files, check }) => format::run(&files, check),
+        Some(Command::Query {
+            query,
+            files,
+            pretty,
+        }) => query::run(&query, &files, pretty),
+        Some(Command::Lint {
+            files,
+            config,
+            types,
+            root,
+        }) => lint::run(&files, config.as_deref(), types.as_deref(), root.as_deref()),
+        Some(Command::Areas { files, dot, json }) => areas::run(&files, dot, json),
+        Some(Command::Networks { rules, files }) => networks::run(&rules, &files),
+        Some(Command::Render {
+            files,
+            out,
+            config,
+            by,
+            tile_size,
+            before,
+            icons,
+            root,
+            scale,
+        }) => render::run(render::RenderArgs {
+            files: &files,
+            out: &out,
+            config: config.as_deref(),
+            by,
+            tile_size,
+            before: before.as_deref(),
+            icons: icons.as_deref(),
+            root: root.as_deref(),
+            scale,
+        }),
+        Some(Command::Stats { files, json, top }) => stats::run(&files, json, top),
+        None => test_parse(&args.files),
+    }
+}
+
+fn test_parse(files: &[PathBuf]) -> anyhow::Result<()> {
+    for file in files {
+        if !is_map(file) {
             continue;
         }
 
-        let string = std::fs::read_to_string(&file)?;
-        match parse_map_multithreaded(
-            file.file_name()
-                .map(|s| s.to_string_lossy())
-                .unwrap_or(std::borrow::Cow::Owned("<unk filename>".to_owned()))
-                .to_string(),
-            &string,
-        ) {
+        let string = std::fs::read_to_string(file)?;
+        match parse_map_multithreaded(map_name(file), &string) {
             Ok((info, (prefabs, blocks))) => {
                 println!(
                     "\x1b[32mSuccesfully parsed {file:#?} - TGM? {} - {} prefabs, {} blocks\x1b[0m",
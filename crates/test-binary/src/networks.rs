@@ -0,0 +1,67 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use dmm_lite::{
+    grid::{key_len, TileGrid},
+    networks::{dir_name, trace_networks, NetworkRules},
+    parse_map_multithreaded,
+};
+
+use crate::{is_map, map_name};
+
+pub fn run(rules: &Path, files: &[PathBuf]) -> anyhow::Result<()> {
+    let rules = NetworkRules::parse(
+        &std::fs::read_to_string(rules)
+            .with_context(|| format!("Failed to read network rules {rules:#?}"))?,
+    )?;
+
+    for file in files {
+        if !is_map(file) {
+            continue;
+        }
+
+        let string = std::fs::read_to_string(file)?;
+        let (_, (prefabs, blocks)) = match parse_map_multithreaded(map_name(file), &string) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!("\x1b[31mFAILED Parsing {file:#?}\x1b[0m");
+                e.debug_print(&string);
+                continue;
+            }
+        };
+
+        let grid = TileGrid::from_blocks(&blocks, key_len(&prefabs));
+        println!("{file:#?}");
+        for report in trace_networks(&prefabs, &grid, &rules) {
+            println!(
+                "  {}: {} objects in {} networks",
+                report.name,
+                report.nodes.len(),
+                report.networks.len()
+            );
+
+            for end in &report.dangling {
+                let node = &report.nodes[end.node];
+                println!(
+                    "    Dangling end: {} at {:?} goes {} to nothing",
+                    node.path,
+                    node.coord,
+                    dir_name(end.dir)
+                );
+            }
+            for network in report.isolated_networks() {
+                let node = &report.nodes[network[0]];
+                println!(
+                    "    Isolated network: {} objects, starting with {} at {:?}",
+                    network.len(),
+                    node.path,
+                    node.coord
+                );
+            }
+            for node in report.unattached_machines() {
+                println!("    Unattached machine: {} at {:?}", node.path, node.coord);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use dmm_lite::{
+    grid::{key_len, TileGrid},
+    parse_map_multithreaded,
+    query::Query,
+};
+use serde_json::{json, Value};
+
+use crate::{
+    is_map,
+    json::{prefab_to_json, print_json},
+    map_name,
+};
+
+pub fn run(query: &str, files: &[PathBuf], pretty: bool) -> anyhow::Result<()> {
+    let query = Query::parse(query).context("Failed to parse query")?;
+
+    let mut results = vec![];
+    for file in files {
+        if !is_map(file) {
+            continue;
+        }
+
+        let string = std::fs::read_to_string(file)?;
+        let (_, (prefabs, blocks)) = match parse_map_multithreaded(map_name(file), &string) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!("\x1b[31mFAILED Parsing {file:#?}\x1b[0m");
+                e.debug_print(&string);
+                continue;
+            }
+        };
+
+        let grid = TileGrid::from_blocks(&blocks, key_len(&prefabs));
+        let matches: Vec<Value> = query
+            .run(&prefabs, &grid)
+            .into_iter()
+            .map(|m| {
+                json!({
+                    "x": m.coord.0,
+                    "y": m.coord.1,
+                    "z": m.coord.2,
+                    "key": m.key,
+                    "area": m.area,
+                    "matches": m.matches.into_iter().map(prefab_to_json).collect::<Vec<_>>(),
+                    "prefab": m.prefab_list.iter().map(prefab_to_json).collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+
+        results.push(json!({
+            "map": file,
+            "matches": matches,
+        }));
+    }
+
+    print_json(&Value::Array(results), pretty)
+}
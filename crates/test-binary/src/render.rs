@@ -0,0 +1,157 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use dmm_lite::{
+    grid::{key_len, TileGrid},
+    parse_map_multithreaded,
+    render::{
+        icons::{IconMapping, IconRenderer},
+        minimap::{render_minimap, ColorBy, MinimapOptions},
+        Color, Image,
+    },
+};
+
+use crate::{is_map, map_name};
+
+pub struct RenderArgs<'a> {
+    pub files: &'a [PathBuf],
+    pub out: &'a Path,
+    pub config: Option<&'a Path>,
+    pub by: Option<ColorBy>,
+    pub tile_size: Option<u32>,
+    pub before: Option<&'a Path>,
+    pub icons: Option<&'a Path>,
+    pub root: Option<&'a Path>,
+    pub scale: Option<u32>,
+}
+
+enum Renderer {
+    Minimap(MinimapOptions),
+    Icons(IconRenderer),
+}
+
+impl Renderer {
+    /// Pixels per tile, before scaling
+    fn tile_size(&self) -> u32 {
+        match self {
+            Renderer::Minimap(options) => options.tile_size.max(1),
+            Renderer::Icons(renderer) => renderer.mapping.icon_size.max(1),
+        }
+    }
+}
+
+/// Renders every z-level of a map, in order.
+fn render_map(
+    file: &Path,
+    renderer: &mut Renderer,
+    scale: u32,
+) -> anyhow::Result<Option<Vec<(usize, Image)>>> {
+    let string = std::fs::read_to_string(file)?;
+    let (_, (prefabs, blocks)) = match parse_map_multithreaded(map_name(file), &string) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("\x1b[31mFAILED Parsing {file:#?}\x1b[0m");
+            e.debug_print(&string);
+            return Ok(None);
+        }
+    };
+
+    let grid = TileGrid::from_blocks(&blocks, key_len(&prefabs));
+    if grid.is_empty() {
+        return Ok(Some(vec![]));
+    }
+    let images = (grid.origin.2..=grid.max().2)
+        .map(|z| {
+            let image = match renderer {
+                Renderer::Minimap(options) => render_minimap(&prefabs, &grid, z, options)?,
+                Renderer::Icons(renderer) => renderer.render_z(&prefabs, &grid, z)?,
+            };
+            Ok((z, image.downscale(scale)))
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok(Some(images))
+}
+
+fn icon_renderer(mapping: &Path, root: Option<&Path>) -> anyhow::Result<Renderer> {
+    let mapping = IconMapping::parse(
+        &std::fs::read_to_string(mapping)
+            .with_context(|| format!("Failed to read icon mapping {mapping:#?}"))?,
+    )?;
+    Ok(Renderer::Icons(IconRenderer::new(
+        root.unwrap_or(Path::new(".")),
+        mapping,
+    )))
+}
+
+pub fn run(args: RenderArgs) -> anyhow::Result<()> {
+    if let Some(mapping) = args.icons {
+        return render_all(&args, icon_renderer(mapping, args.root)?);
+    }
+
+    let mut options = match args.config {
+        Some(path) => MinimapOptions::parse(
+            &std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read render config {path:#?}"))?,
+        )?,
+        None => MinimapOptions::default(),
+    };
+    if let Some(by) = args.by {
+        options.color_by = by;
+    }
+    if let Some(tile_size) = args.tile_size {
+        options.tile_size = tile_size;
+    }
+    render_all(&args, Renderer::Minimap(options))
+}
+
+fn render_all(args: &RenderArgs, mut renderer: Renderer) -> anyhow::Result<()> {
+    let scale = args.scale.unwrap_or(1).max(1);
+    let before = match args.before {
+        Some(_) if args.files.len() != 1 => bail!("--before only works with one map"),
+        Some(path) => match render_map(path, &mut renderer, scale)? {
+            Some(images) => Some(images),
+            None => bail!("Couldn't render {path:#?}"),
+        },
+        None => None,
+    };
+
+    std::fs::create_dir_all(args.out)?;
+    for file in args.files {
+        if !is_map(file) {
+            continue;
+        }
+        let Some(images) = render_map(file, &mut renderer, scale)? else {
+            continue;
+        };
+
+        let name = file.file_stem().unwrap_or_default().to_string_lossy();
+        for (z, image) in images {
+            let (image, out) = match &before {
+                Some(before) => {
+                    let empty = Image::new(image.width, image.height, Color::TRANSPARENT);
+                    let old = before
+                        .iter()
+                        .find(|(before_z, _)| *before_z == z)
+                        .map_or(&empty, |(_, image)| image);
+                    (
+                        Image::side_by_side(old, &image, (renderer.tile_size() * 2 / scale).max(1)),
+                        args.out.join(format!("{name}-{z}-diff.png")),
+                    )
+                }
+                None => (image, args.out.join(format!("{name}-{z}.png"))),
+            };
+            image.save_png(&out)?;
+            println!("Wrote {out:#?}");
+        }
+    }
+
+    if let Renderer::Icons(renderer) = renderer {
+        for (icon, error) in &renderer.missing_icons {
+            eprintln!("Couldn't read '{icon}': {error}");
+        }
+        for (icon, icon_state) in &renderer.missing_states {
+            eprintln!("'{icon}' has no icon_state \"{icon_state}\"");
+        }
+    }
+    Ok(())
+}
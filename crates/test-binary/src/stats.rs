@@ -0,0 +1,125 @@
+use std::{path::PathBuf, time::Instant};
+
+use dmm_lite::{
+    grid::{key_len, TileGrid},
+    parse_map_multithreaded,
+    stats::{map_stats, MapStats},
+};
+use serde_json::{json, Value};
+
+use crate::{is_map, json::print_json, map_name};
+
+pub fn run(files: &[PathBuf], json: bool, top: usize) -> anyhow::Result<()> {
+    let mut results = vec![];
+    for file in files {
+        if !is_map(file) {
+            continue;
+        }
+
+        let string = std::fs::read_to_string(file)?;
+        let start = Instant::now();
+        let (info, (prefabs, blocks)) = match parse_map_multithreaded(map_name(file), &string) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!("\x1b[31mFAILED Parsing {file:#?}\x1b[0m");
+                e.debug_print(&string);
+                continue;
+            }
+        };
+        let parse_time = start.elapsed();
+
+        let grid = TileGrid::from_blocks(&blocks, key_len(&prefabs));
+        let stats = map_stats(&prefabs, &grid);
+        let parse_ms = parse_time.as_secs_f64() * 1000.;
+
+        if json {
+            results.push(stats_to_json(file, info.is_tgm, parse_ms, &stats));
+        } else {
+            print_table(file, info.is_tgm, parse_ms, &stats, top);
+        }
+    }
+
+    if json {
+        print_json(&Value::Array(results), true)?;
+    }
+    Ok(())
+}
+
+fn counts_to_json<K: ToString>(counts: &[(K, usize)]) -> Value {
+    Value::Object(
+        counts
+            .iter()
+            .map(|(key, count)| (key.to_string(), json!(count)))
+            .collect(),
+    )
+}
+
+fn stats_to_json(file: &PathBuf, is_tgm: bool, parse_ms: f64, stats: &MapStats) -> Value {
+    json!({
+        "map": file,
+        "tgm": is_tgm,
+        "parse_ms": parse_ms,
+        "key_length": stats.key_length,
+        "prefabs": stats.prefabs,
+        "unique_paths": stats.unique_paths,
+        "z_levels": stats.z_levels.iter().map(|level| json!({
+            "z": level.z,
+            "min": [level.min.0, level.min.1],
+            "max": [level.max.0, level.max.1],
+            "width": level.width(),
+            "height": level.height(),
+            "tiles": level.tiles,
+        })).collect::<Vec<_>>(),
+        "objects_by_type": counts_to_json(&stats.objects_by_type),
+        "objects_by_area": counts_to_json(&stats.objects_by_area),
+        "turfs_by_area": counts_to_json(&stats.turfs_by_area),
+        "var_edits_by_type": Value::Object(
+            stats
+                .var_edits_by_type
+                .iter()
+                .map(|(path, counts)| (path.to_string(), counts_to_json(counts)))
+                .collect()
+        ),
+    })
+}
+
+fn print_counts<K: std::fmt::Display>(title: &str, counts: &[(K, usize)], top: usize) {
+    println!("  {title} ({} total)", counts.len());
+    for (key, count) in counts.iter().take(top) {
+        println!("    {count:>8}  {key}");
+    }
+}
+
+fn print_table(file: &PathBuf, is_tgm: bool, parse_ms: f64, stats: &MapStats, top: usize) {
+    println!("\x1b[32m{file:#?}\x1b[0m");
+    println!("  Parsed in {parse_ms:.2}ms - TGM? {is_tgm}");
+    println!(
+        "  {} prefabs, key length {}, {} unique paths",
+        stats.prefabs, stats.key_length, stats.unique_paths
+    );
+    for level in &stats.z_levels {
+        println!(
+            "  z{}: {}x{} from {:?} to {:?}, {} tiles",
+            level.z,
+            level.width(),
+            level.height(),
+            level.min,
+            level.max,
+            level.tiles
+        );
+    }
+    print_counts("Objects by type", &stats.objects_by_type, top);
+    print_counts("Objects by area", &stats.objects_by_area, top);
+    print_counts("Turfs by area", &stats.turfs_by_area, top);
+
+    println!(
+        "  Var edits by type ({} total)",
+        stats.var_edits_by_type.len()
+    );
+    for (path, counts) in stats.var_edits_by_type.iter().take(top) {
+        println!("    {path}");
+        for (vars, count) in counts.iter().take(3) {
+            println!("      {count:>8}  {vars}");
+        }
+    }
+}
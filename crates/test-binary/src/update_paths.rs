@@ -0,0 +1,50 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context};
+use dmm_lite::{parse_map_multithreaded, update_paths::UpdateScript, writer::write_map};
+use typed_arena::Arena;
+
+use crate::{is_map, map_name};
+
+pub fn run(script: &Path, files: &[PathBuf]) -> anyhow::Result<()> {
+    let script_text = std::fs::read_to_string(script)
+        .with_context(|| format!("Failed to read script {script:#?}"))?;
+    let script = UpdateScript::parse(&script_text)?;
+
+    let mut failed = 0;
+    for file in files {
+        if !is_map(file) {
+            continue;
+        }
+
+        let string = std::fs::read_to_string(file)?;
+        let (info, data) = match parse_map_multithreaded(map_name(file), &string) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!("\x1b[31mFAILED Parsing {file:#?}\x1b[0m");
+                e.debug_print(&string);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let arena = Arena::new();
+        let (data, summary) = script.apply_to_map(data, &arena);
+        if summary.changed_prefabs == 0 {
+            continue;
+        }
+
+        let written =
+            write_map(info.is_tgm, &data).map_err(|e| anyhow!("Failed to write {file:#?}: {e}"))?;
+        std::fs::write(file, written)?;
+        println!(
+            "\x1b[32mUpdated {file:#?} - {} prefabs changed, {} merged\x1b[0m",
+            summary.changed_prefabs, summary.merged_prefabs
+        );
+    }
+
+    if failed > 0 {
+        bail!("{failed} maps failed to parse and weren't updated");
+    }
+    Ok(())
+}